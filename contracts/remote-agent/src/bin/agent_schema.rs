@@ -0,0 +1,12 @@
+use cosmwasm_schema::write_api;
+
+use otc_remote_agent::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, SudoMsg};
+
+fn main() {
+    write_api! {
+        instantiate: InstantiateMsg,
+        execute: ExecuteMsg,
+        query: QueryMsg,
+        sudo: SudoMsg,
+    }
+}
@@ -0,0 +1,499 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    to_json_binary, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdError, StdResult,
+    SubMsgResult,
+};
+use otc_ibc::hooks::IbcLifecycleComplete;
+
+use crate::error::ContractError;
+use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, SudoMsg};
+use crate::state::{Config, CONFIG};
+
+/// Seconds a forwarded packet or transfer stays valid.
+pub const PACKET_LIFETIME: u64 = 100;
+
+/// Reply id of the transfer carrying a deposit to the host.
+pub const DEPOSIT_REPLY_ID: u64 = 1;
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    CONFIG.save(
+        deps.storage,
+        &Config {
+            host_contract: msg.host_contract,
+            host_port: msg.host_port,
+            host_connection: msg.host_connection,
+            transfer_channel: msg.transfer_channel,
+            host_transfer_channel: msg.host_transfer_channel,
+            trader_group: msg.trader_group,
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "instantiate"))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::CreateDeal(proposal) => execute::create_deal(deps, env, info, proposal),
+        ExecuteMsg::AcceptDeal { deal_id } => execute::accept_deal(deps, env, info, deal_id),
+        ExecuteMsg::CancelDeal { deal_id } => execute::cancel_deal(deps, env, info, deal_id),
+        ExecuteMsg::Deposit { deal_id } => execute::deposit(deps, env, info, deal_id),
+        ExecuteMsg::Withdraw { deal_id } => execute::withdraw(deps, env, info, deal_id),
+        ExecuteMsg::CompleteDeal { deal_id } => execute::complete_deal(deps, env, info, deal_id),
+        ExecuteMsg::LockRemoteLeg { deal_id } => execute::lock_remote_leg(deps, env, info, deal_id),
+        ExecuteMsg::SetPayoutRoute { deal_id, route } => {
+            execute::set_payout_route(deps, env, info, deal_id, route)
+        }
+        ExecuteMsg::LockHtlc {
+            recipient,
+            hashlock,
+            timelock,
+        } => execute::lock_htlc(deps, env, info, recipient, hashlock, timelock),
+        ExecuteMsg::ClaimHtlc { id, preimage } => execute::claim_htlc(deps, env, id, preimage),
+        ExecuteMsg::RefundHtlc { id } => execute::refund_htlc(deps, env, id),
+    }
+}
+
+pub mod execute {
+    use cosmwasm_std::{to_json_string, BankMsg, HexBinary, IbcMsg, StdError, SubMsg, Timestamp};
+    use otc_ibc::cw4;
+    use otc_ibc::hooks::{HookMemo, HostHookMsg, WasmHook};
+    use otc_ibc::htlc::{Htlc, HtlcState, HASHLOCK_LEN};
+    use otc_ibc::{proto, DealProposal, IbcExecuteMsg, Ics20Packet, PayoutRoute};
+    use sha2::{Digest, Sha256};
+
+    use crate::state::{
+        DEPOSIT_TRANSFERS, HOST_CHANNEL, HTLCS, HTLC_COUNT, PENDING_DEPOSITS, SENDING_DEPOSIT,
+    };
+
+    use super::*;
+
+    // Asks the host to create a deal proposed by the user.
+    pub fn create_deal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        proposal: DealProposal,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::CreateDeal {
+            sender: info.sender.to_string(),
+            proposal,
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "create_deal")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    // Accepts a deal proposed to the user on the host.
+    pub fn accept_deal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::AcceptDeal {
+            deal_id,
+            sender: info.sender.to_string(),
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "accept_deal")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    // Calls off one of the user's deals on the host.
+    pub fn cancel_deal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::CancelDeal {
+            deal_id,
+            sender: info.sender.to_string(),
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "cancel_deal")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    // Transfers the user's coin to the host, which credits it to their deal
+    // as it arrives. The coin is tracked until ibc-hooks reports back, so it
+    // can be returned to the user if the host rejects it or it times out.
+    pub fn deposit(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        if info.funds.len() != 1 {
+            return Err(ContractError::InvalidFunds {});
+        }
+        let coin = info.funds[0].clone();
+        let config = CONFIG.load(deps.storage)?;
+        let denom = host_denom(&config, &coin.denom)?;
+
+        if PENDING_DEPOSITS.has(deps.storage, (&info.sender, &denom)) {
+            return Err(ContractError::DepositPending { denom });
+        }
+        PENDING_DEPOSITS.save(deps.storage, (&info.sender, &denom), &coin)?;
+        SENDING_DEPOSIT.save(deps.storage, &(info.sender.clone(), denom))?;
+
+        let memo = HookMemo {
+            wasm: WasmHook {
+                contract: config.host_contract.clone(),
+                msg: HostHookMsg::DepositIbc {
+                    deal_id,
+                    sender: info.sender.to_string(),
+                },
+            },
+            ibc_callback: Some(env.contract.address.to_string()),
+        };
+        let transfer = proto::transfer(
+            &config.transfer_channel,
+            env.contract.address.as_str(),
+            &config.host_contract,
+            &coin,
+            env.block.time.plus_seconds(PACKET_LIFETIME),
+            &to_json_string(&memo)?,
+        );
+
+        Ok(Response::new()
+            .add_attribute("method", "deposit")
+            .add_submessage(SubMsg::reply_on_success(transfer, DEPOSIT_REPLY_ID)))
+    }
+
+    // Remembers which deposit a transfer carries, by its packet sequence.
+    pub fn deposit_sent(deps: DepsMut, data: Option<Binary>) -> Result<Response, ContractError> {
+        let sequence = data
+            .and_then(|data| proto::read_uint(&data, 1))
+            .ok_or_else(|| StdError::generic_err("MsgTransfer returned no sequence"))?;
+        let deposit = SENDING_DEPOSIT.load(deps.storage)?;
+        SENDING_DEPOSIT.remove(deps.storage);
+        let config = CONFIG.load(deps.storage)?;
+        DEPOSIT_TRANSFERS.save(deps.storage, (&config.transfer_channel, sequence), &deposit)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "deposit_sent")
+            .add_attribute("sequence", sequence.to_string()))
+    }
+
+    // Releases a deposit once its transfer is settled: it stays with the host
+    // if the host credited it, and is returned to the user otherwise. ICS-20
+    // has already refunded a failed transfer to us by then.
+    pub fn deposit_settled(
+        deps: DepsMut,
+        channel: String,
+        sequence: u64,
+        success: bool,
+    ) -> Result<Response, ContractError> {
+        let res = Response::new()
+            .add_attribute("method", "deposit_settled")
+            .add_attribute("sequence", sequence.to_string())
+            .add_attribute("success", success.to_string());
+        let Some((depositor, denom)) =
+            DEPOSIT_TRANSFERS.may_load(deps.storage, (&channel, sequence))?
+        else {
+            return Ok(res);
+        };
+        DEPOSIT_TRANSFERS.remove(deps.storage, (&channel, sequence));
+        let key = (&depositor, denom.as_str());
+        let coin = PENDING_DEPOSITS.load(deps.storage, key)?;
+        PENDING_DEPOSITS.remove(deps.storage, key);
+
+        let res = res.add_attribute("depositor", depositor.to_string());
+        if success {
+            return Ok(res);
+        }
+        Ok(res.add_message(BankMsg::Send {
+            to_address: depositor.into_string(),
+            amount: vec![coin],
+        }))
+    }
+
+    // Asks the host to refund an unmatched deposit.
+    pub fn withdraw(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::Withdraw {
+            deal_id,
+            packet20: Ics20Packet {
+                receiver: info.sender.to_string(),
+                sender: info.sender.to_string(),
+                ..Ics20Packet::default()
+            },
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "withdraw")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    // Asks the host to settle the user's deal.
+    pub fn complete_deal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::CompleteDeal {
+            deal_id,
+            packet20: Ics20Packet {
+                receiver: info.sender.to_string(),
+                sender: info.sender.to_string(),
+                ..Ics20Packet::default()
+            },
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "complete_deal")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    // Asks the host to take the user's leg of a deal into its interchain
+    // account on this chain. The user grants that account a send
+    // authorization for the leg beforehand.
+    pub fn lock_remote_leg(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::LockRemoteLeg {
+            deal_id,
+            sender: info.sender.to_string(),
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "lock_remote_leg")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    // Asks the host to forward the user's payouts along a route.
+    pub fn set_payout_route(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+        route: Option<PayoutRoute>,
+    ) -> Result<Response, ContractError> {
+        let msg = IbcExecuteMsg::SetPayoutRoute {
+            deal_id,
+            sender: info.sender.to_string(),
+            route,
+        };
+
+        Ok(Response::new()
+            .add_attribute("method", "set_payout_route")
+            .add_message(send_to_host(deps.as_ref(), &env, &msg)?))
+    }
+
+    fn send_to_host(deps: Deps, env: &Env, msg: &IbcExecuteMsg) -> Result<IbcMsg, ContractError> {
+        let channel_id = HOST_CHANNEL
+            .may_load(deps.storage)?
+            .ok_or(ContractError::NoHostChannel {})?;
+        Ok(IbcMsg::SendPacket {
+            channel_id,
+            data: to_json_binary(msg)?,
+            timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+        })
+    }
+
+    // Locks this chain's leg of an atomic swap with a counterparty elsewhere.
+    pub fn lock_htlc(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        recipient: String,
+        hashlock: HexBinary,
+        timelock: u64,
+    ) -> Result<Response, ContractError> {
+        if info.funds.len() != 1 {
+            return Err(ContractError::InvalidFunds {});
+        }
+        if hashlock.len() != HASHLOCK_LEN {
+            return Err(ContractError::InvalidHashlock {});
+        }
+        if env.block.time >= Timestamp::from_seconds(timelock) {
+            return Err(ContractError::InvalidTimelock {});
+        }
+        deps.api.addr_validate(&recipient)?;
+        if let Some(group) = CONFIG.load(deps.storage)?.trader_group {
+            for party in [info.sender.as_str(), recipient.as_str()] {
+                if !cw4::is_member(deps.as_ref(), &group, party)? {
+                    return Err(ContractError::TraderNotAllowed {
+                        address: party.to_string(),
+                    });
+                }
+            }
+        }
+
+        let id = HTLC_COUNT.may_load(deps.storage)?.unwrap_or_default();
+        HTLC_COUNT.save(deps.storage, &(id + 1))?;
+        let htlc = Htlc {
+            sender: info.sender.to_string(),
+            recipient,
+            amount: info.funds[0].clone(),
+            hashlock,
+            timelock,
+            state: HtlcState::Locked,
+        };
+        HTLCS.save(deps.storage, id, &htlc)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "lock_htlc")
+            .add_attribute("htlc_id", id.to_string())
+            .add_attribute("hashlock", htlc.hashlock.to_hex()))
+    }
+
+    // Releases an HTLC to its recipient, publishing the preimage.
+    pub fn claim_htlc(
+        deps: DepsMut,
+        env: Env,
+        id: u64,
+        preimage: HexBinary,
+    ) -> Result<Response, ContractError> {
+        let mut htlc = load_locked_htlc(deps.as_ref(), id)?;
+        if htlc.expired(env.block.time) {
+            return Err(ContractError::HtlcExpired { id });
+        }
+        if !htlc.opens_with(&preimage) {
+            return Err(ContractError::WrongPreimage {});
+        }
+
+        htlc.state = HtlcState::Claimed {
+            preimage: preimage.clone(),
+        };
+        HTLCS.save(deps.storage, id, &htlc)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "claim_htlc")
+            .add_attribute("htlc_id", id.to_string())
+            .add_attribute("preimage", preimage.to_hex())
+            .add_message(BankMsg::Send {
+                to_address: htlc.recipient,
+                amount: vec![htlc.amount],
+            }))
+    }
+
+    // Returns an expired HTLC to its sender.
+    pub fn refund_htlc(deps: DepsMut, env: Env, id: u64) -> Result<Response, ContractError> {
+        let mut htlc = load_locked_htlc(deps.as_ref(), id)?;
+        if !htlc.expired(env.block.time) {
+            return Err(ContractError::HtlcNotExpired { id });
+        }
+
+        htlc.state = HtlcState::Refunded;
+        HTLCS.save(deps.storage, id, &htlc)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "refund_htlc")
+            .add_attribute("htlc_id", id.to_string())
+            .add_message(BankMsg::Send {
+                to_address: htlc.sender,
+                amount: vec![htlc.amount],
+            }))
+    }
+
+    fn load_locked_htlc(deps: Deps, id: u64) -> Result<Htlc, ContractError> {
+        let htlc = HTLCS
+            .may_load(deps.storage, id)?
+            .ok_or(ContractError::HtlcNotFound { id })?;
+        if htlc.state != HtlcState::Locked {
+            return Err(ContractError::HtlcNotLocked { id });
+        }
+        Ok(htlc)
+    }
+
+    // The voucher denom a native coin of this chain gets on the host after an
+    // ICS-20 transfer, which is the denom deals on the host are quoted in.
+    pub fn host_denom(config: &Config, denom: &str) -> Result<String, ContractError> {
+        if denom.starts_with("ibc/") {
+            return Err(ContractError::UnsupportedDenom {
+                denom: denom.to_string(),
+            });
+        }
+        let trace = format!("transfer/{}/{}", config.host_transfer_channel, denom);
+        let hash: String = Sha256::digest(trace.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        Ok(format!("ibc/{}", hash))
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match (msg.id, msg.result) {
+        (DEPOSIT_REPLY_ID, SubMsgResult::Ok(response)) => {
+            execute::deposit_sent(deps, response.data)
+        }
+        (id, _) => Err(StdError::generic_err(format!("Unknown reply id {}", id)).into()),
+    }
+}
+
+// ibc-hooks reports back on the transfers deposits are sent with.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcAck {
+            channel,
+            sequence,
+            success,
+            ..
+        }) => execute::deposit_settled(deps, channel, sequence, success),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout { channel, sequence }) => {
+            execute::deposit_settled(deps, channel, sequence, false)
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_json_binary(&CONFIG.load(deps.storage)?),
+        QueryMsg::PendingDeposits { address } => {
+            to_json_binary(&query::pending_deposits(deps, address)?)
+        }
+        QueryMsg::Htlc { id } => to_json_binary(&query::htlc(deps, id)?),
+    }
+}
+
+mod query {
+    use cosmwasm_std::{Deps, Order, StdResult};
+
+    use otc_ibc::htlc::Htlc;
+
+    use crate::msg::PendingDepositsResponse;
+    use crate::state::{HTLCS, PENDING_DEPOSITS};
+
+    pub fn pending_deposits(deps: Deps, address: String) -> StdResult<PendingDepositsResponse> {
+        let address = deps.api.addr_validate(&address)?;
+        let deposits = PENDING_DEPOSITS
+            .prefix(&address)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, coin)| coin))
+            .collect::<StdResult<_>>()?;
+        Ok(PendingDepositsResponse { deposits })
+    }
+
+    pub fn htlc(deps: Deps, id: u64) -> StdResult<Htlc> {
+        HTLCS.load(deps.storage, id)
+    }
+}
@@ -0,0 +1,80 @@
+use cosmwasm_std::StdError;
+use otc_ibc::ack::ErrorCode;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Never {}
+
+#[derive(Error, Debug)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("only unordered channels are supported")]
+    OrderedChannel {},
+
+    #[error("invalid IBC channel version. Got ({actual}), expected ({expected})")]
+    InvalidVersion { actual: String, expected: String },
+
+    #[error("a channel to the host contract is already open ({channel_id})")]
+    HostChannelExists { channel_id: String },
+
+    #[error("channel counterparty ({port_id}) on ({connection_id}) is not the host contract")]
+    NotHostCounterparty {
+        connection_id: String,
+        port_id: String,
+    },
+
+    #[error("no channel to the host contract is open")]
+    NoHostChannel {},
+
+    #[error("exactly one coin must be sent")]
+    InvalidFunds {},
+
+    #[error("only native denoms can be forwarded, got ({denom})")]
+    UnsupportedDenom { denom: String },
+
+    #[error("a deposit of ({denom}) is already waiting for the host to acknowledge it")]
+    DepositPending { denom: String },
+
+    #[error("hashlock must be a sha256 hash")]
+    InvalidHashlock {},
+
+    #[error("timelock must be in the future")]
+    InvalidTimelock {},
+
+    #[error("no HTLC with id ({id})")]
+    HtlcNotFound { id: u64 },
+
+    #[error("HTLC ({id}) is no longer locked")]
+    HtlcNotLocked { id: u64 },
+
+    #[error("HTLC ({id}) timelock has passed")]
+    HtlcExpired { id: u64 },
+
+    #[error("HTLC ({id}) timelock has not passed yet")]
+    HtlcNotExpired { id: u64 },
+
+    #[error("preimage does not match the hashlock")]
+    WrongPreimage {},
+
+    #[error("{address} is not an allowed trader")]
+    TraderNotAllowed { address: String },
+}
+
+impl ContractError {
+    /// Classifies the error for the host, which gets it back in an error ack.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ContractError::Std(StdError::ParseErr { .. } | StdError::InvalidUtf8 { .. }) => {
+                ErrorCode::InvalidPacket
+            }
+            ContractError::OrderedChannel {}
+            | ContractError::InvalidVersion { .. }
+            | ContractError::HostChannelExists { .. }
+            | ContractError::NotHostCounterparty { .. }
+            | ContractError::NoHostChannel {} => ErrorCode::ChannelDisabled,
+            _ => ErrorCode::Rejected,
+        }
+    }
+}
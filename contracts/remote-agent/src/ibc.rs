@@ -0,0 +1,220 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    from_json, to_json_binary, Deps, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse,
+    IbcChannel, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg,
+    IbcChannelOpenResponse, IbcOrder, IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg,
+    IbcPacketTimeoutMsg, IbcReceiveResponse,
+};
+use otc_ibc::{
+    ack::{make_ack_fail, make_ack_result, make_ack_success, Ack, AckResult},
+    BalanceProof, IbcExecuteMsg, IbcHostMsg, IBC_VERSION,
+};
+
+use crate::{
+    contract::execute::host_denom,
+    error::{ContractError, Never},
+    state::{CONFIG, HOST_CHANNEL},
+};
+
+/// Handles the `OpenInit` and `OpenTry` parts of the IBC handshake.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_open(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelOpenMsg,
+) -> Result<IbcChannelOpenResponse, ContractError> {
+    validate_order_and_version(msg.channel(), msg.counterparty_version())?;
+    validate_counterparty(deps.as_ref(), msg.channel())?;
+    // The agent talks to a single host contract.
+    if let Some(channel_id) = HOST_CHANNEL.may_load(deps.storage)? {
+        return Err(ContractError::HostChannelExists { channel_id });
+    }
+    Ok(Some(Ibc3ChannelOpenResponse {
+        version: IBC_VERSION.to_string(),
+    }))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_connect(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelConnectMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    validate_order_and_version(msg.channel(), msg.counterparty_version())?;
+    validate_counterparty(deps.as_ref(), msg.channel())?;
+
+    let channel = msg.channel().endpoint.channel_id.clone();
+    HOST_CHANNEL.save(deps.storage, &channel)?;
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("method", "ibc_channel_connect")
+        .add_attribute("channel_id", channel))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_close(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelCloseMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel = msg.channel().endpoint.channel_id.clone();
+    HOST_CHANNEL.remove(deps.storage);
+    Ok(IbcBasicResponse::new()
+        .add_attribute("method", "ibc_channel_close")
+        .add_attribute("channel", channel))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_receive(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> Result<IbcReceiveResponse, Never> {
+    match do_ibc_packet_receive(deps, env, msg) {
+        Ok(response) => Ok(response),
+        Err(error) => Ok(IbcReceiveResponse::new()
+            .add_attribute("method", "ibc_packet_receive")
+            .add_attribute("error", error.to_string())
+            .set_ack(make_ack_fail(error.code(), error.to_string()))),
+    }
+}
+
+// Deal notifications from the host are surfaced as events for remote UIs and bots.
+// Balance queries are answered in the ack.
+pub fn do_ibc_packet_receive(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let channel = msg.packet.dest.channel_id;
+    if HOST_CHANNEL.may_load(deps.storage)? != Some(channel.clone()) {
+        return Err(ContractError::NoHostChannel {});
+    }
+    let msg: IbcHostMsg = from_json(&msg.packet.data)?;
+
+    let ack = match &msg {
+        IbcHostMsg::QueryBalance { address, denom, .. } => {
+            let proof = query_balance(deps.as_ref(), &env, address, denom)?;
+            make_ack_result(to_json_binary(&proof)?)
+        }
+        _ => make_ack_success(),
+    };
+    Ok(IbcReceiveResponse::new()
+        .add_attribute("method", "ibc_packet_receive")
+        .add_attribute("event", msg.event())
+        .add_attribute("deal_id", msg.deal_id().to_string())
+        .set_ack(ack))
+}
+
+// Reads `address`'s balance of the native coin that arrives on the host as `denom`.
+fn query_balance(
+    deps: Deps,
+    env: &Env,
+    address: &str,
+    denom: &str,
+) -> Result<BalanceProof, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let balances = deps.querier.query_all_balances(address)?;
+    let amount = balances
+        .into_iter()
+        .find(|coin| host_denom(&config, &coin.denom).ok().as_deref() == Some(denom))
+        .map(|coin| coin.amount)
+        .unwrap_or_default();
+    Ok(BalanceProof {
+        address: address.to_string(),
+        denom: denom.to_string(),
+        amount,
+        height: env.block.height,
+    })
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_ack(
+    _deps: DepsMut,
+    _env: Env,
+    msg: IbcPacketAckMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let ack: Ack = from_json(&msg.acknowledgement.data)?;
+    let success = matches!(ack, Ack::Result(_));
+    let res = on_packet_settled(&msg.original_packet)?;
+    // Surface the outcome, including the id of a deal the host created for us
+    let res = match ack.outcome::<AckResult>() {
+        Ok(result) => res
+            .add_attribute("deal_id", result.deal_id.to_string())
+            .add_attribute("status", result.status.as_str()),
+        Err(error) => res.add_attribute("error_code", error.code.as_str()),
+    };
+    Ok(res
+        .add_attribute("method", "ibc_packet_ack")
+        .add_attribute("success", success.to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_timeout(
+    _deps: DepsMut,
+    _env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let res = on_packet_settled(&msg.packet)?;
+    Ok(res.add_attribute("method", "ibc_packet_timeout"))
+}
+
+// Surfaces who an instruction the host answered for was sent by. Payouts
+// arrive at them over ICS-20 directly.
+fn on_packet_settled(packet: &IbcPacket) -> Result<IbcBasicResponse, ContractError> {
+    let sender = match from_json(&packet.data)? {
+        IbcExecuteMsg::Withdraw { packet20, .. } | IbcExecuteMsg::CompleteDeal { packet20, .. } => {
+            packet20.sender
+        }
+        IbcExecuteMsg::SetPayoutRoute { sender, .. }
+        | IbcExecuteMsg::CreateDeal { sender, .. }
+        | IbcExecuteMsg::AcceptDeal { sender, .. }
+        | IbcExecuteMsg::CancelDeal { sender, .. }
+        | IbcExecuteMsg::LockRemoteLeg { sender, .. } => sender,
+    };
+    Ok(IbcBasicResponse::new().add_attribute("sender", sender))
+}
+
+pub fn validate_order_and_version(
+    channel: &IbcChannel,
+    counterparty_version: Option<&str>,
+) -> Result<(), ContractError> {
+    if channel.order != IbcOrder::Unordered {
+        return Err(ContractError::OrderedChannel {});
+    }
+
+    // An empty version on `OpenInit` means we propose ours.
+    if !channel.version.is_empty() && channel.version != IBC_VERSION {
+        return Err(ContractError::InvalidVersion {
+            actual: channel.version.to_string(),
+            expected: IBC_VERSION.to_string(),
+        });
+    }
+
+    if let Some(counterparty_version) = counterparty_version {
+        if counterparty_version != IBC_VERSION {
+            return Err(ContractError::InvalidVersion {
+                actual: counterparty_version.to_string(),
+                expected: IBC_VERSION.to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a channel being opened leads to the host contract the agent
+/// was set up for, over the expected connection.
+fn validate_counterparty(deps: Deps, channel: &IbcChannel) -> Result<(), ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if channel.connection_id != config.host_connection
+        || channel.counterparty_endpoint.port_id != config.host_port
+    {
+        return Err(ContractError::NotHostCounterparty {
+            connection_id: channel.connection_id.clone(),
+            port_id: channel.counterparty_endpoint.port_id.clone(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,363 @@
+#[cfg(test)]
+mod tests {
+    use crate::contract::execute::host_denom;
+    use crate::contract::{execute, instantiate, query, reply, sudo, DEPOSIT_REPLY_ID};
+    use crate::error::ContractError;
+    use crate::ibc::{
+        ibc_channel_connect, ibc_channel_open, ibc_packet_ack, ibc_packet_receive,
+        ibc_packet_timeout,
+    };
+    use crate::msg::{ExecuteMsg, InstantiateMsg, PendingDepositsResponse, QueryMsg, SudoMsg};
+    use crate::state::CONFIG;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init,
+        mock_ibc_packet_ack, mock_ibc_packet_recv, mock_ibc_packet_timeout, mock_info, MockApi,
+        MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        coins, from_json, to_json_binary, BankMsg, Binary, Coin, ContractResult, CosmosMsg,
+        HexBinary, IbcAcknowledgement, IbcOrder, OwnedDeps, Reply, Response, SubMsgResponse,
+        SubMsgResult, SystemResult, Uint128, WasmQuery,
+    };
+    use otc_ibc::ack::{make_ack_fail, make_ack_result, Ack, AckResult, DealStatus, ErrorCode};
+    use otc_ibc::hooks::IbcLifecycleComplete;
+    use otc_ibc::{proto, BalanceProof, IbcExecuteMsg, IbcHostMsg, Ics20Packet, IBC_VERSION};
+
+    const HOST_CONTRACT: &str = "host_contract";
+    const HOST_CHANNEL: &str = "channel-1";
+    // Connection and port used by cosmwasm_std::testing::mock_ibc_channel
+    const HOST_CONNECTION: &str = "connection-2";
+    const HOST_PORT: &str = "their_port";
+    const TRANSFER_CHANNEL: &str = "channel-0";
+    const USER: &str = "user";
+    const DENOM: &str = "uatom";
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            host_contract: HOST_CONTRACT.to_string(),
+            host_port: HOST_PORT.to_string(),
+            host_connection: HOST_CONNECTION.to_string(),
+            transfer_channel: TRANSFER_CHANNEL.to_string(),
+            host_transfer_channel: "channel-141".to_string(),
+            trader_group: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+        let connect = mock_ibc_channel_connect_ack(HOST_CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+        deps
+    }
+
+    // Deposits a coin and returns the transfer sent to the host, after
+    // handing its sequence back to the agent.
+    fn deposit(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, sequence: u64) -> Binary {
+        let info = mock_info(USER, &coins(100, DENOM));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit { deal_id: 0 },
+        )
+        .unwrap();
+        assert_eq!(res.messages[0].id, DEPOSIT_REPLY_ID);
+        let CosmosMsg::Stargate { type_url, value } = &res.messages[0].msg else {
+            panic!("unexpected message {:?}", res.messages[0].msg);
+        };
+        assert_eq!(type_url, "/ibc.applications.transfer.v1.MsgTransfer");
+
+        let mut data = vec![];
+        proto::uint(&mut data, 1, sequence);
+        let reply_msg = Reply {
+            id: DEPOSIT_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(data.into()),
+            }),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        value.clone()
+    }
+
+    fn settle(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        msg: IbcLifecycleComplete,
+    ) -> Response {
+        let msg = SudoMsg::IbcLifecycleComplete(msg);
+        sudo(deps.as_mut(), mock_env(), msg).unwrap()
+    }
+
+    fn pending(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>) -> Vec<Coin> {
+        let msg = QueryMsg::PendingDeposits {
+            address: USER.to_string(),
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        from_json::<PendingDepositsResponse>(res).unwrap().deposits
+    }
+
+    #[test]
+    fn deposit_is_sent_with_hook_calling_host() {
+        let mut deps = setup();
+        let transfer = deposit(&mut deps, 7);
+
+        // The memo has the host credit the coin as it arrives, and report back
+        let memo = format!(
+            r#"{{"wasm":{{"contract":"{}","msg":{{"deposit_ibc":{{"deal_id":0,"sender":"{}"}}}}}},"ibc_callback":"{}"}}"#,
+            HOST_CONTRACT, USER, MOCK_CONTRACT_ADDR
+        );
+        assert!(transfer
+            .windows(memo.len())
+            .any(|window| window == memo.as_bytes()));
+
+        // A second deposit of the same coin waits for the first to settle
+        let info = mock_info(USER, &coins(100, DENOM));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit { deal_id: 0 },
+        )
+        .unwrap_err();
+
+        let res = settle(
+            &mut deps,
+            IbcLifecycleComplete::IbcAck {
+                channel: TRANSFER_CHANNEL.to_string(),
+                sequence: 7,
+                ack: String::new(),
+                success: true,
+            },
+        );
+        assert!(res.messages.is_empty());
+        assert!(pending(&deps).is_empty());
+    }
+
+    #[test]
+    fn deposit_is_refunded_when_host_rejects_or_times_out() {
+        let mut deps = setup();
+        let refund = CosmosMsg::Bank(BankMsg::Send {
+            to_address: USER.to_string(),
+            amount: coins(100, DENOM),
+        });
+
+        deposit(&mut deps, 1);
+        assert_eq!(pending(&deps), coins(100, DENOM));
+        let res = settle(
+            &mut deps,
+            IbcLifecycleComplete::IbcAck {
+                channel: TRANSFER_CHANNEL.to_string(),
+                sequence: 1,
+                ack: String::new(),
+                success: false,
+            },
+        );
+        assert_eq!(res.messages[0].msg, refund);
+
+        deposit(&mut deps, 2);
+        let res = settle(
+            &mut deps,
+            IbcLifecycleComplete::IbcTimeout {
+                channel: TRANSFER_CHANNEL.to_string(),
+                sequence: 2,
+            },
+        );
+        assert_eq!(res.messages[0].msg, refund);
+        assert!(pending(&deps).is_empty());
+
+        // Transfers we did not send are ignored
+        let res = settle(
+            &mut deps,
+            IbcLifecycleComplete::IbcTimeout {
+                channel: TRANSFER_CHANNEL.to_string(),
+                sequence: 2,
+            },
+        );
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn instruction_outcome_is_surfaced() {
+        let mut deps = setup();
+        let packet = IbcExecuteMsg::CompleteDeal {
+            deal_id: 0,
+            packet20: Ics20Packet {
+                sender: USER.to_string(),
+                ..Ics20Packet::default()
+            },
+        };
+
+        let result = AckResult {
+            deal_id: 0,
+            status: DealStatus::Settled,
+            credited: None,
+            payout: None,
+        };
+        let ack = IbcAcknowledgement::new(make_ack_result(to_json_binary(&result).unwrap()));
+        let msg = mock_ibc_packet_ack(HOST_CHANNEL, &packet, ack).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "status" && attr.value == "settled"));
+
+        let ack = IbcAcknowledgement::new(make_ack_fail(
+            ErrorCode::Rejected,
+            "No deal found".to_string(),
+        ));
+        let msg = mock_ibc_packet_ack(HOST_CHANNEL, &packet, ack).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "error_code" && attr.value == "rejected"));
+
+        let msg = mock_ibc_packet_timeout(HOST_CHANNEL, &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "sender" && attr.value == USER));
+    }
+
+    #[test]
+    fn handshake_only_with_configured_host() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            host_contract: HOST_CONTRACT.to_string(),
+            host_port: "wasm.host_contract".to_string(),
+            host_connection: HOST_CONNECTION.to_string(),
+            transfer_channel: TRANSFER_CHANNEL.to_string(),
+            host_transfer_channel: "channel-141".to_string(),
+            trader_group: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+        let open = mock_ibc_channel_open_init(HOST_CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+        let err = ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap_err();
+        assert!(matches!(err, ContractError::NotHostCounterparty { .. }));
+        let connect = mock_ibc_channel_connect_ack(HOST_CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap_err();
+    }
+
+    #[test]
+    fn balance_query_answered_in_ack() {
+        let mut deps = setup();
+        deps.querier.update_balance(USER, coins(70, DENOM));
+
+        // The host asks in the denom our coin has over there
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        let denom = host_denom(&config, DENOM).unwrap();
+
+        let query = IbcHostMsg::QueryBalance {
+            deal_id: 0,
+            address: USER.to_string(),
+            denom: denom.clone(),
+        };
+        let recv = mock_ibc_packet_recv(HOST_CHANNEL, &query).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+        let ack: Ack = from_json(res.acknowledgement).unwrap();
+        assert_eq!(
+            ack.outcome(),
+            Ok(BalanceProof {
+                address: USER.to_string(),
+                denom,
+                amount: Uint128::new(70),
+                height: mock_env().block.height,
+            })
+        );
+    }
+
+    #[test]
+    fn htlc_mirrors_lock_on_host() {
+        let mut deps = setup();
+        // sha256("secret"), the hashlock the seller locked with on the host
+        let hashlock =
+            HexBinary::from_hex("2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b")
+                .unwrap();
+        let msg = ExecuteMsg::LockHtlc {
+            recipient: "seller".to_string(),
+            hashlock,
+            timelock: mock_env().block.time.seconds() + 50,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(USER, &coins(200, DENOM)),
+            msg,
+        )
+        .unwrap();
+
+        // Claiming here publishes the preimage that opens the host's lock
+        let msg = ExecuteMsg::ClaimHtlc {
+            id: 0,
+            preimage: HexBinary::from(b"secret"),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "seller".to_string(),
+                amount: coins(200, DENOM),
+            })
+        );
+        assert!(res.attributes.iter().any(
+            |attr| attr.key == "preimage" && attr.value == HexBinary::from(b"secret").to_hex()
+        ));
+
+        let msg = ExecuteMsg::RefundHtlc { id: 0 };
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(50);
+        let err = execute(deps.as_mut(), env, mock_info(USER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::HtlcNotLocked { id: 0 }));
+    }
+
+    #[test]
+    fn htlc_locks_only_between_group_members() {
+        let mut deps = setup();
+        let mut config = CONFIG.load(&deps.storage).unwrap();
+        config.trader_group = Some("group".to_string());
+        CONFIG.save(&mut deps.storage, &config).unwrap();
+        // Only USER is in the group
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { msg, .. } => {
+                let weight = if String::from_utf8_lossy(msg).contains(USER) {
+                    "1"
+                } else {
+                    "null"
+                };
+                SystemResult::Ok(ContractResult::Ok(Binary::from(
+                    format!(r#"{{"weight":{}}}"#, weight).as_bytes(),
+                )))
+            }
+            _ => panic!("unexpected query"),
+        });
+
+        let lock = |recipient: &str| ExecuteMsg::LockHtlc {
+            recipient: recipient.to_string(),
+            hashlock: HexBinary::from([0u8; 32]),
+            timelock: mock_env().block.time.seconds() + 50,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(USER, &coins(200, DENOM)),
+            lock("outsider"),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::TraderNotAllowed { address } if address == "outsider"
+        ));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("outsider", &coins(200, DENOM)),
+            lock(USER),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::TraderNotAllowed { address } if address == "outsider"
+        ));
+    }
+}
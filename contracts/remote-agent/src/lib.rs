@@ -0,0 +1,8 @@
+pub mod contract;
+mod error;
+pub mod ibc;
+pub mod integration_tests;
+pub mod msg;
+pub mod state;
+
+pub use crate::error::ContractError;
@@ -0,0 +1,83 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+use cosmwasm_std::{Coin, HexBinary};
+use otc_ibc::hooks::IbcLifecycleComplete;
+use otc_ibc::htlc::Htlc;
+use otc_ibc::{DealProposal, PayoutRoute};
+
+use crate::state::Config;
+
+#[cw_serde]
+pub struct InstantiateMsg {
+    pub host_contract: String,
+    /// port of the host contract on the host chain, `wasm.<host_contract>`
+    pub host_port: String,
+    /// connection on this chain leading to the host chain
+    pub host_connection: String,
+    pub transfer_channel: String,
+    pub host_transfer_channel: String,
+    /// cw4 group on this chain mirroring the host's trader allowlist
+    pub trader_group: Option<String>,
+}
+
+#[cw_serde]
+pub enum ExecuteMsg {
+    /// Proposes a deal between the sender and another party on the host. Coins
+    /// are given in their host chain denoms. The id of the created deal is
+    /// emitted as the `deal_id` attribute when the host acknowledges it.
+    CreateDeal(DealProposal),
+    /// Accepts a deal proposed to the sender.
+    AcceptDeal { deal_id: u64 },
+    /// Calls off one of the sender's deals before either leg is deposited.
+    CancelDeal { deal_id: u64 },
+    /// Transfers the attached coin to the host, which credits it to the
+    /// sender's leg of deal `deal_id` as it arrives. The coin is refunded if
+    /// the host rejects it or the transfer times out.
+    Deposit { deal_id: u64 },
+    /// Asks the host to refund the sender's unmatched deposit to deal `deal_id`.
+    Withdraw { deal_id: u64 },
+    /// Asks the host to settle deal `deal_id`. The counter leg is paid out
+    /// to the sender on this chain over ICS-20.
+    CompleteDeal { deal_id: u64 },
+    /// Has the host lock the sender's leg of deal `deal_id` that stays on this
+    /// chain. The host's interchain account here takes the leg from the
+    /// sender, who has to grant it a send authorization for the leg first.
+    LockRemoteLeg { deal_id: u64 },
+    /// Has the host forward the sender's payouts from deal `deal_id` along
+    /// `route` once they reach this chain, or stop doing so if unset.
+    SetPayoutRoute {
+        deal_id: u64,
+        route: Option<PayoutRoute>,
+    },
+    /// Locks the attached coin for `recipient` on this chain, mirroring an HTLC
+    /// on the host (or any chain using the same hashlock). Needs no channel.
+    LockHtlc {
+        recipient: String,
+        hashlock: HexBinary,
+        timelock: u64,
+    },
+    /// Releases an HTLC to its recipient. Anyone holding the preimage can claim.
+    ClaimHtlc { id: u64, preimage: HexBinary },
+    /// Returns an unclaimed HTLC to its sender once its timelock has passed.
+    RefundHtlc { id: u64 },
+}
+
+#[cw_serde]
+pub struct PendingDepositsResponse {
+    pub deposits: Vec<Coin>,
+}
+
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum QueryMsg {
+    #[returns(Config)]
+    Config {},
+    #[returns(PendingDepositsResponse)]
+    PendingDeposits { address: String },
+    #[returns(Htlc)]
+    Htlc { id: u64 },
+}
+
+#[cw_serde]
+pub enum SudoMsg {
+    IbcLifecycleComplete(IbcLifecycleComplete),
+}
@@ -0,0 +1,43 @@
+use cosmwasm_std::{Addr, Coin};
+use cw_storage_plus::{Item, Map};
+use otc_ibc::htlc::Htlc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Config {
+    /// the OTC contract on the host chain, receives forwarded deposits
+    pub host_contract: String,
+    /// the only counterparty port a channel to the host may be opened with
+    pub host_port: String,
+    /// the only connection a channel to the host may be opened on
+    pub host_connection: String,
+    /// the ICS-20 channel on this chain leading to the host chain
+    pub transfer_channel: String,
+    /// the host chain's end of `transfer_channel`
+    pub host_transfer_channel: String,
+    /// only members may lock HTLCs or be paid by them, if set
+    pub trader_group: Option<String>,
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+/// The app channel connected to the host contract.
+pub const HOST_CHANNEL: Item<String> = Item::new("host_channel");
+
+/// Deposits on their way to the host until their transfer is settled, keyed
+/// by depositor and the denom the deal is quoted in on the host.
+pub const PENDING_DEPOSITS: Map<(&Addr, &str), Coin> = Map::new("pending_deposits");
+
+/// The deposit whose transfer is being sent, until its sequence is known.
+pub const SENDING_DEPOSIT: Item<(Addr, String)> = Item::new("sending_deposit");
+
+/// Depositor and host denom of each deposit transfer, by our end of the
+/// transfer channel and packet sequence.
+pub const DEPOSIT_TRANSFERS: Map<(&str, u64), (Addr, String)> = Map::new("deposit_transfers");
+
+/// This chain's legs of cross-chain atomic swaps, keyed by id.
+pub const HTLCS: Map<u64, Htlc> = Map::new("htlcs");
+
+/// Number of HTLCs created, the id of the next one.
+pub const HTLC_COUNT: Item<u64> = Item::new("htlc_count");
@@ -0,0 +1,145 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{from_json, to_json_binary, to_json_string, Binary, Coin};
+use serde::de::DeserializeOwned;
+
+/// IBC ACK. See:
+/// https://github.com/cosmos/cosmos-sdk/blob/f999b1ff05a4db4a338a855713864497bedd4396/proto/ibc/core/channel/v1/channel.proto#L141-L147
+///
+/// Instructions to the host contract are answered with a JSON [`AckResult`]
+/// as the result, or a JSON [`AckError`] as the error string.
+#[cw_serde]
+pub enum Ack {
+    Result(Binary),
+    Error(String),
+}
+
+impl Ack {
+    /// Decodes the outcome of a packet. Errors that are not an [`AckError`],
+    /// such as those written by ibc-go itself, come back with an unknown code.
+    pub fn outcome<T: DeserializeOwned>(&self) -> Result<T, AckError> {
+        match self {
+            Ack::Result(data) => from_json(data).map_err(|err| AckError {
+                code: ErrorCode::InvalidAck,
+                message: err.to_string(),
+            }),
+            Ack::Error(error) => Err(from_json(error.as_bytes()).unwrap_or_else(|_| AckError {
+                code: ErrorCode::Unknown,
+                message: error.clone(),
+            })),
+        }
+    }
+}
+
+/// Where a deal stands after the host handled an instruction for it.
+#[cw_serde]
+#[derive(Copy, Eq)]
+pub enum DealStatus {
+    /// proposed over IBC and waiting for the other party to accept
+    Proposed,
+    /// nobody has deposited yet
+    Open,
+    /// one leg is deposited
+    PartiallyFunded,
+    /// both legs are deposited and can be withdrawn
+    Funded,
+    /// both parties have withdrawn
+    Settled,
+    Cancelled,
+    Expired,
+}
+
+impl DealStatus {
+    /// Name of the status, as used in attributes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DealStatus::Proposed => "proposed",
+            DealStatus::Open => "open",
+            DealStatus::PartiallyFunded => "partially_funded",
+            DealStatus::Funded => "funded",
+            DealStatus::Settled => "settled",
+            DealStatus::Cancelled => "cancelled",
+            DealStatus::Expired => "expired",
+        }
+    }
+}
+
+/// A payout made while handling an instruction.
+#[cw_serde]
+pub struct Payout {
+    pub amount: Coin,
+    /// counter local to the host numbering its payouts in the order they were
+    /// made, emitted as the `payout_id` attribute of the transaction that made
+    /// it. Not the sequence of the IBC packet carrying a payout on.
+    pub id: u64,
+}
+
+/// Result of an instruction the host contract carried out.
+#[cw_serde]
+pub struct AckResult {
+    pub deal_id: u64,
+    pub status: DealStatus,
+    /// the leg a deposit credited to the deal
+    pub credited: Option<Coin>,
+    /// what a withdrawal or settlement paid out to the sender
+    pub payout: Option<Payout>,
+}
+
+#[cw_serde]
+#[derive(Copy, Eq)]
+pub enum ErrorCode {
+    /// not written by an otc-escrow contract
+    Unknown,
+    /// the acknowledgement itself could not be decoded
+    InvalidAck,
+    /// the packet could not be decoded
+    InvalidPacket,
+    /// the sender may not do this, or not over this channel
+    Unauthorized,
+    /// the channel is not allowlisted or frozen
+    ChannelDisabled,
+    /// the instruction does not fit the state of the deal
+    Rejected,
+    /// the host contract is paused for this kind of instruction
+    Paused,
+    /// a party was flagged by the host's screening contract
+    Flagged,
+}
+
+impl ErrorCode {
+    /// Name of the code, as used in attributes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Unknown => "unknown",
+            ErrorCode::InvalidAck => "invalid_ack",
+            ErrorCode::InvalidPacket => "invalid_packet",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::ChannelDisabled => "channel_disabled",
+            ErrorCode::Rejected => "rejected",
+            ErrorCode::Paused => "paused",
+            ErrorCode::Flagged => "flagged",
+        }
+    }
+}
+
+#[cw_serde]
+pub struct AckError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+pub fn make_ack_success() -> Binary {
+    let res = Ack::Result(b"1".into());
+    to_json_binary(&res).unwrap()
+}
+
+/// A success ack carrying `data`, usually a JSON [`AckResult`], back to the sender.
+pub fn make_ack_result(data: Binary) -> Binary {
+    let res = Ack::Result(data);
+    to_json_binary(&res).unwrap()
+}
+
+pub fn make_ack_fail(code: ErrorCode, message: String) -> Binary {
+    let error = AckError { code, message };
+    let res = Ack::Error(to_json_string(&error).unwrap());
+    to_json_binary(&res).unwrap()
+}
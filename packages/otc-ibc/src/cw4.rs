@@ -0,0 +1,29 @@
+use cosmwasm_std::{Deps, StdResult};
+use serde::{Deserialize, Serialize};
+
+// The part of the cw4 group query interface the contracts use.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Cw4QueryMsg {
+    Member {
+        addr: String,
+        at_height: Option<u64>,
+    },
+}
+
+#[derive(Deserialize)]
+struct MemberResponse {
+    weight: Option<u64>,
+}
+
+/// Whether `addr` is currently a member of the cw4 group contract at `group`.
+pub fn is_member(deps: Deps, group: &str, addr: &str) -> StdResult<bool> {
+    let res: MemberResponse = deps.querier.query_wasm_smart(
+        group,
+        &Cw4QueryMsg::Member {
+            addr: addr.to_string(),
+            at_height: None,
+        },
+    )?;
+    Ok(res.weight.is_some())
+}
@@ -0,0 +1,84 @@
+//! ibc-hooks lets an ICS-20 transfer call a contract on the receiving chain
+//! with the transferred coins attached, and tell the sending contract how the
+//! transfer went. Remote agents deposit this way, so the host only credits a
+//! leg once its coins have arrived.
+
+use bech32::{FromBase32, ToBase32, Variant};
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{StdError, StdResult};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Prefix ibc-hooks derives the addresses it calls contracts from with.
+const SENDER_PREFIX: &str = "ibc-wasm-hook-intermediary";
+
+/// Transfer memo having ibc-hooks on the receiving chain call `contract`
+/// with `msg`, and report the outcome to `ibc_callback` on the sending chain.
+#[derive(Serialize)]
+pub struct HookMemo<T> {
+    pub wasm: WasmHook<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ibc_callback: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WasmHook<T> {
+    /// has to be the receiver of the transfer
+    pub contract: String,
+    pub msg: T,
+}
+
+/// Calls remote agents make on the host contract through ibc-hooks.
+#[cw_serde]
+pub enum HostHookMsg {
+    /// Credits the transferred coin to `sender`'s leg of deal `deal_id`.
+    DepositIbc { deal_id: u64, sender: String },
+}
+
+/// Sudo message ibc-hooks sends the contract that sent a transfer with an
+/// `ibc_callback`, once the transfer is acknowledged or times out.
+#[cw_serde]
+pub enum IbcLifecycleComplete {
+    IbcAck {
+        /// our end of the transfer channel
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    IbcTimeout {
+        channel: String,
+        sequence: u64,
+    },
+}
+
+/// Whether `sender` is the address ibc-hooks calls contracts from for
+/// transfers `original_sender` made to `channel`, the receiving chain's end
+/// of the transfer channel. The bech32 prefix is not checked, as it is the
+/// receiving chain's own.
+pub fn is_intermediate_sender(sender: &str, channel: &str, original_sender: &str) -> bool {
+    let Ok((_, data, Variant::Bech32)) = bech32::decode(sender) else {
+        return false;
+    };
+    Vec::<u8>::from_base32(&data)
+        .is_ok_and(|bytes| bytes == intermediate_hash(channel, original_sender))
+}
+
+/// Address ibc-hooks calls contracts from, under `prefix`.
+pub fn intermediate_sender(
+    prefix: &str,
+    channel: &str,
+    original_sender: &str,
+) -> StdResult<String> {
+    let hash = intermediate_hash(channel, original_sender);
+    bech32::encode(prefix, hash.to_base32(), Variant::Bech32)
+        .map_err(|err| StdError::generic_err(err.to_string()))
+}
+
+// As Cosmos SDK `address.Hash`: sha256 of the hashed prefix and the key.
+fn intermediate_hash(channel: &str, original_sender: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(Sha256::digest(SENDER_PREFIX.as_bytes()));
+    hasher.update(format!("{}/{}", channel, original_sender).as_bytes());
+    hasher.finalize().into()
+}
@@ -0,0 +1,43 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Coin, HexBinary, Timestamp};
+use sha2::{Digest, Sha256};
+
+/// Length of a sha256 hashlock in bytes.
+pub const HASHLOCK_LEN: usize = 32;
+
+/// A hashed time-locked transfer. `recipient` can have `amount` released by
+/// revealing the preimage of `hashlock` before `timelock`, after which only
+/// `sender` can take it back. The host contract and remote agents hold these
+/// the same way, so a swap locks each leg on its own chain under one hashlock
+/// and the preimage revealed by the first claim opens the other lock.
+#[cw_serde]
+pub struct Htlc {
+    pub sender: String,
+    pub recipient: String,
+    pub amount: Coin,
+    /// sha256 of the preimage
+    pub hashlock: HexBinary,
+    /// unix time in seconds from which the lock can be refunded
+    pub timelock: u64,
+    pub state: HtlcState,
+}
+
+#[cw_serde]
+pub enum HtlcState {
+    Locked,
+    /// released to the recipient; the preimage is kept for the other chain's lock
+    Claimed {
+        preimage: HexBinary,
+    },
+    Refunded,
+}
+
+impl Htlc {
+    pub fn opens_with(&self, preimage: &[u8]) -> bool {
+        Sha256::digest(preimage).as_slice() == self.hashlock.as_slice()
+    }
+
+    pub fn expired(&self, now: Timestamp) -> bool {
+        now >= Timestamp::from_seconds(self.timelock)
+    }
+}
@@ -0,0 +1,16 @@
+pub mod ack;
+pub mod cw4;
+pub mod hooks;
+pub mod htlc;
+mod msg;
+mod packet;
+pub mod proto;
+mod route;
+
+pub use crate::msg::{BalanceProof, DealProposal, IbcExecuteMsg, IbcHostMsg};
+pub use crate::packet::Ics20Packet;
+pub use crate::route::{ForwardHop, PayoutRoute};
+
+/// The app protocol version spoken on channels between the host contract
+/// and its remote agents.
+pub const IBC_VERSION: &str = "otc-escrow-1";
@@ -0,0 +1,131 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Coin, Uint128};
+
+use crate::packet::Ics20Packet;
+use crate::route::PayoutRoute;
+
+/// Terms of a deal originated on a remote chain. coin_a belongs to the seller
+/// and coin_b to the buyer, both in their denoms on the host chain.
+#[cw_serde]
+pub struct DealProposal {
+    pub seller: String,
+    pub buyer: String,
+    pub coin_a: Coin,
+    pub coin_b: Coin,
+    pub expiry: u64,
+    /// channel the other party is reached over, if it is not on the host chain
+    pub counterparty_channel: Option<String>,
+}
+
+/// Instructions a remote agent sends to the host contract. Deposits are not
+/// among them: they arrive as ICS-20 transfers calling the host through
+/// ibc-hooks, see [`crate::hooks`].
+#[cw_serde]
+pub enum IbcExecuteMsg {
+    /// Proposes a deal between `sender` and another party, who has to accept it.
+    /// The success ack carries the new deal id in its `AckResult`.
+    CreateDeal {
+        sender: String,
+        proposal: DealProposal,
+    },
+    AcceptDeal {
+        deal_id: u64,
+        sender: String,
+    },
+    /// Calls a deal off before either leg is deposited.
+    CancelDeal {
+        deal_id: u64,
+        sender: String,
+    },
+    Withdraw {
+        deal_id: u64,
+        packet20: Ics20Packet,
+    },
+    CompleteDeal {
+        deal_id: u64,
+        packet20: Ics20Packet,
+    },
+    /// Has the host's interchain account on `sender`'s chain take `sender`'s
+    /// leg of deal `deal_id` from it, under the send authorization `sender`
+    /// granted the account.
+    LockRemoteLeg {
+        deal_id: u64,
+        sender: String,
+    },
+    /// Sets or clears the route `sender`'s payouts from deal `deal_id` take.
+    SetPayoutRoute {
+        deal_id: u64,
+        sender: String,
+        route: Option<PayoutRoute>,
+    },
+}
+
+/// Packets the host contract sends to remote agents when a deal changes state.
+#[cw_serde]
+pub enum IbcHostMsg {
+    DealCreated {
+        deal_id: u64,
+        seller: String,
+        buyer: String,
+        coin_a: Coin,
+        coin_b: Coin,
+        expiry: u64,
+    },
+    /// `party` has deposited its leg of the deal.
+    CounterpartyFunded {
+        deal_id: u64,
+        party: String,
+    },
+    Settled {
+        deal_id: u64,
+    },
+    Expired {
+        deal_id: u64,
+    },
+    Cancelled {
+        deal_id: u64,
+    },
+    /// Asks for `address`'s balance of `denom`, given in its host chain denom.
+    /// The agent answers with a `BalanceProof` in its success ack.
+    QueryBalance {
+        deal_id: u64,
+        address: String,
+        denom: String,
+    },
+}
+
+/// A remote party's balance as read by its agent.
+#[cw_serde]
+pub struct BalanceProof {
+    pub address: String,
+    /// host chain denom the balance was asked for
+    pub denom: String,
+    pub amount: Uint128,
+    /// block height on the agent's chain the balance was read at
+    pub height: u64,
+}
+
+impl IbcHostMsg {
+    pub fn deal_id(&self) -> u64 {
+        match self {
+            IbcHostMsg::DealCreated { deal_id, .. }
+            | IbcHostMsg::CounterpartyFunded { deal_id, .. }
+            | IbcHostMsg::Settled { deal_id }
+            | IbcHostMsg::Expired { deal_id }
+            | IbcHostMsg::Cancelled { deal_id }
+            | IbcHostMsg::QueryBalance { deal_id, .. } => *deal_id,
+        }
+    }
+
+    /// Short name of the event, used in attributes and delivery tracking.
+    pub fn event(&self) -> &'static str {
+        match self {
+            IbcHostMsg::DealCreated { .. } => "deal_created",
+            IbcHostMsg::CounterpartyFunded { .. } => "counterparty_funded",
+            IbcHostMsg::Settled { .. } => "settled",
+            IbcHostMsg::Expired { .. } => "expired",
+            IbcHostMsg::Cancelled { .. } => "cancelled",
+            IbcHostMsg::QueryBalance { .. } => "query_balance",
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Minimal protobuf encoding for the few Cosmos SDK messages the contracts
+//! send as `CosmosMsg::Stargate`. Default values are skipped, as in proto3.
+
+use cosmwasm_std::{Coin, CosmosMsg, Timestamp};
+
+pub fn uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    if value != 0 {
+        varint(buf, u64::from(field << 3));
+        varint(buf, value);
+    }
+}
+
+pub fn string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        bytes(buf, field, value.as_bytes());
+    }
+}
+
+pub fn message(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    bytes(buf, field, value);
+}
+
+fn bytes(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    varint(buf, u64::from(field << 3 | 2));
+    varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// The first varint `field` of an encoded message, if present.
+pub fn read_uint(mut buf: &[u8], field: u32) -> Option<u64> {
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        let value = match key & 7 {
+            0 => read_varint(&mut buf)?,
+            2 => {
+                let len = read_varint(&mut buf)? as usize;
+                buf = buf.get(len..)?;
+                continue;
+            }
+            _ => return None,
+        };
+        if key >> 3 == u64::from(field) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// An ICS-20 `MsgTransfer` from `sender`, sent as a stargate message since
+/// `IbcMsg::Transfer` cannot carry a memo. Its response carries the packet
+/// sequence as field 1.
+pub fn transfer(
+    channel_id: &str,
+    sender: &str,
+    receiver: &str,
+    amount: &Coin,
+    timeout: Timestamp,
+    memo: &str,
+) -> CosmosMsg {
+    let mut token = vec![];
+    string(&mut token, 1, &amount.denom);
+    string(&mut token, 2, &amount.amount.to_string());
+
+    let mut msg = vec![];
+    string(&mut msg, 1, "transfer");
+    string(&mut msg, 2, channel_id);
+    message(&mut msg, 3, &token);
+    string(&mut msg, 4, sender);
+    string(&mut msg, 5, receiver);
+    uint(&mut msg, 7, timeout.nanos());
+    string(&mut msg, 8, memo);
+
+    CosmosMsg::Stargate {
+        type_url: "/ibc.applications.transfer.v1.MsgTransfer".to_string(),
+        value: msg.into(),
+    }
+}
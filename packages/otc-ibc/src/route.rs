@@ -0,0 +1,23 @@
+use cosmwasm_schema::cw_serde;
+
+/// A multi-hop payout path. The payout first lands with the party on its own
+/// chain and is then forwarded along `hops` by packet-forward-middleware.
+#[cw_serde]
+#[derive(Eq)]
+pub struct PayoutRoute {
+    pub hops: Vec<ForwardHop>,
+}
+
+/// One forward, taken by the chain the previous hop reached.
+#[cw_serde]
+#[derive(Eq)]
+pub struct ForwardHop {
+    /// channel the forwarding chain sends over
+    pub channel: String,
+    /// receiver on the chain this hop reaches; the last one gets the payout
+    pub receiver: String,
+    /// how long the forward may take before it is refunded, middleware default if unset
+    pub timeout_seconds: Option<u64>,
+    /// how often a timed out forward is retried, middleware default if unset
+    pub retries: Option<u8>,
+}
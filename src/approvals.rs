@@ -0,0 +1,123 @@
+use cosmwasm_std::{Addr, Coin, Deps, StdError};
+use otc_ibc::cw4;
+
+use crate::state::{Deal, CONFIG};
+use crate::ContractError;
+
+/// Who has to approve a deal before it can settle.
+pub struct Approval {
+    pub approvers: Vec<String>,
+    /// approvals needed from `approvers`
+    pub required: u32,
+    pub group: Option<String>,
+    /// approvals needed from members of `group`
+    pub group_required: u32,
+}
+
+/// Works out the approvals a new deal needs. Deals with a leg at or above its
+/// denom's threshold always need approvals from the configured approver
+/// group. Approvers listed with the deal have to approve on top of that,
+/// whatever its size. Neither party can be one of them.
+pub fn policy(
+    deps: Deps,
+    parties: [&str; 2],
+    legs: [&Coin; 2],
+    approvers: Vec<String>,
+    required: u32,
+) -> Result<Approval, ContractError> {
+    let mut validated: Vec<String> = vec![];
+    for approver in approvers {
+        let approver = deps.api.addr_validate(&approver)?.into_string();
+        if parties.contains(&approver.as_str()) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "A party cannot approve its own deal",
+            )));
+        }
+        if !validated.contains(&approver) {
+            validated.push(approver);
+        }
+    }
+    if validated.is_empty() && required > 0 {
+        return Err(ContractError::Std(StdError::generic_err(
+            "Required approvals need approvers",
+        )));
+    }
+    if !validated.is_empty() && (required == 0 || required as usize > validated.len()) {
+        return Err(ContractError::Std(StdError::generic_err(
+            "Required approvals must be between 1 and the number of approvers",
+        )));
+    }
+
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let large = legs.iter().any(|leg| {
+        config
+            .large_deal_thresholds
+            .iter()
+            .any(|threshold| threshold.denom == leg.denom && leg.amount >= threshold.amount)
+    });
+    let (group, group_required) = match (large, config.approver_group) {
+        (false, _) => (None, 0),
+        (true, Some(group)) => (Some(group), config.group_approvals_required),
+        (true, None) => {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deals this large need an approver group",
+            )))
+        }
+    };
+    Ok(Approval {
+        approvers: validated,
+        required,
+        group,
+        group_required,
+    })
+}
+
+/// Whether `sender` may approve `deal`, as a listed approver or a member of
+/// its approver group. Its parties never can.
+pub fn is_approver(deps: Deps, deal: &Deal, sender: &Addr) -> Result<bool, ContractError> {
+    if sender == &deal.seller || sender == &deal.buyer {
+        return Ok(false);
+    }
+    if deal.approvers.iter().any(|approver| approver == sender) {
+        return Ok(true);
+    }
+    match &deal.approver_group {
+        Some(group) => Ok(cw4::is_member(deps, group, sender.as_str())?),
+        None => Ok(false),
+    }
+}
+
+/// Fails until a deal has the approvals it requires from its listed
+/// approvers and from its approver group. Membership is checked as the deal
+/// settles, so approvals of members since removed no longer count.
+pub fn assert_approved(deps: Deps, deal: &Deal) -> Result<(), ContractError> {
+    if deal.approvals_required == 0 && deal.group_approvals_required == 0 {
+        return Ok(());
+    }
+    let listed = deal
+        .approvals
+        .iter()
+        .filter(|approval| deal.approvers.contains(approval))
+        .count() as u32;
+    if listed < deal.approvals_required {
+        return Err(ContractError::Std(StdError::generic_err(format!(
+            "Deal has {} of {} required approvals from its approvers",
+            listed, deal.approvals_required
+        ))));
+    }
+    let mut members = 0;
+    if let Some(group) = &deal.approver_group {
+        for approval in &deal.approvals {
+            if cw4::is_member(deps, group, approval)? {
+                members += 1;
+            }
+        }
+    }
+    if members < deal.group_approvals_required {
+        return Err(ContractError::Std(StdError::generic_err(format!(
+            "Deal has {} of {} required approvals from its approver group",
+            members, deal.group_approvals_required
+        ))));
+    }
+    Ok(())
+}
@@ -1,13 +1,18 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Binary, Coin, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+    to_json_binary, Addr, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Reply, Response,
+    StdError, StdResult, SubMsgResult,
 };
 // use cw2::set_contract_version;
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{State, STATE};
+use crate::msg::{
+    ExecuteMsg, IbcLifecycleComplete, InstantiateMsg, PauseStatusResponse, QueryMsg,
+    RoleMembersResponse, SudoMsg, TraderResponse,
+};
+use crate::state::{Role, State, PAUSED, ROLES, STATE};
+use crate::{screening, traders};
 
 use cw_controllers::Admin;
 
@@ -17,7 +22,8 @@ const CONTRACT_NAME: &str = "crates.io:assignment";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 */
 
-const ADMIN: Admin = Admin::new("admin");
+// Can pause and unpause the contract alongside operators.
+const GUARDIAN: Admin = Admin::new("guardian");
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -27,8 +33,17 @@ pub fn instantiate(
     _msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     // set_contract_version(_deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    ADMIN.set(deps.branch(), Some(info.sender))?;
-    STATE.save(deps.storage, &State { deals: vec![] })?;
+    for role in Role::ALL {
+        ROLES.save(deps.storage, (role.as_str(), &info.sender), &Empty {})?;
+    }
+    GUARDIAN.set(deps.branch(), None)?;
+    STATE.save(
+        deps.storage,
+        &State {
+            deals: vec![],
+            first_deal_id: 0,
+        },
+    )?;
     Ok(Response::new().add_attribute("method", "instantiate"))
 }
 
@@ -40,537 +55,2932 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::CreateDeal {
-            seller,
-            buyer,
-            coin_a,
-            coin_b,
-            expiry,
-        } => execute::create_deal(deps, env, info, seller, buyer, coin_a, coin_b, expiry),
-        ExecuteMsg::Deposit {} => execute::deposit(deps, env, info),
-        ExecuteMsg::CompleteDeal {} => execute::complete_deal(deps, env, info),
-        ExecuteMsg::Withdraw {} => execute::withdraw(deps, env, info),
+        ExecuteMsg::CreateDeal(terms) => execute::create_deal(deps, env, info, terms),
+        ExecuteMsg::Deposit { deal_id } => execute::deposit(deps, env, info, deal_id),
+        ExecuteMsg::CompleteDeal { deal_id } => execute::complete_deal(deps, env, info, deal_id),
+        ExecuteMsg::Withdraw { deal_id } => execute::withdraw(deps, env, info, deal_id),
+        ExecuteMsg::DepositIbc { deal_id, sender } => {
+            execute::deposit_ibc(deps, env, info, deal_id, sender)
+        }
         ExecuteMsg::Reset {} => execute::reset(deps, env, info),
+        ExecuteMsg::ForceRefund { deal_id } => execute::force_refund(deps, env, info, deal_id),
+        ExecuteMsg::ForceSettle { deal_id } => execute::force_settle(deps, env, info, deal_id),
+        ExecuteMsg::NotifyExpired { deal_id } => execute::notify_expired(deps, env, deal_id),
+        ExecuteMsg::AddChannel {
+            connection_id,
+            counterparty_port_id,
+            transfer_channel,
+        } => execute::add_channel(
+            deps,
+            info,
+            connection_id,
+            counterparty_port_id,
+            transfer_channel,
+        ),
+        ExecuteMsg::RemoveChannel {
+            connection_id,
+            counterparty_port_id,
+        } => execute::remove_channel(deps, info, connection_id, counterparty_port_id),
+        ExecuteMsg::FreezeChannel { channel_id } => {
+            execute::set_channel_frozen(deps, info, channel_id, true)
+        }
+        ExecuteMsg::UnfreezeChannel { channel_id } => {
+            execute::set_channel_frozen(deps, info, channel_id, false)
+        }
+        ExecuteMsg::RegisterInterchainAccount {
+            connection_id,
+            host_connection_id,
+        } => {
+            execute::register_interchain_account(deps, env, info, connection_id, host_connection_id)
+        }
+        ExecuteMsg::AcceptDeal { deal_id } => execute::accept_deal(deps, env, info, deal_id),
+        ExecuteMsg::CancelDeal { deal_id } => execute::cancel_deal(deps, env, info, deal_id),
+        ExecuteMsg::CheckFunding { deal_id } => execute::check_funding(deps, env, deal_id),
+        ExecuteMsg::LockHtlc {
+            recipient,
+            hashlock,
+            timelock,
+        } => execute::lock_htlc(deps, env, info, recipient, hashlock, timelock),
+        ExecuteMsg::ClaimHtlc { id, preimage } => execute::claim_htlc(deps, env, id, preimage),
+        ExecuteMsg::RefundHtlc { id } => execute::refund_htlc(deps, env, id),
+        ExecuteMsg::UpdateFees {
+            fee,
+            denom_fees,
+            fee_collector,
+        } => execute::update_fees(deps, info, fee, denom_fees, fee_collector),
+        ExecuteMsg::UpdateFeeTiers {
+            maker_discount_bps,
+            volume_tiers,
+            volume_window,
+        } => execute::update_fee_tiers(deps, info, maker_discount_bps, volume_tiers, volume_window),
+        ExecuteMsg::SetFeeDiscount {
+            address,
+            discount_bps,
+        } => execute::set_fee_discount(deps, info, address, discount_bps),
+        ExecuteMsg::ClaimFees { recipient } => execute::claim_fees(deps, info, recipient),
+        ExecuteMsg::Pause { scopes } => execute::set_paused(deps, info, scopes, true),
+        ExecuteMsg::Unpause { scopes } => execute::set_paused(deps, info, scopes, false),
+        ExecuteMsg::SetGuardian { guardian } => execute::set_guardian(deps, info, guardian),
+        ExecuteMsg::RaiseDispute { deal_id, reason } => {
+            execute::raise_dispute(deps, env, info, deal_id, reason)
+        }
+        ExecuteMsg::Resolve {
+            deal_id,
+            seller_gets,
+            buyer_gets,
+        } => execute::resolve(deps, env, info, deal_id, seller_gets, buyer_gets),
+        ExecuteMsg::Approve { deal_id } => execute::approve(deps, info, deal_id),
+        ExecuteMsg::UpdateApprovalPolicy {
+            large_deal_thresholds,
+            approver_group,
+            group_approvals_required,
+        } => execute::update_approval_policy(
+            deps,
+            info,
+            large_deal_thresholds,
+            approver_group,
+            group_approvals_required,
+        ),
+        ExecuteMsg::UpdateLimits {
+            max_deal_amounts,
+            max_open_deals,
+            max_locked,
+        } => execute::update_limits(deps, info, max_deal_amounts, max_open_deals, max_locked),
+        ExecuteMsg::UpdatePriceOracle { oracle, band_bps } => {
+            execute::update_price_oracle(deps, info, oracle, band_bps)
+        }
+        ExecuteMsg::SetScreening { contract } => execute::set_screening(deps, info, contract),
+        ExecuteMsg::SetTraderAllowlist { traders } => {
+            execute::set_trader_allowlist(deps, info, traders)
+        }
+        ExecuteMsg::UpdateTraders { add, remove } => {
+            execute::update_traders(deps, info, add, remove)
+        }
+        ExecuteMsg::DepositInventory {} => execute::deposit_inventory(deps, info),
+        ExecuteMsg::WithdrawInventory { coins } => execute::withdraw_inventory(deps, info, coins),
+        ExecuteMsg::RegisterQuoteKey { pubkey } => execute::register_quote_key(deps, info, pubkey),
+        ExecuteMsg::CancelQuote { nonce } => execute::cancel_quote(deps, info, nonce),
+        ExecuteMsg::TakeQuote {
+            quote,
+            signature,
+            maker_pubkey,
+        } => execute::take_quote(deps, env, info, quote, signature, maker_pubkey),
+        ExecuteMsg::GrantRole { role, address } => {
+            execute::set_role(deps, info, role, address, true)
+        }
+        ExecuteMsg::RevokeRole { role, address } => {
+            execute::set_role(deps, info, role, address, false)
+        }
+        ExecuteMsg::RetryPayouts { deal_id } => execute::retry_payouts(deps, env, deal_id),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    // Every reply is to a payout transfer, by the id `ibc_payout` gave it
+    match msg.result {
+        SubMsgResult::Ok(response) => execute::payout_sent(deps, msg.id, response.data),
+        SubMsgResult::Err(err) => Err(StdError::generic_err(err).into()),
+    }
+}
+
+// ibc-hooks reports back on the transfers remote parties are paid with.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcAck {
+            channel,
+            sequence,
+            success,
+            ..
+        }) => execute::payout_settled(deps, channel, sequence, success),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout { channel, sequence }) => {
+            execute::payout_settled(deps, channel, sequence, false)
+        }
     }
 }
 
 pub mod execute {
-    use crate::state::{Deal, Ics20Packet, State, STATE};
-    use cosmwasm_std::{BankMsg, CosmosMsg, IbcMsg, StdError, Timestamp};
+    use crate::approvals;
+    use crate::fees;
+    use crate::funding::{assert_funding_checked, request_checks};
+    use crate::ibc::{hook_channel, validate_channel_enabled};
+    use crate::ica::{self, IcaAction, IcaMemo};
+    use crate::limits;
+    use crate::msg::{DealTerms, Quote};
+    use crate::notify::notify;
+    use crate::oracle;
+    use crate::pfm;
+    use crate::rfq;
+    use crate::state::{
+        Config, Deal, DealProposal, DenomFee, Dispute, FeeRate, Htlc, HtlcState, IbcPayout,
+        Ics20Packet, InterchainAccount, PauseScope, PayoutRoute, Peg, Ruling, Side, State,
+        TraderAllowlist, VolumeTier, ACCOUNT_DISCOUNTS, ACCRUED_FEES, ALLOWED_COUNTERPARTIES,
+        CHANNELS, CONFIG, FAILED_PAYOUTS, HTLCS, HTLC_COUNT, INTERCHAIN_ACCOUNTS, INVENTORY,
+        LAST_PAYOUT_ID, NOTIFICATIONS, OPEN_DEALS, PAUSED, PAYOUT_TRANSFERS, QUOTE_KEYS,
+        QUOTE_NONCES, SENDING_PAYOUTS, STATE, TRADERS,
+    };
+    use cosmwasm_std::{
+        Addr, BankMsg, Coin, Event, HexBinary, IbcMsg, Order, StdError, Storage, SubMsg, Timestamp,
+    };
+    use otc_ibc::ack::{AckResult, Payout};
+    use otc_ibc::htlc::HASHLOCK_LEN;
+    use otc_ibc::{proto, IbcHostMsg};
 
     use super::*;
 
     // Enables anyone to submit an OTC deal where coin_a belongs to seller and coin_b belongs to buyer.
     pub fn create_deal(
         deps: DepsMut,
-        _env: Env,
-        _info: MessageInfo,
-        seller: String,
-        buyer: String,
-        coin_a: Coin,
-        coin_b: Coin,
-        expiry: u64,
+        env: Env,
+        info: MessageInfo,
+        terms: DealTerms,
     ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::NewDeals)?;
+        for party in [&terms.seller, &terms.buyer] {
+            traders::assert_allowed(deps.as_ref(), party)?;
+            screening::assert_not_flagged(deps.as_ref(), party)?;
+        }
+        match &terms.broker {
+            Some(broker) => {
+                deps.api.addr_validate(broker)?;
+            }
+            None if terms.broker_fee_bps > 0 => {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "A broker fee needs a broker",
+                )));
+            }
+            None => {}
+        }
+        if terms.broker_fee_bps > 10_000 {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Broker fee cannot exceed 10000 bps",
+            )));
+        }
+        if let Some(spread_bps) = terms.peg_spread_bps {
+            if spread_bps.unsigned_abs() >= 10_000 {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "Spread must be under 10000 bps",
+                )));
+            }
+            let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+            if config.price_oracle.is_none() {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "Pegged deals need a price oracle",
+                )));
+            }
+        }
+        let arbitrator = terms
+            .arbitrator
+            .map(|arbitrator| deps.api.addr_validate(&arbitrator))
+            .transpose()?
+            .map(Addr::into_string);
+        if arbitrator
+            .as_ref()
+            .is_some_and(|arbitrator| *arbitrator == terms.seller || *arbitrator == terms.buyer)
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "A party cannot arbitrate its own deal",
+            )));
+        }
+
+        // Legs staying on their home chain need an interchain account there,
+        // and an owner reached over that chain to lock them
+        for (leg, channel) in [
+            (&terms.seller_leg, &terms.seller_channel),
+            (&terms.buyer_leg, &terms.buyer_channel),
+        ] {
+            let Some(leg) = leg else {
+                continue;
+            };
+            if !INTERCHAIN_ACCOUNTS.has(deps.storage, &leg.connection_id) {
+                return Err(ContractError::Std(StdError::generic_err(format!(
+                    "No interchain account on {}",
+                    leg.connection_id
+                ))));
+            }
+            let connection_id = match channel {
+                Some(channel) => CHANNELS
+                    .may_load(deps.storage, channel)?
+                    .map(|channel| channel.connection_id),
+                None => None,
+            };
+            if connection_id.as_ref() != Some(&leg.connection_id) {
+                return Err(ContractError::Std(StdError::generic_err(format!(
+                    "The owner of a leg on {} must be reached over it",
+                    leg.connection_id
+                ))));
+            }
+        }
+
+        // An off-market price binds both parties only once the other one accepts
+        // it, unless an operator set it
+        let awaiting_acceptance = if terms.off_market
+            && !ROLES.has(deps.storage, (Role::Operator.as_str(), &info.sender))
+        {
+            if info.sender == terms.seller {
+                Some(terms.buyer.clone())
+            } else if info.sender == terms.buyer {
+                Some(terms.seller.clone())
+            } else {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "Only a party or an operator can create an off-market deal",
+                )));
+            }
+        } else {
+            None
+        };
+
+        let approval = approvals::policy(
+            deps.as_ref(),
+            [&terms.seller, &terms.buyer],
+            [&terms.coin_a, &terms.coin_b],
+            terms.approvers,
+            terms.approvals_required,
+        )?;
+
         let deal = Deal {
-            seller,
-            buyer,
-            coin_a,
-            coin_b,
-            expiry,
+            seller: terms.seller,
+            buyer: terms.buyer,
+            coin_a: terms.coin_a,
+            coin_b: terms.coin_b,
+            expiry: terms.expiry,
             finished: false,
             seller_deposited: false,
             buyer_deposited: false,
             seller_withdrew: false,
             buyer_withdrew: false,
-            channel_id_recieved_a: "".to_string(),
-            channel_id_recieved_b: "".to_string(),
+            channel_id_recieved_a: terms.seller_channel.unwrap_or_default(),
+            channel_id_recieved_b: terms.buyer_channel.unwrap_or_default(),
+            seller_leg: terms.seller_leg,
+            buyer_leg: terms.buyer_leg,
+            seller_route: None,
+            buyer_route: None,
+            awaiting_acceptance,
+            cancelled: false,
+            funding_check: terms.funding_check,
+            creator: info.sender.into_string(),
+            broker: terms.broker,
+            broker_fee_bps: terms.broker_fee_bps,
+            arbitrator,
+            dispute: None,
+            approvers: approval.approvers,
+            approver_group: approval.group,
+            approvals_required: approval.required,
+            group_approvals_required: approval.group_required,
+            approvals: vec![],
+            off_market: terms.off_market,
+            peg: terms.peg_spread_bps.map(|spread_bps| Peg {
+                spread_bps,
+                price: None,
+            }),
         };
+        save_deal(deps, &env, deal)
+    }
 
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.deals.push(deal);
-            Ok(state)
-        })?;
+    // Enables a party on another chain to propose a deal, which the other party has to accept.
+    pub fn create_deal_ibc(
+        deps: DepsMut,
+        env: Env,
+        channel: String,
+        sender: String,
+        proposal: DealProposal,
+    ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::NewDeals)?;
+        for party in [&proposal.seller, &proposal.buyer] {
+            traders::assert_allowed(deps.as_ref(), party)?;
+            screening::assert_not_flagged(deps.as_ref(), party)?;
+        }
+        // The proposer acts over the channel it proposed on
+        let (seller_channel, buyer_channel, counterparty) = if sender == proposal.seller {
+            (
+                Some(channel),
+                proposal.counterparty_channel,
+                proposal.buyer.clone(),
+            )
+        } else if sender == proposal.buyer {
+            (
+                proposal.counterparty_channel,
+                Some(channel),
+                proposal.seller.clone(),
+            )
+        } else {
+            return Err(ContractError::Unauthorized {});
+        };
+        let approval = approvals::policy(
+            deps.as_ref(),
+            [&proposal.seller, &proposal.buyer],
+            [&proposal.coin_a, &proposal.coin_b],
+            vec![],
+            0,
+        )?;
+
+        let deal = Deal {
+            seller: proposal.seller,
+            buyer: proposal.buyer,
+            coin_a: proposal.coin_a,
+            coin_b: proposal.coin_b,
+            expiry: proposal.expiry,
+            finished: false,
+            seller_deposited: false,
+            buyer_deposited: false,
+            seller_withdrew: false,
+            buyer_withdrew: false,
+            channel_id_recieved_a: seller_channel.unwrap_or_default(),
+            channel_id_recieved_b: buyer_channel.unwrap_or_default(),
+            seller_leg: None,
+            buyer_leg: None,
+            seller_route: None,
+            buyer_route: None,
+            awaiting_acceptance: Some(counterparty),
+            cancelled: false,
+            funding_check: false,
+            creator: sender,
+            broker: None,
+            broker_fee_bps: 0,
+            arbitrator: None,
+            dispute: None,
+            approvers: vec![],
+            approver_group: approval.group,
+            approvals_required: approval.required,
+            group_approvals_required: approval.group_required,
+            approvals: vec![],
+            off_market: false,
+            peg: None,
+        };
+        save_deal(deps, &env, deal)
+    }
+
+    // Executed by user on contract hosting chain to accept a deal proposed to them.
+    pub fn accept_deal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        accept(deps, env, deal_id, info.sender.as_str())
+    }
+
+    // Executed by user on another chain to accept a deal proposed to them.
+    pub fn accept_deal_ibc(
+        deps: DepsMut,
+        env: Env,
+        channel: String,
+        deal_id: u64,
+        sender: String,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        assert_bound_to_channel(&deal, &sender, &channel)?;
+        accept(deps, env, deal_id, &sender)
+    }
+
+    // Executed by user on contract hosting chain to call off a deal nobody funded yet.
+    pub fn cancel_deal(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        cancel(deps, env, deal_id, info.sender.as_str())
+    }
 
-        Ok(Response::new().add_attribute("method", "created_deal"))
+    // Executed by user on another chain to call off a deal nobody funded yet.
+    pub fn cancel_deal_ibc(
+        deps: DepsMut,
+        env: Env,
+        channel: String,
+        deal_id: u64,
+        sender: String,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        assert_bound_to_channel(&deal, &sender, &channel)?;
+        cancel(deps, env, deal_id, &sender)
     }
 
     // Enables the user on contract hosting chain to deposit the funds.
-    pub fn deposit(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    pub fn deposit(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::Deposits)?;
+        traders::assert_allowed(deps.as_ref(), info.sender.as_str())?;
+        screening::assert_not_flagged(deps.as_ref(), info.sender.as_str())?;
+        let [deposit] = info.funds.as_slice() else {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Exactly one coin must be deposited",
+            )));
+        };
         let depositor = info.sender.clone().into_string();
-        let state = STATE.load(deps.storage)?;
-
-        // Find the index of deal matching with incoming deposit
-        let deal_found = find_deal_with_denom(
-            state.clone(),
-            depositor.clone(),
-            info.funds[0].denom.clone(),
-        );
-
-        // Get the deal if found
-        if deal_found.is_some() {
-            let deal_index = deal_found.unwrap();
-            let deal = state.deals[deal_index].clone();
-
-            // Deal expired and finished check
-            deal_expired_or_finished(deal.clone(), env)?;
-
-            // Deposit funds for seller
-            if info.sender == deal.seller {
-                STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                    if state.deals[deal_index].coin_a.amount == info.funds[0].amount {
-                        state.deals[deal_index].seller_deposited = true;
-                    } else {
-                        return Err(ContractError::Std(StdError::generic_err(
-                            "Incorrect amount deposited",
-                        )));
-                    }
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        limits::assert_deposit_allowed(deps.storage, &config, deposit)?;
+
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+
+        // Deal expired and finished check
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+        assert_accepted(&deal)?;
+        assert_funding_checked(deps.storage, deal_id, &deal)?;
+        assert_escrowed_here(&deal, &depositor)?;
+
+        // Deposit funds for seller
+        if info.sender == deal.seller {
+            update_deal(deps.storage, env.block.time, deal_id, |deal| {
+                if deal.coin_a == *deposit {
+                    deal.seller_deposited = true;
+                } else {
+                    return Err(ContractError::Std(StdError::generic_err(
+                        "Incorrect coin deposited",
+                    )));
+                }
 
-                    Ok(state)
-                })?;
-            // Deposit funds for buyer
-            } else if info.sender == deal.buyer {
-                STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                    if state.deals[deal_index].coin_b.amount == info.funds[0].amount {
-                        state.deals[deal_index].buyer_deposited = true;
-                    } else {
-                        return Err(ContractError::Std(StdError::generic_err(
-                            "Incorrect amount deposited",
-                        )));
-                    }
-                    Ok(state)
-                })?;
-            } else {
-                return Err(ContractError::Unauthorized {});
-            }
+                Ok(())
+            })?;
+        // Deposit funds for buyer
+        } else if info.sender == deal.buyer {
+            update_deal(deps.storage, env.block.time, deal_id, |deal| {
+                if deal.coin_b == *deposit {
+                    deal.buyer_deposited = true;
+                } else {
+                    return Err(ContractError::Std(StdError::generic_err(
+                        "Incorrect coin deposited",
+                    )));
+                }
+                Ok(())
+            })?;
         } else {
-            return Err(ContractError::Std(StdError::generic_err("No deal found")));
+            return Err(ContractError::Unauthorized {});
         }
-        Ok(Response::new().add_attribute("method", "deposited"))
+
+        let packets = notify_funded(deps.storage, &env, deal_id, &deal, &depositor)?;
+        let credited = Some(deposit.clone());
+        let data = deal_result(deps.storage, &env, deal_id, credited, None)?;
+        Ok(Response::new()
+            .add_attribute("method", "deposited")
+            .set_data(data)
+            .add_messages(packets))
     }
 
     // Executed by user on contract hosting chain to complete the deal.
     pub fn complete_deal(
-        deps: DepsMut,
+        mut deps: DepsMut,
         env: Env,
         info: MessageInfo,
+        deal_id: u64,
     ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::Settlements)?;
         let withdrawer = info.sender.clone();
-        let state = STATE.load(deps.storage)?;
 
-        // Find the deal
-        let deal_found_index = find_deal(state.clone(), withdrawer.clone().into_string());
-        let deal_found = if deal_found_index.is_some() {
-            Some(state.deals[deal_found_index.unwrap()].clone())
-        } else {
-            None
-        };
-
-        let deal_coin;
-        let deal;
-        let mut seller_withdrew = state.deals[deal_found_index.unwrap()].seller_withdrew;
-        let mut buyer_withdrew = state.deals[deal_found_index.unwrap()].buyer_withdrew;
-
-        if deal_found.is_some() {
-            deal = deal_found.unwrap();
-
-            deal_expired_or_finished(deal.clone(), env.clone())?;
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        let mut seller_withdrew = deal.seller_withdrew;
+        let mut buyer_withdrew = deal.buyer_withdrew;
+
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+        assert_not_disputed(&deal)?;
+        approvals::assert_approved(deps.as_ref(), &deal)?;
+        // Checked once, so the second party is never kept from its leg
+        if !deal.seller_withdrew && !deal.buyer_withdrew {
+            let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+            oracle::assert_price_in_band(deps.as_ref(), &config, &deal)?;
+        }
 
-            // Check if both parties have deposited
-            if !deal.seller_deposited || !deal.buyer_deposited {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "Both parties must deposit first",
-                )));
-            }
+        // Check if both parties have deposited
+        if !deal.seller_deposited || !deal.buyer_deposited {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Both parties must deposit first",
+            )));
+        }
+        if info.sender != deal.seller && info.sender != deal.buyer {
+            return Err(ContractError::Std(StdError::generic_err(
+                "No deposit found",
+            )));
+        }
 
-            deal_coin = if info.sender == deal.seller {
-                seller_withdrew = true;
+        // Fixed only once the payout is sure to go ahead
+        let state = STATE.load(deps.storage)?;
+        let (state, peg_refund) = fix_pegged_price(deps.branch(), &env, state, deal_id)?;
+        let deal = state.deal(deal_id)?.clone();
 
-                deal.coin_b
-            } else if info.sender == deal.buyer {
-                buyer_withdrew = true;
+        let deal_coin = if info.sender == deal.seller {
+            seller_withdrew = true;
 
-                deal.coin_a
-            } else {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "No deposit found",
-                )));
-            };
+            deal.coin_b.clone()
         } else {
-            return Err(ContractError::Std(StdError::generic_err("No deal found")));
-        }
+            buyer_withdrew = true;
 
-        let msg = CosmosMsg::Bank(BankMsg::Send {
-            to_address: withdrawer.into_string(),
-            amount: vec![deal_coin],
-        });
+            deal.coin_a.clone()
+        };
 
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.deals[deal_found_index.unwrap()].seller_withdrew = seller_withdrew;
-            state.deals[deal_found_index.unwrap()].buyer_withdrew = buyer_withdrew;
+        // The withdrawer receives the other party's leg
+        let paid_side = if withdrawer == deal.seller {
+            Side::Buyer
+        } else {
+            Side::Seller
+        };
+        let charges = fees::charge(
+            deps.storage,
+            env.block.time,
+            &deal,
+            paid_side,
+            withdrawer.as_str(),
+            &deal_coin,
+        )?;
+        let deal_coin = charges.net;
+        let payout = next_payout(deps.storage, deal_coin.clone())?;
+        let msg = pay_out_leg(
+            deps.branch(),
+            &env,
+            &deal,
+            IcaMemo {
+                deal_id,
+                side: paid_side,
+                action: IcaAction::Payout,
+            },
+            withdrawer.as_str(),
+            deal_coin,
+        )?;
+
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_withdrew = seller_withdrew;
+            deal.buyer_withdrew = buyer_withdrew;
 
             // Mark deal as finished if both parties withdrew
             if seller_withdrew && buyer_withdrew {
-                state.deals[deal_found_index.unwrap()].finished = true;
+                deal.finished = true;
             }
-            Ok(state)
+            Ok(())
         })?;
 
-        Ok(Response::default().add_message(msg))
+        let packets = if seller_withdrew && buyer_withdrew {
+            notify_settled(deps.storage, &env, &state, deal_id)?
+        } else {
+            vec![]
+        };
+
+        Ok(Response::default()
+            .add_attribute("payout_id", payout.id.to_string())
+            .add_attribute("fee", charges.fee.to_string())
+            .add_attribute("broker_fee", charges.broker_fee.to_string())
+            .set_data(deal_result(
+                deps.storage,
+                &env,
+                deal_id,
+                None,
+                Some(payout),
+            )?)
+            .add_submessage(msg)
+            .add_messages(charges.messages)
+            .add_submessages(peg_refund)
+            .add_messages(packets))
     }
 
     // Executed by user on contract hosting chain to withdraw the funds if deal is not completed.
     // Or other party has not deposited.
     pub fn withdraw(
-        deps: DepsMut,
-        _env: Env,
+        mut deps: DepsMut,
+        env: Env,
         info: MessageInfo,
+        deal_id: u64,
     ) -> Result<Response, ContractError> {
         let withdrawer = info.sender.clone();
-        let state = STATE.load(deps.storage)?;
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+
+        let mut seller_deposited = deal.seller_deposited;
+        let mut buyer_deposited = deal.buyer_deposited;
+
+        // A cancelled deal can still hold a remote leg whose refund failed
+        if deal.finished && !deal.cancelled {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already finished.",
+            )));
+        }
+        assert_not_disputed(&deal)?;
+
+        // Don't allow withdrawal if both parties have deposited, unless the
+        // deal can no longer settle at its price
+        if deal.seller_deposited
+            && deal.buyer_deposited
+            && !deal.cancelled
+            && !stuck_outside_band(deps.as_ref(), &deal)?
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Refund not allowed as both parties have deposited",
+            )));
+        }
 
-        // Find the deal
-        let deal_found_index = find_deal(state.clone(), withdrawer.clone().into_string());
-        let deal_found = if deal_found_index.is_some() {
-            Some(state.deals[deal_found_index.unwrap()].clone())
+        // Allow a party that has deposited to withdraw if not already withdrawn
+        // Mark party deposit as false
+        let (deal_coin, refunded_side) = if withdrawer == deal.seller && deal.seller_deposited {
+            seller_deposited = false;
+            (deal.coin_a.clone(), Side::Seller)
+        } else if withdrawer == deal.buyer && deal.buyer_deposited {
+            buyer_deposited = false;
+            (deal.coin_b.clone(), Side::Buyer)
+        } else if deal.seller_deposited || deal.buyer_deposited {
+            return Err(ContractError::Unauthorized {});
         } else {
-            None
+            return Err(ContractError::Std(StdError::generic_err(
+                "No deposit found",
+            )));
         };
 
-        let deal_coin;
+        // Update deal with deposit status
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_deposited = seller_deposited;
+            deal.buyer_deposited = buyer_deposited;
 
-        let mut seller_deposited = state.deals[deal_found_index.unwrap()].seller_deposited;
-        let mut buyer_deposited = state.deals[deal_found_index.unwrap()].buyer_deposited;
+            Ok(())
+        })?;
 
-        if deal_found.is_some() {
-            let deal = deal_found.unwrap();
+        let payout = next_payout(deps.storage, deal_coin.clone())?;
+        let msg = pay_out_leg(
+            deps.branch(),
+            &env,
+            &deal,
+            IcaMemo {
+                deal_id,
+                side: refunded_side,
+                action: IcaAction::Refund,
+            },
+            withdrawer.as_str(),
+            deal_coin,
+        )?;
+
+        Ok(Response::default()
+            .add_attribute("payout_id", payout.id.to_string())
+            .set_data(deal_result(
+                deps.storage,
+                &env,
+                deal_id,
+                None,
+                Some(payout),
+            )?)
+            .add_submessage(msg))
+    }
 
-            if deal.finished {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "Deal has already finished.",
-                )));
-            }
+    // Enables the admin to reset the deals for testing purposes.
+    pub fn reset(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
 
-            // Don't allow withdrawal if both parties have deposited
-            if deal.seller_deposited && deal.buyer_deposited {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "Refund not allowed as both parties have deposited",
-                )));
-            }
+        // Dropping a deal that still escrows a leg would strand the funds
+        let state = STATE.load(deps.storage)?;
+        if let Some(index) = state
+            .deals
+            .iter()
+            .position(|deal| leg_locked(deal, true) || leg_locked(deal, false))
+        {
+            return Err(ContractError::Std(StdError::generic_err(format!(
+                "Deal {} still holds deposits, force a refund or settlement first",
+                state.first_deal_id + index as u64
+            ))));
+        }
 
-            // If only one party has deposited, allow that party to withdraw if not already withdrawn
-            // Mark party deposit as false
-            deal_coin = if deal.seller_deposited && !deal.buyer_deposited {
-                if withdrawer == deal.seller {
-                    seller_deposited = false;
-                    deal.coin_a
-                } else {
-                    return Err(ContractError::Unauthorized {});
-                }
-            } else if deal.buyer_deposited && !deal.seller_deposited {
-                if withdrawer == deal.buyer {
-                    buyer_deposited = false;
-                    deal.coin_b
-                } else {
-                    return Err(ContractError::Unauthorized {});
-                }
-            } else {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "No deposit found",
-                )));
-            };
+        // Ids are not handed out again, as packets about the dropped deals
+        // may still be acknowledged
+        STATE.save(
+            deps.storage,
+            &State {
+                deals: vec![],
+                first_deal_id: state.next_deal_id(),
+            },
+        )?;
+        OPEN_DEALS.clear(deps.storage);
 
-            // Update deal with deposit status
-            STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                state.deals[deal_found_index.unwrap()].seller_deposited = seller_deposited;
-                state.deals[deal_found_index.unwrap()].buyer_deposited = buyer_deposited;
+        Ok(Response::new().add_attribute("method", "reset"))
+    }
 
-                Ok(state)
-            })?;
-        } else {
-            return Err(ContractError::Std(StdError::generic_err("No deal found")));
+    // Enables the admin to return the escrowed legs of a deal to their
+    // depositors and close it.
+    pub fn force_refund(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Operator, &info.sender)?;
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        assert_not_disputed(&deal)?;
+        if deal.finished {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already finished.",
+            )));
+        }
+        // Once a leg is paid out the other one can only be settled
+        if deal.seller_withdrew || deal.buyer_withdrew {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal is partly settled, force a settlement instead",
+            )));
         }
 
-        let msg = BankMsg::Send {
-            to_address: withdrawer.into_string(),
-            amount: vec![deal_coin],
-        };
+        let mut audit = forced_action_event("force_refund", deal_id, &info.sender);
+        let mut messages = vec![];
+        for (side, deposited, party, coin) in [
+            (
+                Side::Seller,
+                deal.seller_deposited,
+                &deal.seller,
+                &deal.coin_a,
+            ),
+            (Side::Buyer, deal.buyer_deposited, &deal.buyer, &deal.coin_b),
+        ] {
+            if !deposited {
+                continue;
+            }
+            let payout = next_payout(deps.storage, coin.clone())?;
+            messages.push(force_payout(
+                deps.branch(),
+                &env,
+                &deal,
+                deal_id,
+                side,
+                party,
+                coin.clone(),
+            )?);
+            audit = audit
+                .add_attribute("refunded", format!("{} {}", party, coin))
+                .add_attribute("payout_id", payout.id.to_string());
+        }
 
-        Ok(Response::default().add_message(msg))
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_deposited = false;
+            deal.buyer_deposited = false;
+            deal.finished = true;
+            deal.cancelled = true;
+            Ok(())
+        })?;
+        let packets = notify(
+            deps.storage,
+            &env,
+            &[&deal.channel_id_recieved_a, &deal.channel_id_recieved_b],
+            &IbcHostMsg::Cancelled { deal_id },
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "force_refund")
+            .add_event(audit)
+            .add_submessages(messages)
+            .add_messages(packets))
     }
 
-    // Enables the admin to reset the deals for testing purposes.
-    pub fn reset(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, ContractError> {
-        let res = ADMIN.assert_admin(deps.as_ref(), &info.sender.clone()); // Check if admin
-
-        if res.is_err() {
-            return Err(ContractError::Unauthorized {});
+    // Enables the admin to pay both legs of a funded deal to their recipients,
+    // whatever the expiry.
+    pub fn force_settle(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Operator, &info.sender)?;
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        assert_not_disputed(&deal)?;
+        approvals::assert_approved(deps.as_ref(), &deal)?;
+        if deal.finished {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already finished.",
+            )));
+        }
+        if !deal.seller_deposited || !deal.buyer_deposited {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Both parties must deposit first",
+            )));
+        }
+        let state = STATE.load(deps.storage)?;
+        let (state, peg_refund) = fix_pegged_price(deps.branch(), &env, state, deal_id)?;
+        let deal = state.deal(deal_id)?.clone();
+
+        let mut audit = forced_action_event("force_settle", deal_id, &info.sender);
+        let mut messages = peg_refund;
+        // Each party receives the other's leg, unless it already withdrew it
+        for (paid_side, withdrew, party, coin) in [
+            (
+                Side::Buyer,
+                deal.seller_withdrew,
+                &deal.seller,
+                &deal.coin_b,
+            ),
+            (Side::Seller, deal.buyer_withdrew, &deal.buyer, &deal.coin_a),
+        ] {
+            if withdrew {
+                continue;
+            }
+            let charges =
+                fees::charge(deps.storage, env.block.time, &deal, paid_side, party, coin)?;
+            let payout = next_payout(deps.storage, charges.net.clone())?;
+            messages.push(force_payout(
+                deps.branch(),
+                &env,
+                &deal,
+                deal_id,
+                paid_side,
+                party,
+                charges.net.clone(),
+            )?);
+            messages.extend(charges.messages.into_iter().map(SubMsg::new));
+            audit = audit
+                .add_attribute("paid", format!("{} {}", party, charges.net))
+                .add_attribute("payout_id", payout.id.to_string())
+                .add_attribute("fee", charges.fee.to_string())
+                .add_attribute("broker_fee", charges.broker_fee.to_string());
         }
 
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.deals = vec![];
-            Ok(state)
+        let state = update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_withdrew = true;
+            deal.buyer_withdrew = true;
+            deal.finished = true;
+            Ok(())
         })?;
+        let packets = notify_settled(deps.storage, &env, &state, deal_id)?;
 
-        Ok(Response::new().add_attribute("method", "reset"))
+        Ok(Response::new()
+            .add_attribute("method", "force_settle")
+            .add_event(audit)
+            .add_submessages(messages)
+            .add_messages(packets))
     }
 
-    // Enabless the user on another chain to withdraw funds.
-    pub fn withdraw_ibc(
+    // Enables the admin to allow channels from a counterparty port on a connection.
+    pub fn add_channel(
         deps: DepsMut,
-        env: Env,
-        _channel: String,
-        packet: Ics20Packet,
+        info: MessageInfo,
+        connection_id: String,
+        counterparty_port_id: String,
+        transfer_channel: String,
     ) -> Result<Response, ContractError> {
-        let state = STATE.load(deps.storage)?;
-        let deal_found_index =
-            find_deal_with_denom(state.clone(), packet.sender.clone(), packet.denom.clone());
-        let deal_found = if deal_found_index.is_some() {
-            Some(state.deals[deal_found_index.unwrap()].clone())
-        } else {
-            None
-        };
+        assert_role(deps.as_ref(), Role::ChannelManager, &info.sender)?;
+
+        ALLOWED_COUNTERPARTIES.save(
+            deps.storage,
+            (&connection_id, &counterparty_port_id),
+            &transfer_channel,
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "add_channel")
+            .add_attribute("connection_id", connection_id)
+            .add_attribute("counterparty_port_id", counterparty_port_id)
+            .add_attribute("transfer_channel", transfer_channel))
+    }
 
-        let withdrawer = packet.sender.clone();
-        if deal_found.is_some() {
-            let deal = deal_found.unwrap();
+    // Enables the admin to drop a counterparty from the allowlist.
+    // Channels already open with it are rejected on packet receive.
+    pub fn remove_channel(
+        deps: DepsMut,
+        info: MessageInfo,
+        connection_id: String,
+        counterparty_port_id: String,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::ChannelManager, &info.sender)?;
 
-            if deal.finished {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "Deal has already finished.",
-                )));
-            }
+        ALLOWED_COUNTERPARTIES.remove(deps.storage, (&connection_id, &counterparty_port_id));
 
-            // Don't allow withdrawal if both parties have deposited
-            if deal.seller_deposited && deal.buyer_deposited {
+        Ok(Response::new()
+            .add_attribute("method", "remove_channel")
+            .add_attribute("connection_id", connection_id)
+            .add_attribute("counterparty_port_id", counterparty_port_id))
+    }
+
+    // Enables the admin to freeze or unfreeze an open channel.
+    pub fn set_channel_frozen(
+        deps: DepsMut,
+        info: MessageInfo,
+        channel_id: String,
+        frozen: bool,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::ChannelManager, &info.sender)?;
+
+        CHANNELS.update(deps.storage, &channel_id, |channel| match channel {
+            Some(mut channel) => {
+                channel.frozen = frozen;
+                Ok(channel)
+            }
+            None => Err(ContractError::ChannelDisabled {
+                channel_id: channel_id.clone(),
+            }),
+        })?;
+
+        Ok(Response::new()
+            .add_attribute("method", "set_channel_frozen")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("frozen", frozen.to_string()))
+    }
+
+    // Enabless the user on another chain to withdraw funds.
+    pub fn withdraw_ibc(
+        mut deps: DepsMut,
+        env: Env,
+        channel: String,
+        deal_id: u64,
+        packet: Ics20Packet,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+
+        let withdrawer = packet.sender.clone();
+        assert_bound_to_channel(&deal, &withdrawer, &channel)?;
+
+        // A cancelled deal can still hold a remote leg whose refund failed
+        if deal.finished && !deal.cancelled {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already finished.",
+            )));
+        }
+        assert_not_disputed(&deal)?;
+
+        // Don't allow withdrawal if both parties have deposited, unless the
+        // deal can no longer settle at its price
+        if deal.seller_deposited
+            && deal.buyer_deposited
+            && !deal.cancelled
+            && !stuck_outside_band(deps.as_ref(), &deal)?
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Refund not allowed as both parties have deposited",
+            )));
+        }
+
+        let mut seller_deposited = deal.seller_deposited;
+        let mut buyer_deposited = deal.buyer_deposited;
+
+        // Allow a party that has deposited to withdraw if not already withdrawn
+        // Mark party deposit as false
+        let (deal_coin, refunded_side) = if withdrawer == deal.seller && deal.seller_deposited {
+            seller_deposited = false;
+            (deal.coin_a.clone(), Side::Seller)
+        } else if withdrawer == deal.buyer && deal.buyer_deposited {
+            buyer_deposited = false;
+            (deal.coin_b.clone(), Side::Buyer)
+        } else if deal.seller_deposited || deal.buyer_deposited {
+            return Err(ContractError::Unauthorized {});
+        } else {
+            return Err(ContractError::Std(StdError::generic_err(
+                "No deposit found",
+            )));
+        };
+
+        // Update deal with deposit status
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_deposited = seller_deposited;
+            deal.buyer_deposited = buyer_deposited;
+
+            Ok(())
+        })?;
+
+        let payout = next_payout(deps.storage, deal_coin.clone())?;
+        let msg = pay_out_leg(
+            deps.branch(),
+            &env,
+            &deal,
+            IcaMemo {
+                deal_id,
+                side: refunded_side,
+                action: IcaAction::Refund,
+            },
+            &withdrawer,
+            deal_coin,
+        )?;
+
+        Ok(Response::default()
+            .add_attribute("payout_id", payout.id.to_string())
+            .set_data(deal_result(
+                deps.storage,
+                &env,
+                deal_id,
+                None,
+                Some(payout),
+            )?)
+            .add_submessage(msg))
+    }
+
+    // Enables the user on another chain to deposit funds. Their agent sends
+    // the coin over ICS-20 and ibc-hooks calls this as it arrives, so the leg
+    // is only credited with coins that are actually here.
+    pub fn deposit_ibc(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+        sender: String,
+    ) -> Result<Response, ContractError> {
+        let channel = hook_channel(deps.as_ref(), &info.sender)?;
+        validate_channel_enabled(deps.as_ref(), &channel)?;
+        assert_not_paused(deps.storage, PauseScope::IbcReceive)?;
+        assert_not_paused(deps.storage, PauseScope::Deposits)?;
+        let [deposit] = info.funds.as_slice() else {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Exactly one coin must be deposited",
+            )));
+        };
+        traders::assert_allowed(deps.as_ref(), &sender)?;
+        screening::assert_not_flagged(deps.as_ref(), &sender)?;
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        limits::assert_deposit_allowed(deps.storage, &config, deposit)?;
+
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        assert_bound_to_channel(&deal, &sender, &channel)?;
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+        assert_accepted(&deal)?;
+        assert_funding_checked(deps.storage, deal_id, &deal)?;
+        assert_escrowed_here(&deal, &sender)?;
+
+        // Deposit funds for seller
+        if sender == deal.seller {
+            update_deal(deps.storage, env.block.time, deal_id, |deal| {
+                if deal.coin_a == *deposit {
+                    deal.seller_deposited = true;
+                } else {
+                    return Err(ContractError::Std(StdError::generic_err(
+                        "Incorrect coin deposited",
+                    )));
+                }
+
+                Ok(())
+            })?;
+        // Deposit funds for buyer
+        } else if sender == deal.buyer {
+            update_deal(deps.storage, env.block.time, deal_id, |deal| {
+                if deal.coin_b == *deposit {
+                    deal.buyer_deposited = true;
+                } else {
+                    return Err(ContractError::Std(StdError::generic_err(
+                        "Incorrect coin deposited",
+                    )));
+                }
+                Ok(())
+            })?;
+        } else {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        let packets = notify_funded(deps.storage, &env, deal_id, &deal, &sender)?;
+        let data = deal_result(deps.storage, &env, deal_id, Some(deposit.clone()), None)?;
+        Ok(Response::new()
+            .add_attribute("method", "deposit_ibc")
+            .add_attribute("channel_id", channel)
+            .set_data(data)
+            .add_messages(packets))
+    }
+
+    // Executed by user on another chain to complete the deal.
+    pub fn deal_complete_ibc(
+        mut deps: DepsMut,
+        env: Env,
+        channel: String,
+        deal_id: u64,
+        packet: Ics20Packet,
+    ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::Settlements)?;
+        let withdrawer = packet.sender.clone();
+
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        let mut seller_withdrew = deal.seller_withdrew;
+        let mut buyer_withdrew = deal.buyer_withdrew;
+
+        assert_bound_to_channel(&deal, &withdrawer, &channel)?;
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+        assert_not_disputed(&deal)?;
+        approvals::assert_approved(deps.as_ref(), &deal)?;
+        // Checked once, so the second party is never kept from its leg
+        if !deal.seller_withdrew && !deal.buyer_withdrew {
+            let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+            oracle::assert_price_in_band(deps.as_ref(), &config, &deal)?;
+        }
+
+        // Check if both parties have deposited
+        if !deal.seller_deposited || !deal.buyer_deposited {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Both parties must deposit first",
+            )));
+        }
+
+        // Fixed only once the payout is sure to go ahead
+        let state = STATE.load(deps.storage)?;
+        let (state, peg_refund) = fix_pegged_price(deps.branch(), &env, state, deal_id)?;
+        let deal = state.deal(deal_id)?.clone();
+
+        let deal_coin = if withdrawer == deal.seller {
+            seller_withdrew = true;
+            deal.coin_b.clone()
+        } else {
+            buyer_withdrew = true;
+            deal.coin_a.clone()
+        };
+
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_withdrew = seller_withdrew;
+            deal.buyer_withdrew = buyer_withdrew;
+
+            // Mark deal as finished if both parties withdrew
+            if seller_withdrew && buyer_withdrew {
+                deal.finished = true;
+            }
+            Ok(())
+        })?;
+
+        // The withdrawer receives the other party's leg
+        let paid_side = if withdrawer == deal.seller {
+            Side::Buyer
+        } else {
+            Side::Seller
+        };
+        let charges = fees::charge(
+            deps.storage,
+            env.block.time,
+            &deal,
+            paid_side,
+            withdrawer.as_str(),
+            &deal_coin,
+        )?;
+        let deal_coin = charges.net;
+        let payout = next_payout(deps.storage, deal_coin.clone())?;
+        let msg = pay_out_leg(
+            deps.branch(),
+            &env,
+            &deal,
+            IcaMemo {
+                deal_id,
+                side: paid_side,
+                action: IcaAction::Payout,
+            },
+            &withdrawer,
+            deal_coin,
+        )?;
+
+        let packets = if seller_withdrew && buyer_withdrew {
+            notify_settled(deps.storage, &env, &state, deal_id)?
+        } else {
+            vec![]
+        };
+
+        Ok(Response::default()
+            .add_attribute("payout_id", payout.id.to_string())
+            .add_attribute("fee", charges.fee.to_string())
+            .add_attribute("broker_fee", charges.broker_fee.to_string())
+            .set_data(deal_result(
+                deps.storage,
+                &env,
+                deal_id,
+                None,
+                Some(payout),
+            )?)
+            .add_submessage(msg)
+            .add_messages(charges.messages)
+            .add_submessages(peg_refund)
+            .add_messages(packets))
+    }
+
+    pub fn update_fees(
+        deps: DepsMut,
+        info: MessageInfo,
+        fee: FeeRate,
+        denom_fees: Vec<DenomFee>,
+        fee_collector: Option<String>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::FeeManager, &info.sender)?;
+        let rates = denom_fees.iter().map(|fee| &fee.rate);
+        if std::iter::once(&fee)
+            .chain(rates)
+            .any(|rate| rate.bps > 10_000)
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Fees cannot exceed 10000 bps",
+            )));
+        }
+        if let Some(collector) = &fee_collector {
+            deps.api.addr_validate(collector)?;
+        }
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                fee,
+                denom_fees,
+                fee_collector,
+                ..config
+            },
+        )?;
+        Ok(Response::new().add_attribute("method", "update_fees"))
+    }
+
+    pub fn update_fee_tiers(
+        deps: DepsMut,
+        info: MessageInfo,
+        maker_discount_bps: u16,
+        volume_tiers: Vec<VolumeTier>,
+        volume_window: u64,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::FeeManager, &info.sender)?;
+        let discounts = volume_tiers.iter().map(|tier| tier.discount_bps);
+        if std::iter::once(maker_discount_bps)
+            .chain(discounts)
+            .any(|bps| bps > 10_000)
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Discounts cannot exceed 10000 bps",
+            )));
+        }
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                maker_discount_bps,
+                volume_tiers,
+                volume_window,
+                ..config
+            },
+        )?;
+        Ok(Response::new().add_attribute("method", "update_fee_tiers"))
+    }
+
+    // Grants an account a negotiated fee discount, or removes it.
+    pub fn set_fee_discount(
+        deps: DepsMut,
+        info: MessageInfo,
+        address: String,
+        discount_bps: Option<u16>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::FeeManager, &info.sender)?;
+        match discount_bps {
+            Some(bps) if bps > 10_000 => {
                 return Err(ContractError::Std(StdError::generic_err(
-                    "Refund not allowed as both parties have deposited",
+                    "Discounts cannot exceed 10000 bps",
                 )));
             }
+            Some(bps) => ACCOUNT_DISCOUNTS.save(deps.storage, &address, &bps)?,
+            None => ACCOUNT_DISCOUNTS.remove(deps.storage, &address),
+        }
+        Ok(Response::new()
+            .add_attribute("method", "set_fee_discount")
+            .add_attribute("address", address))
+    }
+
+    // Pays out the fees accrued while no fee collector was set.
+    pub fn claim_fees(
+        deps: DepsMut,
+        info: MessageInfo,
+        recipient: Option<String>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::FeeManager, &info.sender)?;
+        let recipient = match recipient {
+            Some(recipient) => deps.api.addr_validate(&recipient)?,
+            None => info.sender,
+        };
+
+        let fees = ACCRUED_FEES
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+            .collect::<StdResult<Vec<_>>>()?;
+        if fees.is_empty() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "No fees to claim",
+            )));
+        }
+        ACCRUED_FEES.clear(deps.storage);
+
+        Ok(Response::new()
+            .add_attribute("method", "claim_fees")
+            .add_attribute("recipient", recipient.to_string())
+            .add_message(BankMsg::Send {
+                to_address: recipient.into_string(),
+                amount: fees,
+            }))
+    }
+
+    // Stops or resumes parts of the contract in an emergency.
+    pub fn set_paused(
+        deps: DepsMut,
+        info: MessageInfo,
+        scopes: Vec<PauseScope>,
+        paused: bool,
+    ) -> Result<Response, ContractError> {
+        let guardian = GUARDIAN
+            .is_admin(deps.as_ref(), &info.sender)
+            .unwrap_or_default();
+        if !guardian {
+            assert_role(deps.as_ref(), Role::Operator, &info.sender)?;
+        }
+
+        let mut current = PAUSED.may_load(deps.storage)?.unwrap_or_default();
+        current.retain(|scope| !scopes.contains(scope));
+        if paused {
+            current.extend(scopes.iter().copied());
+        }
+        PAUSED.save(deps.storage, &current)?;
+
+        let scopes: Vec<_> = scopes.iter().map(|scope| scope.as_str()).collect();
+        Ok(Response::new()
+            .add_attribute("method", if paused { "pause" } else { "unpause" })
+            .add_attribute("scopes", scopes.join(",")))
+    }
+
+    pub fn set_guardian(
+        deps: DepsMut,
+        info: MessageInfo,
+        guardian: Option<String>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        let guardian = guardian
+            .map(|guardian| deps.api.addr_validate(&guardian))
+            .transpose()?;
+        GUARDIAN.set(deps, guardian.clone())?;
+        Ok(Response::new()
+            .add_attribute("method", "set_guardian")
+            .add_attribute(
+                "guardian",
+                guardian.map(Addr::into_string).unwrap_or_default(),
+            ))
+    }
+
+    pub fn raise_dispute(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+        reason: String,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        if info.sender != deal.seller && info.sender != deal.buyer {
+            return Err(ContractError::Unauthorized {});
+        }
+        if deal.arbitrator.is_none() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has no arbitrator",
+            )));
+        }
+        if deal.dispute.is_some() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already been disputed",
+            )));
+        }
+        // Only a fully funded deal that nobody has been paid from can be split
+        if deal.finished
+            || !deal.seller_deposited
+            || !deal.buyer_deposited
+            || deal.seller_withdrew
+            || deal.buyer_withdrew
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Only funded deals can be disputed before settlement",
+            )));
+        }
+
+        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+            state.deal_mut(deal_id)?.dispute = Some(Dispute {
+                raised_by: info.sender.to_string(),
+                reason: reason.clone(),
+                raised_at: env.block.time,
+                ruling: None,
+            });
+            Ok(state)
+        })?;
+
+        Ok(Response::new()
+            .add_attribute("method", "raise_dispute")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_attribute("raised_by", info.sender)
+            .add_attribute("reason", reason))
+    }
+
+    // Pays out a disputed deal as its arbitrator ruled. Operators can rule in
+    // the arbitrator's place.
+    pub fn resolve(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        deal_id: u64,
+        seller_gets: Vec<Coin>,
+        buyer_gets: Vec<Coin>,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        if deal.arbitrator.as_deref() != Some(info.sender.as_str()) {
+            assert_role(deps.as_ref(), Role::Operator, &info.sender)?;
+        }
+        if !deal.disputed() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has no open dispute",
+            )));
+        }
+
+        // Draw each award from the legs in that denom, seller's first
+        let mut legs = [
+            (Side::Seller, deal.coin_a.clone()),
+            (Side::Buyer, deal.coin_b.clone()),
+        ];
+        let mut messages = vec![];
+        let mut payouts = vec![];
+        for (party, awards) in [(&deal.seller, &seller_gets), (&deal.buyer, &buyer_gets)] {
+            for award in awards {
+                let mut owed = award.amount;
+                for (side, leg) in legs.iter_mut() {
+                    let amount = owed.min(leg.amount);
+                    if leg.denom != award.denom || amount.is_zero() {
+                        continue;
+                    }
+                    leg.amount -= amount;
+                    owed -= amount;
+                    let coin = Coin::new(amount.u128(), &leg.denom);
+                    let payout = next_payout(deps.storage, coin.clone())?;
+                    payouts.push(("payout_id", payout.id.to_string()));
+                    messages.push(force_payout(
+                        deps.branch(),
+                        &env,
+                        &deal,
+                        deal_id,
+                        *side,
+                        party,
+                        coin,
+                    )?);
+                }
+                if !owed.is_zero() {
+                    return Err(ContractError::Std(StdError::generic_err(format!(
+                        "Ruling awards more {} than is escrowed",
+                        award.denom
+                    ))));
+                }
+            }
+        }
+        if legs.iter().any(|(_, leg)| !leg.amount.is_zero()) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Ruling must split all of both legs",
+            )));
+        }
+
+        let state = update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.seller_withdrew = true;
+            deal.buyer_withdrew = true;
+            deal.finished = true;
+            if let Some(dispute) = deal.dispute.as_mut() {
+                dispute.ruling = Some(Ruling {
+                    seller_gets,
+                    buyer_gets,
+                });
+            }
+            Ok(())
+        })?;
+        let packets = notify_settled(deps.storage, &env, &state, deal_id)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "resolve")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_attribute("resolved_by", info.sender)
+            .add_attributes(payouts)
+            .add_submessages(messages)
+            .add_messages(packets))
+    }
+
+    pub fn approve(
+        deps: DepsMut,
+        info: MessageInfo,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        let required = deal.approvals_required + deal.group_approvals_required;
+        if required == 0 {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal needs no approvals",
+            )));
+        }
+        if deal.finished {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already finished.",
+            )));
+        }
+        if !approvals::is_approver(deps.as_ref(), &deal, &info.sender)? {
+            return Err(ContractError::Unauthorized {});
+        }
+        if deal.approvals.contains(&info.sender.to_string()) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal already approved by sender",
+            )));
+        }
+
+        let mut state = STATE.load(deps.storage)?;
+        let approvals = &mut state.deal_mut(deal_id)?.approvals;
+        approvals.push(info.sender.to_string());
+        let count = approvals.len();
+        STATE.save(deps.storage, &state)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "approve")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_attribute("approver", info.sender)
+            .add_attribute("approvals", format!("{}/{}", count, required)))
+    }
+
+    pub fn update_approval_policy(
+        deps: DepsMut,
+        info: MessageInfo,
+        large_deal_thresholds: Vec<Coin>,
+        approver_group: Option<String>,
+        group_approvals_required: u32,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        let approver_group = approver_group
+            .map(|group| deps.api.addr_validate(&group))
+            .transpose()?
+            .map(Addr::into_string);
+        if approver_group.is_some() && group_approvals_required == 0 {
+            return Err(ContractError::Std(StdError::generic_err(
+                "An approver group needs at least one required approval",
+            )));
+        }
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                large_deal_thresholds,
+                approver_group,
+                group_approvals_required,
+                ..config
+            },
+        )?;
+        Ok(Response::new().add_attribute("method", "update_approval_policy"))
+    }
+
+    pub fn update_limits(
+        deps: DepsMut,
+        info: MessageInfo,
+        max_deal_amounts: Vec<Coin>,
+        max_open_deals: u32,
+        max_locked: Vec<Coin>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                max_deal_amounts,
+                max_open_deals,
+                max_locked,
+                ..config
+            },
+        )?;
+        Ok(Response::new().add_attribute("method", "update_limits"))
+    }
+
+    pub fn update_price_oracle(
+        deps: DepsMut,
+        info: MessageInfo,
+        oracle: Option<String>,
+        band_bps: u16,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        let price_oracle = oracle
+            .map(|oracle| deps.api.addr_validate(&oracle))
+            .transpose()?
+            .map(Addr::into_string);
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                price_oracle,
+                price_band_bps: band_bps,
+                ..config
+            },
+        )?;
+        Ok(Response::new().add_attribute("method", "update_price_oracle"))
+    }
+
+    pub fn set_screening(
+        deps: DepsMut,
+        info: MessageInfo,
+        contract: Option<String>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        let screening_contract = contract
+            .map(|contract| deps.api.addr_validate(&contract))
+            .transpose()?
+            .map(Addr::into_string);
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(
+            deps.storage,
+            &Config {
+                screening_contract: screening_contract.clone(),
+                ..config
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("method", "set_screening")
+            .add_attribute("contract", screening_contract.unwrap_or_default()))
+    }
+
+    pub fn set_trader_allowlist(
+        deps: DepsMut,
+        info: MessageInfo,
+        traders: TraderAllowlist,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        if let TraderAllowlist::Group { address } = &traders {
+            deps.api.addr_validate(address)?;
+        }
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        CONFIG.save(deps.storage, &Config { traders, ..config })?;
+        Ok(Response::new().add_attribute("method", "set_trader_allowlist"))
+    }
+
+    // Edits the internal allowlist. Addresses are kept as given, since
+    // remote traders are listed with their own chain's addresses.
+    pub fn update_traders(
+        deps: DepsMut,
+        info: MessageInfo,
+        add: Vec<String>,
+        remove: Vec<String>,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        for address in &add {
+            TRADERS.save(deps.storage, address, &Empty {})?;
+        }
+        for address in &remove {
+            TRADERS.remove(deps.storage, address);
+        }
+        Ok(Response::new()
+            .add_attribute("method", "update_traders")
+            .add_attribute("added", add.len().to_string())
+            .add_attribute("removed", remove.len().to_string()))
+    }
+
+    // Sets coins aside for the maker's signed quotes to be filled from.
+    pub fn deposit_inventory(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::Deposits)?;
+        traders::assert_allowed(deps.as_ref(), info.sender.as_str())?;
+        screening::assert_not_flagged(deps.as_ref(), info.sender.as_str())?;
+        if info.funds.is_empty() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "No coins deposited",
+            )));
+        }
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        for coin in &info.funds {
+            limits::assert_deposit_allowed(deps.storage, &config, coin)?;
+            INVENTORY.update(
+                deps.storage,
+                (&info.sender, &coin.denom),
+                |held| -> StdResult<_> { Ok(held.unwrap_or_default().checked_add(coin.amount)?) },
+            )?;
+            limits::lock(deps.storage, coin)?;
+        }
+        Ok(Response::new()
+            .add_attribute("method", "deposit_inventory")
+            .add_attribute("maker", info.sender))
+    }
+
+    pub fn withdraw_inventory(
+        deps: DepsMut,
+        info: MessageInfo,
+        coins: Vec<Coin>,
+    ) -> Result<Response, ContractError> {
+        for coin in &coins {
+            take_inventory(deps.storage, &info.sender, coin)?;
+        }
+        Ok(Response::new()
+            .add_attribute("method", "withdraw_inventory")
+            .add_attribute("maker", info.sender.to_string())
+            .add_message(BankMsg::Send {
+                to_address: info.sender.into_string(),
+                amount: coins,
+            }))
+    }
+
+    pub fn register_quote_key(
+        deps: DepsMut,
+        info: MessageInfo,
+        pubkey: Binary,
+    ) -> Result<Response, ContractError> {
+        // Compressed or uncompressed SEC1 encoding
+        if !matches!(pubkey.len(), 33 | 65) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Quote key must be a 33 or 65 byte secp256k1 public key",
+            )));
+        }
+        QUOTE_KEYS.save(deps.storage, &info.sender, &pubkey)?;
+        Ok(Response::new()
+            .add_attribute("method", "register_quote_key")
+            .add_attribute("maker", info.sender))
+    }
+
+    pub fn cancel_quote(
+        deps: DepsMut,
+        info: MessageInfo,
+        nonce: u64,
+    ) -> Result<Response, ContractError> {
+        QUOTE_NONCES.save(deps.storage, (&info.sender, nonce), &Empty {})?;
+        Ok(Response::new()
+            .add_attribute("method", "cancel_quote")
+            .add_attribute("maker", info.sender)
+            .add_attribute("nonce", nonce.to_string()))
+    }
+
+    // Creates a deal from a quote its maker signed off-chain, with the maker's
+    // leg taken from their inventory and the taker's leg attached. Both legs
+    // are deposited, so the deal can be completed straight away.
+    pub fn take_quote(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        quote: Quote,
+        signature: Binary,
+        maker_pubkey: Binary,
+    ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::NewDeals)?;
+        assert_not_paused(deps.storage, PauseScope::Deposits)?;
+        if quote.contract != env.contract.address.as_str() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Quote is for another contract",
+            )));
+        }
+        if env.block.time > Timestamp::from_seconds(quote.valid_until) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Quote has expired",
+            )));
+        }
+        // The deal has to outlive the quote, or it could be created already expired
+        if env.block.time >= Timestamp::from_seconds(quote.deal_expiry)
+            || quote.deal_expiry < quote.valid_until
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal must not expire before the quote",
+            )));
+        }
+        if quote
+            .taker
+            .as_ref()
+            .is_some_and(|taker| *taker != info.sender.as_str())
+        {
+            return Err(ContractError::Unauthorized {});
+        }
+        let maker = deps.api.addr_validate(&quote.maker)?;
+        if maker == info.sender {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Makers cannot take their own quotes",
+            )));
+        }
+
+        // Only the key the maker registered can sign for them
+        if QUOTE_KEYS.may_load(deps.storage, &maker)?.as_ref() != Some(&maker_pubkey) {
+            return Err(ContractError::InvalidQuoteSignature {});
+        }
+        rfq::verify(deps.as_ref(), &quote, &signature, &maker_pubkey)?;
+        if QUOTE_NONCES.has(deps.storage, (&maker, quote.nonce)) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Quote was already taken or cancelled",
+            )));
+        }
+        QUOTE_NONCES.save(deps.storage, (&maker, quote.nonce), &Empty {})?;
+
+        let taker = info.sender.into_string();
+        let (maker_coin, taker_coin, seller, buyer) = match quote.maker_side {
+            Side::Seller => (&quote.coin_a, &quote.coin_b, maker.to_string(), taker),
+            Side::Buyer => (&quote.coin_b, &quote.coin_a, taker, maker.to_string()),
+        };
+        if info.funds != [taker_coin.clone()] {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Incorrect amount deposited",
+            )));
+        }
+        take_inventory(deps.storage, &maker, maker_coin)?;
+
+        for party in [&seller, &buyer] {
+            traders::assert_allowed(deps.as_ref(), party)?;
+            screening::assert_not_flagged(deps.as_ref(), party)?;
+        }
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        for coin in [maker_coin, taker_coin] {
+            limits::assert_deposit_allowed(deps.storage, &config, coin)?;
+        }
+        let legs = [&quote.coin_a, &quote.coin_b];
+        let approval = approvals::policy(deps.as_ref(), [&seller, &buyer], legs, vec![], 0)?;
+
+        let deal = Deal {
+            seller,
+            buyer,
+            coin_a: quote.coin_a,
+            coin_b: quote.coin_b,
+            expiry: quote.deal_expiry,
+            finished: false,
+            seller_deposited: true,
+            buyer_deposited: true,
+            seller_withdrew: false,
+            buyer_withdrew: false,
+            channel_id_recieved_a: String::new(),
+            channel_id_recieved_b: String::new(),
+            seller_leg: None,
+            buyer_leg: None,
+            seller_route: None,
+            buyer_route: None,
+            awaiting_acceptance: None,
+            cancelled: false,
+            funding_check: false,
+            creator: maker.to_string(),
+            broker: None,
+            broker_fee_bps: 0,
+            arbitrator: None,
+            dispute: None,
+            approvers: approval.approvers,
+            approver_group: approval.group,
+            approvals_required: approval.required,
+            group_approvals_required: approval.group_required,
+            approvals: vec![],
+            off_market: false,
+            peg: None,
+        };
+        Ok(save_deal(deps, &env, deal)?
+            .add_attribute("quote_maker", maker)
+            .add_attribute("quote_nonce", quote.nonce.to_string()))
+    }
+
+    fn take_inventory(
+        storage: &mut dyn Storage,
+        maker: &Addr,
+        coin: &Coin,
+    ) -> Result<(), ContractError> {
+        let held = INVENTORY
+            .may_load(storage, (maker, &coin.denom))?
+            .unwrap_or_default();
+        let left = held.checked_sub(coin.amount).map_err(|_| {
+            StdError::generic_err(format!("Not enough {} in inventory", coin.denom))
+        })?;
+        if left.is_zero() {
+            INVENTORY.remove(storage, (maker, &coin.denom));
+        } else {
+            INVENTORY.save(storage, (maker, &coin.denom), &left)?;
+        }
+        limits::release(storage, coin)?;
+        Ok(())
+    }
+
+    pub fn set_role(
+        deps: DepsMut,
+        info: MessageInfo,
+        role: Role,
+        address: String,
+        granted: bool,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Owner, &info.sender)?;
+        // Holders are revoked as stored, which needs no validation
+        let address = if granted {
+            deps.api.addr_validate(&address)?
+        } else {
+            Addr::unchecked(address)
+        };
+        if granted {
+            ROLES.save(deps.storage, (role.as_str(), &address), &Empty {})?;
+        } else {
+            ROLES.remove(deps.storage, (role.as_str(), &address));
+            // Nobody could grant roles any more
+            if role == Role::Owner && role_members(deps.storage, role)?.is_empty() {
+                return Err(ContractError::Std(StdError::generic_err(
+                    "Cannot revoke the last owner",
+                )));
+            }
+        }
+        Ok(Response::new()
+            .add_attribute("method", if granted { "grant_role" } else { "revoke_role" })
+            .add_attribute("role", role.as_str())
+            .add_attribute("address", address))
+    }
+
+    pub fn assert_role(deps: Deps, role: Role, sender: &Addr) -> Result<(), ContractError> {
+        if !ROLES.has(deps.storage, (role.as_str(), sender)) {
+            return Err(ContractError::Unauthorized {});
+        }
+        Ok(())
+    }
+
+    pub fn role_members(storage: &dyn Storage, role: Role) -> StdResult<Vec<Addr>> {
+        ROLES
+            .prefix(role.as_str())
+            .keys(storage, None, None, Order::Ascending)
+            .collect()
+    }
+
+    pub fn assert_not_paused(
+        storage: &dyn Storage,
+        scope: PauseScope,
+    ) -> Result<(), ContractError> {
+        if PAUSED
+            .may_load(storage)?
+            .unwrap_or_default()
+            .contains(&scope)
+        {
+            return Err(ContractError::Paused {
+                scope: scope.as_str().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Enables the admin to change the expiry of the deal.
+    pub fn change_expiry(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        expiry: u64,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::Operator, &info.sender)?;
+
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.expiry = expiry;
+            Ok(())
+        })?;
+
+        Ok(Response::new().add_attribute("method", "changed_expiry"))
+    }
+
+    // Enables anyone to tell the remote parties of an expired deal that it expired.
+    pub fn notify_expired(
+        deps: DepsMut,
+        env: Env,
+        deal_id: u64,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let deal = state.deal(deal_id)?;
+
+        if deal.finished || env.block.time <= Timestamp::from_seconds(deal.expiry) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has not expired or already finished.",
+            )));
+        }
+        let sent = NOTIFICATIONS
+            .may_load(deps.storage, deal_id)?
+            .unwrap_or_default();
+        if sent
+            .iter()
+            .any(|notification| notification.event == "expired")
+        {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Expiry has already been notified.",
+            )));
+        }
+
+        let packets = notify(
+            deps.storage,
+            &env,
+            &[&deal.channel_id_recieved_a, &deal.channel_id_recieved_b],
+            &IbcHostMsg::Expired { deal_id },
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "notify_expired")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_messages(packets))
+    }
+
+    // Enables the admin to open an interchain account on another chain.
+    pub fn register_interchain_account(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        connection_id: String,
+        host_connection_id: String,
+    ) -> Result<Response, ContractError> {
+        assert_role(deps.as_ref(), Role::ChannelManager, &info.sender)?;
+
+        // A channel closed by a timeout can be reopened, keeping the account address
+        let existing = INTERCHAIN_ACCOUNTS.may_load(deps.storage, &connection_id)?;
+        if existing.iter().any(|account| account.channel_id.is_some()) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Interchain account already has an open channel",
+            )));
+        }
+        let account = InterchainAccount {
+            connection_id: connection_id.clone(),
+            host_connection_id,
+            channel_id: None,
+            address: existing.map(|account| account.address).unwrap_or_default(),
+            lock_pending: false,
+        };
+        INTERCHAIN_ACCOUNTS.save(deps.storage, &connection_id, &account)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "register_interchain_account")
+            .add_attribute("connection_id", connection_id)
+            .add_message(ica::register(&env, &account)?))
+    }
+
+    // Enables a remote party to lock its leg of a deal that stays on its home
+    // chain. The interchain account there takes the leg from the party under
+    // the send authorization it granted, so every coin the account holds came
+    // from the owner of a leg, and the leg counts as deposited once it has.
+    pub fn lock_remote_leg_ibc(
+        deps: DepsMut,
+        env: Env,
+        channel: String,
+        deal_id: u64,
+        sender: String,
+    ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::Deposits)?;
+        traders::assert_allowed(deps.as_ref(), &sender)?;
+        screening::assert_not_flagged(deps.as_ref(), &sender)?;
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        assert_bound_to_channel(&deal, &sender, &channel)?;
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+        assert_accepted(&deal)?;
+        assert_funding_checked(deps.storage, deal_id, &deal)?;
+
+        let (side, leg, coin, deposited) = if sender == deal.seller {
+            (
+                Side::Seller,
+                &deal.seller_leg,
+                &deal.coin_a,
+                deal.seller_deposited,
+            )
+        } else {
+            (
+                Side::Buyer,
+                &deal.buyer_leg,
+                &deal.coin_b,
+                deal.buyer_deposited,
+            )
+        };
+        let leg = leg
+            .as_ref()
+            .ok_or_else(|| StdError::generic_err("Leg is escrowed on this chain"))?;
+        if deposited {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Leg is already locked",
+            )));
+        }
+
+        // One lock at a time, so a leg cannot be taken twice
+        let mut account = INTERCHAIN_ACCOUNTS.load(deps.storage, &leg.connection_id)?;
+        if account.lock_pending {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Another leg is being locked in this interchain account",
+            )));
+        }
+        account.lock_pending = true;
+        INTERCHAIN_ACCOUNTS.save(deps.storage, &leg.connection_id, &account)?;
+
+        let msg = ica::pull_tx(
+            &env,
+            &account,
+            &sender,
+            coin.clone(),
+            &IcaMemo {
+                deal_id,
+                side,
+                action: IcaAction::Lock,
+            },
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "lock_remote_leg")
+            .add_attribute("deal_id", deal_id.to_string())
+            .set_data(deal_result(deps.storage, &env, deal_id, None, None)?)
+            .add_message(msg))
+    }
+
+    // Lets anyone re-run the funding check of a deal, e.g. after a remote party
+    // topped up its balance or the previous query timed out. Parties with a
+    // query still in flight are not asked again.
+    pub fn check_funding(deps: DepsMut, env: Env, deal_id: u64) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        if !deal.funding_check {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal did not opt into funding checks",
+            )));
+        }
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+
+        let packets = request_checks(deps.storage, &env, deal_id, &deal)?;
+        Ok(Response::new()
+            .add_attribute("method", "check_funding")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_messages(packets))
+    }
+
+    // Locks one leg of a cross-chain atomic swap. The other leg is locked under
+    // the same hashlock on its own chain, with an earlier timelock, so its
+    // recipient has to reveal the preimage there before this lock expires.
+    pub fn lock_htlc(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        recipient: String,
+        hashlock: HexBinary,
+        timelock: u64,
+    ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::NewDeals)?;
+        if info.funds.len() != 1 {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Exactly one coin must be locked",
+            )));
+        }
+        if hashlock.len() != HASHLOCK_LEN {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Hashlock must be a sha256 hash",
+            )));
+        }
+        if env.block.time >= Timestamp::from_seconds(timelock) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Timelock must be in the future",
+            )));
+        }
+        deps.api.addr_validate(&recipient)?;
+        for party in [info.sender.as_str(), recipient.as_str()] {
+            traders::assert_allowed(deps.as_ref(), party)?;
+            screening::assert_not_flagged(deps.as_ref(), party)?;
+        }
+        let htlc = Htlc {
+            sender: info.sender.to_string(),
+            recipient,
+            amount: info.funds[0].clone(),
+            hashlock,
+            timelock,
+            state: HtlcState::Locked,
+        };
+        let state = STATE.load(deps.storage)?;
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        limits::assert_htlc_allowed(deps.storage, &state, &config, env.block.time, &htlc)?;
+
+        let id = HTLC_COUNT.may_load(deps.storage)?.unwrap_or_default();
+        HTLC_COUNT.save(deps.storage, &(id + 1))?;
+        HTLCS.save(deps.storage, id, &htlc)?;
+        limits::track_htlc(deps.storage, &htlc)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "lock_htlc")
+            .add_attribute("htlc_id", id.to_string())
+            .add_attribute("hashlock", htlc.hashlock.to_hex()))
+    }
+
+    pub fn claim_htlc(
+        deps: DepsMut,
+        env: Env,
+        id: u64,
+        preimage: HexBinary,
+    ) -> Result<Response, ContractError> {
+        let mut htlc = load_locked_htlc(deps.as_ref(), id)?;
+        if htlc.expired(env.block.time) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "HTLC timelock has passed",
+            )));
+        }
+        if !htlc.opens_with(&preimage) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Preimage does not match hashlock",
+            )));
+        }
 
-            let deal_coin;
-            let mut seller_deposited = state.deals[deal_found_index.unwrap()].seller_deposited;
-            let mut buyer_deposited = state.deals[deal_found_index.unwrap()].buyer_deposited;
-            let dest_channel;
-
-            // If only one party has deposited, allow that party to withdraw if not already withdrawn
-            // Mark party deposit as false
-            deal_coin = if deal.seller_deposited && !deal.buyer_deposited {
-                if withdrawer == deal.seller {
-                    seller_deposited = false;
-                    dest_channel = deal.channel_id_recieved_a;
-                    deal.coin_a
+        htlc.state = HtlcState::Claimed {
+            preimage: preimage.clone(),
+        };
+        HTLCS.save(deps.storage, id, &htlc)?;
+        limits::track_htlc(deps.storage, &htlc)?;
+
+        // The preimage is published so the other leg can be claimed with it
+        Ok(Response::new()
+            .add_attribute("method", "claim_htlc")
+            .add_attribute("htlc_id", id.to_string())
+            .add_attribute("preimage", preimage.to_hex())
+            .add_message(BankMsg::Send {
+                to_address: htlc.recipient,
+                amount: vec![htlc.amount],
+            }))
+    }
+
+    pub fn refund_htlc(deps: DepsMut, env: Env, id: u64) -> Result<Response, ContractError> {
+        let mut htlc = load_locked_htlc(deps.as_ref(), id)?;
+        if !htlc.expired(env.block.time) {
+            return Err(ContractError::Std(StdError::generic_err(
+                "HTLC timelock has not passed yet",
+            )));
+        }
+
+        htlc.state = HtlcState::Refunded;
+        HTLCS.save(deps.storage, id, &htlc)?;
+        limits::track_htlc(deps.storage, &htlc)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "refund_htlc")
+            .add_attribute("htlc_id", id.to_string())
+            .add_message(BankMsg::Send {
+                to_address: htlc.sender,
+                amount: vec![htlc.amount],
+            }))
+    }
+
+    fn load_locked_htlc(deps: Deps, id: u64) -> Result<Htlc, ContractError> {
+        let htlc = HTLCS
+            .may_load(deps.storage, id)?
+            .ok_or_else(|| StdError::generic_err("No HTLC found"))?;
+        if htlc.state != HtlcState::Locked {
+            return Err(ContractError::Std(StdError::generic_err(
+                "HTLC is no longer locked",
+            )));
+        }
+        Ok(htlc)
+    }
+
+    // Applies the outcome of an interchain account transaction to the deal it
+    // was sent for. Failed payouts and refunds are rolled back so they can be retried.
+    pub fn remote_leg_result(
+        storage: &mut dyn Storage,
+        env: &Env,
+        memo: IcaMemo,
+        success: bool,
+    ) -> Result<Vec<IbcMsg>, ContractError> {
+        let mut state = STATE.load(storage)?;
+        let Ok(deal) = state.deal_mut(memo.deal_id) else {
+            return Ok(vec![]);
+        };
+
+        let seller = memo.side == Side::Seller;
+        let leg = if seller {
+            &deal.seller_leg
+        } else {
+            &deal.buyer_leg
+        };
+        if let (IcaAction::Lock, Some(leg)) = (memo.action, leg) {
+            INTERCHAIN_ACCOUNTS.update(storage, &leg.connection_id, |account| {
+                let mut account = account.ok_or_else(|| StdError::not_found("account"))?;
+                account.lock_pending = false;
+                StdResult::Ok(account)
+            })?;
+        }
+
+        let before = deal.clone();
+        match (memo.action, success) {
+            (IcaAction::Lock, true) if deal.finished => {
+                // The deal was called off while the leg was on its way in, so
+                // it goes straight back to its owner
+                let (owner, coin, leg) = if seller {
+                    (&deal.seller, &deal.coin_a, &deal.seller_leg)
+                } else {
+                    (&deal.buyer, &deal.coin_b, &deal.buyer_leg)
+                };
+                let Some(leg) = leg else {
+                    return Ok(vec![]);
+                };
+                let account = INTERCHAIN_ACCOUNTS.load(storage, &leg.connection_id)?;
+                let refund = IcaMemo {
+                    action: IcaAction::Refund,
+                    ..memo
+                };
+                return Ok(vec![ica::send_tx(
+                    env,
+                    &account,
+                    owner,
+                    coin.clone(),
+                    &refund,
+                )?]);
+            }
+            (IcaAction::Lock, true) => {
+                if seller {
+                    deal.seller_deposited = true;
                 } else {
-                    return Err(ContractError::Unauthorized {});
+                    deal.buyer_deposited = true;
                 }
-            } else if deal.buyer_deposited && !deal.seller_deposited {
-                if withdrawer == deal.buyer {
-                    buyer_deposited = false;
-                    dest_channel = deal.channel_id_recieved_b;
-                    deal.coin_b
+            }
+            (IcaAction::Payout, true) => {
+                // Only a payout that went through counts toward volume
+                let (recipient, coin) = if seller {
+                    (&deal.buyer, &deal.coin_a)
                 } else {
-                    return Err(ContractError::Unauthorized {});
+                    (&deal.seller, &deal.coin_b)
+                };
+                fees::record_volume(storage, env.block.time, recipient, coin)?;
+                return Ok(vec![]);
+            }
+            (IcaAction::Payout, false) => {
+                // The party receiving this leg has not been paid after all
+                if seller {
+                    deal.buyer_withdrew = false;
+                } else {
+                    deal.seller_withdrew = false;
                 }
-            } else {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "No deposit found",
-                )));
-            };
-
-            // Update deal with deposit status
-            STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                state.deals[deal_found_index.unwrap()].seller_deposited = seller_deposited;
-                state.deals[deal_found_index.unwrap()].buyer_deposited = buyer_deposited;
+                deal.finished = false;
+            }
+            (IcaAction::Refund, false) => {
+                if seller {
+                    deal.seller_deposited = true;
+                } else {
+                    deal.buyer_deposited = true;
+                }
+            }
+            _ => return Ok(vec![]),
+        }
+        limits::track_deal(storage, env.block.time, memo.deal_id, Some(&before), deal)?;
+        let deal = deal.clone();
+        STATE.save(storage, &state)?;
 
-                Ok(state)
-            })?;
+        if memo.action == IcaAction::Lock {
+            let party = if seller { &deal.seller } else { &deal.buyer };
+            return Ok(notify_funded(storage, env, memo.deal_id, &deal, party)?);
+        }
+        Ok(vec![])
+    }
 
-            let msg = CosmosMsg::Ibc(IbcMsg::Transfer {
-                channel_id: dest_channel,
-                to_address: withdrawer.to_string(),
-                amount: deal_coin,
-                timeout: env.block.time.plus_seconds(100).into(),
-            });
+    // Pays `amount` of one leg of a deal to `party`. A leg that stayed on its
+    // home chain is sent by the interchain account holding it, anything else
+    // from here, over the party's channel if it is remote.
+    fn pay_out_leg(
+        deps: DepsMut,
+        env: &Env,
+        deal: &Deal,
+        memo: IcaMemo,
+        party: &str,
+        amount: Coin,
+    ) -> Result<SubMsg, ContractError> {
+        let leg = match memo.side {
+            Side::Seller => &deal.seller_leg,
+            Side::Buyer => &deal.buyer_leg,
+        };
+        if let Some(leg) = leg {
+            // A refund goes back to the owner the leg was taken from
+            let to_address = match memo.action {
+                IcaAction::Refund => party,
+                _ => &leg.payout_address,
+            };
+            let account = INTERCHAIN_ACCOUNTS.load(deps.storage, &leg.connection_id)?;
+            return Ok(SubMsg::new(ica::send_tx(
+                env, &account, to_address, amount, &memo,
+            )?));
+        }
 
-            Ok(Response::default().add_message(msg))
+        let (channel_id, route) = if party == deal.seller {
+            (&deal.channel_id_recieved_a, &deal.seller_route)
         } else {
-            return Err(ContractError::Std(StdError::generic_err("No deal found")));
+            (&deal.channel_id_recieved_b, &deal.buyer_route)
+        };
+        if channel_id.is_empty() {
+            return Ok(SubMsg::new(BankMsg::Send {
+                to_address: party.to_string(),
+                amount: vec![amount],
+            }));
         }
+        let payout = IbcPayout {
+            deal_id: memo.deal_id,
+            party: party.to_string(),
+            channel_id: channel_id.clone(),
+            amount,
+        };
+        ibc_payout(deps, env, payout, route.as_ref())
     }
 
-    // Enables the user on another chain to deposit funds.
-    pub fn deposit_ibc(
+    // Enables a remote party to have its payouts forwarded past its own chain.
+    pub fn set_payout_route_ibc(
         deps: DepsMut,
         env: Env,
         channel: String,
-        packet: Ics20Packet,
+        deal_id: u64,
+        sender: String,
+        route: Option<PayoutRoute>,
     ) -> Result<Response, ContractError> {
-        let state = STATE.load(deps.storage)?;
+        if let Some(route) = &route {
+            pfm::validate_route(route)?;
+        }
+
+        let mut state = STATE.load(deps.storage)?;
+        let deal = state.deal_mut(deal_id)?;
+        assert_bound_to_channel(deal, &sender, &channel)?;
+        // A route a payout failed along can still be fixed before retrying it
+        let failed = FAILED_PAYOUTS
+            .sub_prefix(deal_id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .next()
+            .is_some();
+        if deal.finished && !failed {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has already finished.",
+            )));
+        }
 
-        let deal_found_index =
-            find_deal_with_denom(state.clone(), packet.sender.clone(), packet.denom.clone());
-        let deal_found = if deal_found_index.is_some() {
-            Some(state.deals[deal_found_index.unwrap()].clone())
+        if sender == deal.seller {
+            deal.seller_route = route;
         } else {
-            None
-        };
+            deal.buyer_route = route;
+        }
+        STATE.save(deps.storage, &state)?;
 
-        if deal_found.is_some() {
-            let deal = deal_found.unwrap();
+        Ok(Response::new()
+            .add_attribute("method", "set_payout_route")
+            .add_attribute("deal_id", deal_id.to_string())
+            .set_data(deal_result(deps.storage, &env, deal_id, None, None)?))
+    }
 
-            deal_expired_or_finished(deal.clone(), env.clone())?;
+    // Pays a remote party over the transfer channel of the app channel it is
+    // reached on, forwarded along `route` if it set one. The transfer's reply
+    // files the payout under its packet sequence, so it can be sent again
+    // should the transfer fail.
+    fn ibc_payout(
+        deps: DepsMut,
+        env: &Env,
+        payout: IbcPayout,
+        route: Option<&PayoutRoute>,
+    ) -> Result<SubMsg, ContractError> {
+        let channel_id = transfer_channel(deps.as_ref(), &payout.channel_id)?;
+        let transfer =
+            pfm::payout_transfer(env, &channel_id, &payout.party, &payout.amount, route)?;
+        let id = SENDING_PAYOUTS
+            .keys(deps.storage, None, None, Order::Descending)
+            .next()
+            .transpose()?
+            .map_or(0, |id| id + 1);
+        SENDING_PAYOUTS.save(deps.storage, id, &payout)?;
+        Ok(SubMsg::reply_on_success(transfer, id))
+    }
 
-            // Deposit funds for seller
-            if packet.sender == deal.seller {
-                STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                    if state.deals[deal_found_index.unwrap()].coin_a.amount == packet.amount {
-                        state.deals[deal_found_index.unwrap()].seller_deposited = true;
-                        state.deals[deal_found_index.unwrap()].channel_id_recieved_a = channel;
-                    } else {
-                        return Err(ContractError::Std(StdError::generic_err(
-                            "Incorrect amount deposited",
-                        )));
-                    }
+    // Files a payout under the packet sequence its transfer was sent with.
+    pub fn payout_sent(
+        deps: DepsMut,
+        id: u64,
+        data: Option<Binary>,
+    ) -> Result<Response, ContractError> {
+        let sequence = data
+            .and_then(|data| proto::read_uint(&data, 1))
+            .ok_or_else(|| StdError::generic_err("MsgTransfer returned no sequence"))?;
+        let payout = SENDING_PAYOUTS.load(deps.storage, id)?;
+        SENDING_PAYOUTS.remove(deps.storage, id);
+        let channel_id = transfer_channel(deps.as_ref(), &payout.channel_id)?;
+        PAYOUT_TRANSFERS.save(deps.storage, (&channel_id, sequence), &payout)?;
+
+        Ok(Response::new()
+            .add_attribute("method", "payout_sent")
+            .add_attribute("sequence", sequence.to_string()))
+    }
 
-                    Ok(state)
-                })?;
-            // Deposit funds for buyer
-            } else if packet.sender == deal.buyer {
-                STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                    if state.deals[deal_found_index.unwrap()].coin_b.amount == packet.amount {
-                        state.deals[deal_found_index.unwrap()].buyer_deposited = true;
-                        state.deals[deal_found_index.unwrap()].channel_id_recieved_b = channel;
-                    } else {
-                        return Err(ContractError::Std(StdError::generic_err(
-                            "Incorrect amount deposited",
-                        )));
-                    }
-                    Ok(state)
-                })?;
-            } else {
-                return Err(ContractError::Unauthorized {});
-            }
-        } else {
-            return Err(ContractError::Std(StdError::generic_err("No deal found")));
+    // Releases a payout once its transfer is settled. ICS-20 has returned the
+    // coins of a failed one to us by then, so they are kept for the party
+    // until the payout is sent again.
+    pub fn payout_settled(
+        deps: DepsMut,
+        channel: String,
+        sequence: u64,
+        success: bool,
+    ) -> Result<Response, ContractError> {
+        let res = Response::new()
+            .add_attribute("method", "payout_settled")
+            .add_attribute("sequence", sequence.to_string())
+            .add_attribute("success", success.to_string());
+        let Some(payout) = PAYOUT_TRANSFERS.may_load(deps.storage, (&channel, sequence))? else {
+            return Ok(res);
+        };
+        PAYOUT_TRANSFERS.remove(deps.storage, (&channel, sequence));
+
+        let res = res.add_attribute("deal_id", payout.deal_id.to_string());
+        if success {
+            return Ok(res);
         }
-        Ok(Response::default())
+        limits::lock(deps.storage, &payout.amount)?;
+        FAILED_PAYOUTS.save(deps.storage, (payout.deal_id, &channel, sequence), &payout)?;
+        Ok(res.add_attribute("failed", format!("{} {}", payout.party, payout.amount)))
     }
 
-    // Executed by user on another chain to complete the deal.
-    pub fn deal_complete_ibc(
-        deps: DepsMut,
+    // Sends the payouts of a deal whose transfers failed again, along the
+    // parties' current payout routes. Anyone may retry them.
+    pub fn retry_payouts(
+        mut deps: DepsMut,
         env: Env,
-        _channel: String,
-        packet: Ics20Packet,
+        deal_id: u64,
     ) -> Result<Response, ContractError> {
+        assert_not_paused(deps.storage, PauseScope::Settlements)?;
+        let failed = FAILED_PAYOUTS
+            .sub_prefix(deal_id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        if failed.is_empty() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal has no failed payouts",
+            )));
+        }
+
         let state = STATE.load(deps.storage)?;
-        let withdrawer = packet.sender.clone();
+        let mut messages = vec![];
+        for ((channel, sequence), payout) in failed {
+            FAILED_PAYOUTS.remove(deps.storage, (deal_id, &channel, sequence));
+            limits::release(deps.storage, &payout.amount)?;
+            // A reset may have dropped the deal, and its routes with it
+            let route = state.deal(deal_id).ok().and_then(|deal| {
+                if payout.party == deal.seller {
+                    deal.seller_route.as_ref()
+                } else {
+                    deal.buyer_route.as_ref()
+                }
+            });
+            messages.push(ibc_payout(deps.branch(), &env, payout, route)?);
+        }
 
-        // Find the deal
-        let deal_found_index = find_deal(state.clone(), withdrawer.clone());
-        let deal_found = if deal_found_index.is_some() {
-            Some(state.deals[deal_found_index.unwrap()].clone())
-        } else {
-            None
+        Ok(Response::new()
+            .add_attribute("method", "retry_payouts")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_submessages(messages))
+    }
+
+    // Fixes the price of a funded pegged deal from the oracle on its first
+    // payout, refunding the buyer what it deposited over the amount owed.
+    // Returns the updated state.
+    fn fix_pegged_price(
+        mut deps: DepsMut,
+        env: &Env,
+        mut state: State,
+        deal_id: u64,
+    ) -> Result<(State, Vec<SubMsg>), ContractError> {
+        let deal = state.deal(deal_id)?.clone();
+        let Some(peg) = &deal.peg else {
+            return Ok((state, vec![]));
         };
+        if peg.price.is_some() || !deal.seller_deposited || !deal.buyer_deposited {
+            return Ok((state, vec![]));
+        }
+
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        let (owed, price) = oracle::pegged_amount(deps.as_ref(), &config, &deal, peg.spread_bps)?;
+        let refund = Coin::new((deal.coin_b.amount - owed).u128(), &deal.coin_b.denom);
+        let mut messages = vec![];
+        if !refund.amount.is_zero() {
+            messages.push(force_payout(
+                deps.branch(),
+                env,
+                &deal,
+                deal_id,
+                Side::Buyer,
+                &deal.buyer,
+                refund,
+            )?);
+        }
 
-        let dest_channel;
-        let deal_coin;
-        let deal;
-        let mut seller_withdrew = state.deals[deal_found_index.unwrap()].seller_withdrew;
-        let mut buyer_withdrew = state.deals[deal_found_index.unwrap()].buyer_withdrew;
+        let fixed = state.deal_mut(deal_id)?;
+        fixed.coin_b.amount = owed;
+        fixed.peg = Some(Peg {
+            price: Some(price),
+            ..peg.clone()
+        });
+        let now = env.block.time;
+        limits::track_deal(deps.storage, now, deal_id, Some(&deal), fixed)?;
+        STATE.save(deps.storage, &state)?;
+        Ok((state, messages))
+    }
 
-        if deal_found.is_some() {
-            deal = deal_found.unwrap();
+    // A funded deal nobody has been paid from cannot settle once its price
+    // leaves the oracle band, or once a pegged buyer would owe more than it
+    // deposited, so its parties may take their deposits back. An open dispute
+    // keeps the deposits for the arbitrator to split.
+    fn stuck_outside_band(deps: Deps, deal: &Deal) -> Result<bool, ContractError> {
+        if deal.seller_withdrew || deal.buyer_withdrew || deal.disputed() {
+            return Ok(false);
+        }
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        Ok(oracle::is_outside_band(deps, &config, deal)?
+            || oracle::is_pegged_over_cap(deps, &config, deal)?)
+    }
 
-            deal_expired_or_finished(deal.clone(), env.clone())?;
+    // Rejects paying out or refunding a deal while its dispute is open.
+    fn assert_not_disputed(deal: &Deal) -> Result<(), ContractError> {
+        if deal.disputed() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Deal is disputed until its arbitrator rules",
+            )));
+        }
+        Ok(())
+    }
 
-            // Check if both parties have deposited
-            if !deal.seller_deposited || !deal.buyer_deposited {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "Both parties must deposit first",
-                )));
-            }
+    // Records who forced what on a deal, for the audit trail.
+    fn forced_action_event(action: &str, deal_id: u64, admin: &Addr) -> Event {
+        Event::new("forced_action")
+            .add_attribute("action", action)
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_attribute("admin", admin)
+    }
 
-            deal_coin = if withdrawer == deal.seller {
-                seller_withdrew = true;
-                dest_channel = deal.channel_id_recieved_b;
-                deal.coin_b
-            } else if withdrawer == deal.buyer {
-                buyer_withdrew = true;
-                dest_channel = deal.channel_id_recieved_a;
-                deal.coin_a
-            } else {
-                return Err(ContractError::Std(StdError::generic_err(
-                    "No deposit found",
-                )));
-            };
+    // Pays the leg of `side` to `party`, over its channel if it is remote.
+    fn force_payout(
+        deps: DepsMut,
+        env: &Env,
+        deal: &Deal,
+        deal_id: u64,
+        side: Side,
+        party: &str,
+        amount: Coin,
+    ) -> Result<SubMsg, ContractError> {
+        // The owner of a leg gets it back, anyone else is paid it
+        let action = if (side == Side::Seller) == (party == deal.seller) {
+            IcaAction::Refund
+        } else {
+            IcaAction::Payout
+        };
+        pay_out_leg(
+            deps,
+            env,
+            deal,
+            IcaMemo {
+                deal_id,
+                side,
+                action,
+            },
+            party,
+            amount,
+        )
+    }
+
+    // Whether a leg is still held for the deal: deposited and not yet paid out.
+    fn leg_locked(deal: &Deal, seller: bool) -> bool {
+        if seller {
+            deal.seller_deposited && !deal.buyer_withdrew
         } else {
-            return Err(ContractError::Std(StdError::generic_err("No deal found")));
+            deal.buyer_deposited && !deal.seller_withdrew
         }
+    }
 
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.deals[deal_found_index.unwrap()].seller_withdrew = seller_withdrew;
-            state.deals[deal_found_index.unwrap()].buyer_withdrew = buyer_withdrew;
+    // Rejects deposits here for a leg that stays on its home chain.
+    fn assert_escrowed_here(deal: &Deal, party: &str) -> Result<(), ContractError> {
+        let leg = if party == deal.seller {
+            &deal.seller_leg
+        } else {
+            &deal.buyer_leg
+        };
+        if leg.is_some() {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Leg is locked in an interchain account",
+            )));
+        }
+        Ok(())
+    }
 
-            // Mark deal as finished if both parties withdrew
-            if seller_withdrew && buyer_withdrew {
-                state.deals[deal_found_index.unwrap()].finished = true;
+    // Stores a new deal and tells its remote parties about it. The deal id is
+    // returned as the response data.
+    fn save_deal(deps: DepsMut, env: &Env, deal: Deal) -> Result<Response, ContractError> {
+        // Remote parties must be registered on an open channel
+        for channel_id in [&deal.channel_id_recieved_a, &deal.channel_id_recieved_b] {
+            if !channel_id.is_empty() && !CHANNELS.has(deps.storage, channel_id) {
+                return Err(ContractError::ChannelDisabled {
+                    channel_id: channel_id.clone(),
+                });
             }
-            Ok(state)
-        })?;
+        }
 
-        let msg = CosmosMsg::Ibc(IbcMsg::Transfer {
-            channel_id: dest_channel,
-            to_address: withdrawer.to_string(),
-            amount: deal_coin,
-            timeout: env.block.time.plus_seconds(100).into(),
-        });
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        oracle::assert_price_in_band(deps.as_ref(), &config, &deal)?;
+        let mut state = STATE.load(deps.storage)?;
+        limits::assert_deal_allowed(deps.storage, &state, &config, env.block.time, &deal)?;
+        let deal_id = state.next_deal_id();
+        limits::track_deal(deps.storage, env.block.time, deal_id, None, &deal)?;
+        state.deals.push(deal.clone());
+        STATE.save(deps.storage, &state)?;
+
+        let mut packets = notify(
+            deps.storage,
+            env,
+            &[&deal.channel_id_recieved_a, &deal.channel_id_recieved_b],
+            &IbcHostMsg::DealCreated {
+                deal_id,
+                seller: deal.seller.clone(),
+                buyer: deal.buyer.clone(),
+                coin_a: deal.coin_a.clone(),
+                coin_b: deal.coin_b.clone(),
+                expiry: deal.expiry,
+            },
+        )?;
+        if deal.funding_check {
+            packets.extend(request_checks(deps.storage, env, deal_id, &deal)?);
+        }
 
-        Ok(Response::default().add_message(msg))
+        Ok(Response::new()
+            .add_attribute("method", "created_deal")
+            .add_attribute("deal_id", deal_id.to_string())
+            .add_attribute("off_market", deal.off_market.to_string())
+            .set_data(deal_result(deps.storage, env, deal_id, None, None)?)
+            .add_messages(packets))
     }
 
-    // Enables the admin to change the expiry of the deal.
-    pub fn change_expiry(
+    fn accept(
         deps: DepsMut,
-        _env: Env,
-        info: MessageInfo,
-        expiry: u64,
+        env: Env,
         deal_id: u64,
+        sender: &str,
     ) -> Result<Response, ContractError> {
-        let res = ADMIN.assert_admin(deps.as_ref(), &info.sender.clone()); // Check if admin
-
-        if res.is_err() {
+        let mut state = STATE.load(deps.storage)?;
+        let deal = state.deal_mut(deal_id)?;
+        deal_expired_or_finished(deal.clone(), env.clone())?;
+        if deal.awaiting_acceptance.as_deref() != Some(sender) {
             return Err(ContractError::Unauthorized {});
         }
+        deal.awaiting_acceptance = None;
+        STATE.save(deps.storage, &state)?;
 
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.deals[deal_id as usize].expiry = expiry;
-            Ok(state)
+        Ok(Response::new()
+            .add_attribute("method", "accept_deal")
+            .add_attribute("deal_id", deal_id.to_string())
+            .set_data(deal_result(deps.storage, &env, deal_id, None, None)?))
+    }
+
+    fn cancel(
+        deps: DepsMut,
+        env: Env,
+        deal_id: u64,
+        sender: &str,
+    ) -> Result<Response, ContractError> {
+        let deal = load_deal(deps.as_ref(), deal_id)?;
+        if sender != deal.seller && sender != deal.buyer {
+            return Err(ContractError::Unauthorized {});
+        }
+        if deal.finished || deal.seller_deposited || deal.buyer_deposited {
+            return Err(ContractError::Std(StdError::generic_err(
+                "Only deals nobody has deposited into can be cancelled",
+            )));
+        }
+        update_deal(deps.storage, env.block.time, deal_id, |deal| {
+            deal.finished = true;
+            deal.cancelled = true;
+            Ok(())
         })?;
+        let channels = [
+            deal.channel_id_recieved_a.clone(),
+            deal.channel_id_recieved_b.clone(),
+        ];
+
+        let packets = notify(
+            deps.storage,
+            &env,
+            &[&channels[0], &channels[1]],
+            &IbcHostMsg::Cancelled { deal_id },
+        )?;
+
+        Ok(Response::new()
+            .add_attribute("method", "cancel_deal")
+            .add_attribute("deal_id", deal_id.to_string())
+            .set_data(deal_result(deps.storage, &env, deal_id, None, None)?)
+            .add_messages(packets))
+    }
 
-        Ok(Response::new().add_attribute("method", "changed_expiry"))
+    // Describes a deal after an instruction for it. Set as the response data,
+    // which remote senders get back in the ack.
+    fn deal_result(
+        storage: &dyn Storage,
+        env: &Env,
+        deal_id: u64,
+        credited: Option<Coin>,
+        payout: Option<Payout>,
+    ) -> StdResult<Binary> {
+        let state = STATE.load(storage)?;
+        let deal = state.deal(deal_id)?;
+        to_json_binary(&AckResult {
+            deal_id,
+            status: deal.status(env.block.time),
+            credited,
+            payout,
+        })
     }
 
-    // Find deal by seller or buyer
-    fn find_deal(state: State, sender: String) -> Option<usize> {
-        for (i, deal) in state.deals.iter().enumerate() {
-            if deal.seller == sender || deal.buyer == sender {
-                return Some(i);
-            }
+    fn next_payout(storage: &mut dyn Storage, amount: Coin) -> StdResult<Payout> {
+        let id = LAST_PAYOUT_ID.may_load(storage)?.unwrap_or_default() + 1;
+        LAST_PAYOUT_ID.save(storage, &id)?;
+        Ok(Payout { amount, id })
+    }
+
+    // Applies `change` to deal `deal_id` and saves it, keeping the running
+    // limit totals in step. Returns the updated state.
+    fn update_deal(
+        storage: &mut dyn Storage,
+        now: Timestamp,
+        deal_id: u64,
+        change: impl FnOnce(&mut Deal) -> Result<(), ContractError>,
+    ) -> Result<State, ContractError> {
+        let mut state = STATE.load(storage)?;
+        let deal = state.deal_mut(deal_id)?;
+        let before = deal.clone();
+        change(deal)?;
+        limits::track_deal(storage, now, deal_id, Some(&before), deal)?;
+        STATE.save(storage, &state)?;
+        Ok(state)
+    }
+
+    fn load_deal(deps: Deps, deal_id: u64) -> Result<Deal, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        Ok(state.deal(deal_id)?.clone())
+    }
+
+    // Rejects funding a deal proposed over IBC until the other party accepted it.
+    fn assert_accepted(deal: &Deal) -> Result<(), ContractError> {
+        if let Some(party) = &deal.awaiting_acceptance {
+            return Err(ContractError::Std(StdError::generic_err(format!(
+                "Deal has not been accepted by {}",
+                party
+            ))));
         }
-        None
+        Ok(())
     }
 
-    // Find deal by seller or buyer and denom
-    fn find_deal_with_denom(state: State, sender: String, denom: String) -> Option<usize> {
-        for (i, deal) in state.deals.iter().enumerate() {
-            if (deal.seller == sender && deal.coin_a.denom == denom)
-                || (deal.buyer == sender && deal.coin_b.denom == denom)
-            {
-                return Some(i);
-            }
+    // Tells the other party of a deal that `party` has deposited its leg.
+    fn notify_funded(
+        storage: &mut dyn Storage,
+        env: &Env,
+        deal_id: u64,
+        deal: &Deal,
+        party: &str,
+    ) -> StdResult<Vec<IbcMsg>> {
+        let counterparty_channel = if party == deal.seller {
+            &deal.channel_id_recieved_b
+        } else {
+            &deal.channel_id_recieved_a
+        };
+        notify(
+            storage,
+            env,
+            &[counterparty_channel],
+            &IbcHostMsg::CounterpartyFunded {
+                deal_id,
+                party: party.to_string(),
+            },
+        )
+    }
+
+    // Tells both parties of a deal that it has settled.
+    fn notify_settled(
+        storage: &mut dyn Storage,
+        env: &Env,
+        state: &State,
+        deal_id: u64,
+    ) -> StdResult<Vec<IbcMsg>> {
+        let deal = state.deal(deal_id)?;
+        notify(
+            storage,
+            env,
+            &[&deal.channel_id_recieved_a, &deal.channel_id_recieved_b],
+            &IbcHostMsg::Settled { deal_id },
+        )
+    }
+
+    // A remote party is identified by its address together with the channel
+    // registered for it at deal creation. Only allowlisted agents can open
+    // channels and they attest the sender, so an instruction for a party
+    // arriving over any other channel is rejected.
+    fn assert_bound_to_channel(
+        deal: &Deal,
+        sender: &str,
+        channel: &str,
+    ) -> Result<(), ContractError> {
+        let registered = if sender == deal.seller {
+            &deal.channel_id_recieved_a
+        } else if sender == deal.buyer {
+            &deal.channel_id_recieved_b
+        } else {
+            return Err(ContractError::Unauthorized {});
+        };
+
+        if registered.is_empty() || registered != channel {
+            return Err(ContractError::SenderNotOnChannel {
+                sender: sender.to_string(),
+                channel_id: channel.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Look up the ICS-20 channel used to pay out parties reached over an app channel
+    fn transfer_channel(deps: Deps, channel_id: &str) -> Result<String, ContractError> {
+        match CHANNELS.may_load(deps.storage, channel_id)? {
+            Some(channel) => Ok(channel.transfer_channel),
+            None => Err(ContractError::ChannelDisabled {
+                channel_id: channel_id.to_string(),
+            }),
         }
-        None
     }
 
     // Check if deal has expired or finished
@@ -586,15 +2996,71 @@ pub mod execute {
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetBalances { address } => to_json_binary(&query::get_balances(deps, address)?),
         QueryMsg::GetDeal { id } => to_json_binary(&query::get_deal(deps, id)?),
+        QueryMsg::AllowedCounterparties {} => to_json_binary(&query::allowed_counterparties(deps)?),
+        QueryMsg::Channels {} => to_json_binary(&query::channels(deps)?),
+        QueryMsg::Notifications { deal_id } => {
+            to_json_binary(&query::notifications(deps, deal_id)?)
+        }
+        QueryMsg::InterchainAccounts { deal_id } => {
+            to_json_binary(&query::interchain_accounts(deps, deal_id)?)
+        }
+        QueryMsg::FundingChecks { deal_id } => {
+            to_json_binary(&query::funding_checks(deps, deal_id)?)
+        }
+        QueryMsg::Htlc { id } => to_json_binary(&query::htlc(deps, id)?),
+        QueryMsg::FailedPayouts { deal_id } => {
+            to_json_binary(&query::failed_payouts(deps, deal_id)?)
+        }
+        QueryMsg::Config {} => to_json_binary(&query::config(deps)?),
+        QueryMsg::AccruedFees {} => to_json_binary(&query::accrued_fees(deps)?),
+        QueryMsg::AccountFees { address } => {
+            to_json_binary(&query::account_fees(deps, env, address)?)
+        }
+        QueryMsg::BrokerEarnings { broker } => {
+            to_json_binary(&query::broker_earnings(deps, broker)?)
+        }
+        QueryMsg::Utilisation { address } => {
+            to_json_binary(&query::utilisation(deps, env, address)?)
+        }
+        QueryMsg::Trader { address } => to_json_binary(&TraderResponse {
+            allowed: traders::is_allowed(deps, &address)
+                .map_err(|error| StdError::generic_err(error.to_string()))?,
+        }),
+        QueryMsg::Inventory { maker } => to_json_binary(&query::inventory(deps, maker)?),
+        QueryMsg::Roles { address } => to_json_binary(&query::roles(deps, address)?),
+        QueryMsg::RoleMembers { role } => to_json_binary(&RoleMembersResponse {
+            members: execute::role_members(deps.storage, role)?
+                .into_iter()
+                .map(Addr::into_string)
+                .collect(),
+        }),
+        QueryMsg::PauseStatus {} => to_json_binary(&PauseStatusResponse {
+            paused: PAUSED.may_load(deps.storage)?.unwrap_or_default(),
+            guardian: GUARDIAN.get(deps).ok().flatten().map(Addr::into_string),
+        }),
     }
 }
 
 mod query {
-    use cosmwasm_std::{to_json_binary, Binary, Deps, StdResult};
+    use cosmwasm_std::{to_json_binary, Addr, Binary, Coin, Deps, Env, Order, StdResult};
+
+    use crate::fees;
+    use crate::limits;
+    use crate::msg::{
+        AccountFeesResponse, AccruedFeesResponse, AllowedCounterpartiesResponse,
+        AllowedCounterparty, BrokerEarningsResponse, ChannelsResponse, FailedPayoutsResponse,
+        FundingChecksResponse, InterchainAccountsResponse, InventoryResponse,
+        NotificationsResponse, RolesResponse, UtilisationResponse,
+    };
+    use crate::state::{
+        Config, Htlc, RemoteLeg, Role, ACCOUNT_DISCOUNTS, ACCRUED_FEES, ALLOWED_COUNTERPARTIES,
+        BROKER_EARNINGS, CHANNELS, CONFIG, FAILED_PAYOUTS, FUNDING_CHECKS, HTLCS,
+        INTERCHAIN_ACCOUNTS, INVENTORY, NOTIFICATIONS, ROLES, STATE, VOLUMES,
+    };
 
     pub fn get_balances(deps: Deps, address: String) -> StdResult<Binary> {
         let balances = deps
@@ -606,7 +3072,151 @@ mod query {
 
     pub fn get_deal(deps: Deps, id: u64) -> StdResult<Binary> {
         let state = super::STATE.load(deps.storage)?;
-        let deal = state.deals[id as usize].clone();
+        let deal = state.deal(id)?.clone();
         to_json_binary(&deal)
     }
+
+    pub fn allowed_counterparties(deps: Deps) -> StdResult<AllowedCounterpartiesResponse> {
+        let counterparties = ALLOWED_COUNTERPARTIES
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| {
+                item.map(
+                    |((connection_id, counterparty_port_id), transfer_channel)| {
+                        AllowedCounterparty {
+                            connection_id,
+                            counterparty_port_id,
+                            transfer_channel,
+                        }
+                    },
+                )
+            })
+            .collect::<StdResult<_>>()?;
+        Ok(AllowedCounterpartiesResponse { counterparties })
+    }
+
+    pub fn channels(deps: Deps) -> StdResult<ChannelsResponse> {
+        let channels = CHANNELS
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, channel)| channel))
+            .collect::<StdResult<_>>()?;
+        Ok(ChannelsResponse { channels })
+    }
+
+    pub fn notifications(deps: Deps, deal_id: u64) -> StdResult<NotificationsResponse> {
+        let notifications = NOTIFICATIONS
+            .may_load(deps.storage, deal_id)?
+            .unwrap_or_default();
+        Ok(NotificationsResponse { notifications })
+    }
+
+    pub fn interchain_accounts(deps: Deps, deal_id: u64) -> StdResult<InterchainAccountsResponse> {
+        let state = super::STATE.load(deps.storage)?;
+        let deal = state.deal(deal_id)?;
+        let account = |leg: &Option<RemoteLeg>| {
+            leg.as_ref()
+                .map(|leg| INTERCHAIN_ACCOUNTS.load(deps.storage, &leg.connection_id))
+                .transpose()
+        };
+        Ok(InterchainAccountsResponse {
+            seller: account(&deal.seller_leg)?,
+            buyer: account(&deal.buyer_leg)?,
+        })
+    }
+
+    pub fn funding_checks(deps: Deps, deal_id: u64) -> StdResult<FundingChecksResponse> {
+        let checks = FUNDING_CHECKS
+            .prefix(deal_id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, check)| check))
+            .collect::<StdResult<_>>()?;
+        Ok(FundingChecksResponse { checks })
+    }
+
+    pub fn htlc(deps: Deps, id: u64) -> StdResult<Htlc> {
+        HTLCS.load(deps.storage, id)
+    }
+
+    pub fn failed_payouts(deps: Deps, deal_id: u64) -> StdResult<FailedPayoutsResponse> {
+        let payouts = FAILED_PAYOUTS
+            .sub_prefix(deal_id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, payout)| payout))
+            .collect::<StdResult<_>>()?;
+        Ok(FailedPayoutsResponse { payouts })
+    }
+
+    pub fn config(deps: Deps) -> StdResult<Config> {
+        Ok(CONFIG.may_load(deps.storage)?.unwrap_or_default())
+    }
+
+    pub fn accrued_fees(deps: Deps) -> StdResult<AccruedFeesResponse> {
+        let fees = ACCRUED_FEES
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+            .collect::<StdResult<_>>()?;
+        Ok(AccruedFeesResponse { fees })
+    }
+
+    pub fn account_fees(deps: Deps, env: Env, address: String) -> StdResult<AccountFeesResponse> {
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        let volumes = VOLUMES
+            .prefix(&address)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| {
+                item.map(|(denom, volume)| Coin {
+                    denom,
+                    amount: fees::current_volume(&config, env.block.time, Some(volume)).amount,
+                })
+            })
+            .collect::<StdResult<_>>()?;
+        Ok(AccountFeesResponse {
+            discount_bps: ACCOUNT_DISCOUNTS.may_load(deps.storage, &address)?,
+            volumes,
+        })
+    }
+
+    pub fn utilisation(
+        deps: Deps,
+        env: Env,
+        address: Option<String>,
+    ) -> StdResult<UtilisationResponse> {
+        let state = STATE.load(deps.storage)?;
+        let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+        Ok(UtilisationResponse {
+            locked: limits::locked(deps.storage)?,
+            max_locked: config.max_locked,
+            open_deals: address
+                .map(|address| limits::open_deals(deps.storage, &state, env.block.time, &address))
+                .transpose()?,
+            max_open_deals: config.max_open_deals,
+        })
+    }
+
+    pub fn inventory(deps: Deps, maker: String) -> StdResult<InventoryResponse> {
+        let maker = deps.api.addr_validate(&maker)?;
+        let coins = INVENTORY
+            .prefix(&maker)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(InventoryResponse { coins })
+    }
+
+    pub fn roles(deps: Deps, address: String) -> StdResult<RolesResponse> {
+        let address = Addr::unchecked(address);
+        let roles = Role::ALL
+            .into_iter()
+            .filter(|role| ROLES.has(deps.storage, (role.as_str(), &address)))
+            .collect();
+        Ok(RolesResponse { roles })
+    }
+
+    pub fn broker_earnings(deps: Deps, broker: String) -> StdResult<BrokerEarningsResponse> {
+        let earnings = BROKER_EARNINGS
+            .prefix(&broker)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+            .collect::<StdResult<_>>()?;
+        Ok(BrokerEarningsResponse { earnings })
+    }
 }
@@ -1,4 +1,5 @@
 use cosmwasm_std::StdError;
+use otc_ibc::ack::ErrorCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -18,4 +19,53 @@ pub enum ContractError {
 
     #[error("invalid IBC channel version. Got ({actual}), expected ({expected})")]
     InvalidVersion { actual: String, expected: String },
+
+    #[error("counterparty port ({port_id}) on connection ({connection_id}) is not allowlisted")]
+    CounterpartyNotAllowed {
+        connection_id: String,
+        port_id: String,
+    },
+
+    #[error("channel ({channel_id}) is not allowlisted or has been frozen")]
+    ChannelDisabled { channel_id: String },
+
+    #[error("sender ({sender}) is not registered on channel ({channel_id})")]
+    SenderNotOnChannel { sender: String, channel_id: String },
+
+    #[error("{scope} are paused")]
+    Paused { scope: String },
+
+    #[error("{address} is not an allowed trader")]
+    TraderNotAllowed { address: String },
+
+    #[error("{address} was flagged by screening: {reason}")]
+    AddressFlagged { address: String, reason: String },
+
+    #[error("quote is not signed by its maker")]
+    InvalidQuoteSignature {},
+}
+
+impl ContractError {
+    /// Classifies the error for remote senders, who get it back in an error ack.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ContractError::Std(
+                StdError::ParseErr { .. }
+                | StdError::SerializeErr { .. }
+                | StdError::InvalidBase64 { .. }
+                | StdError::InvalidUtf8 { .. },
+            ) => ErrorCode::InvalidPacket,
+            ContractError::Unauthorized {}
+            | ContractError::SenderNotOnChannel { .. }
+            | ContractError::TraderNotAllowed { .. }
+            | ContractError::InvalidQuoteSignature {} => ErrorCode::Unauthorized,
+            ContractError::ChannelDisabled { .. }
+            | ContractError::CounterpartyNotAllowed { .. }
+            | ContractError::OrderedChannel {}
+            | ContractError::InvalidVersion { .. } => ErrorCode::ChannelDisabled,
+            ContractError::Paused { .. } => ErrorCode::Paused,
+            ContractError::AddressFlagged { .. } => ErrorCode::Flagged,
+            ContractError::Std(_) => ErrorCode::Rejected,
+        }
+    }
 }
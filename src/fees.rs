@@ -0,0 +1,172 @@
+use cosmwasm_std::{BankMsg, Coin, StdResult, Storage, Timestamp, Uint128};
+
+use crate::state::{
+    Config, Deal, FeeRate, Side, Volume, ACCOUNT_DISCOUNTS, ACCRUED_FEES, BROKER_EARNINGS, CONFIG,
+    VOLUMES,
+};
+
+const BPS_DENOMINATOR: u16 = 10_000;
+
+/// What is taken from a settlement payout before it reaches its recipient.
+pub struct Charges {
+    pub fee: Coin,
+    pub broker_fee: Coin,
+    /// what the recipient is paid
+    pub net: Coin,
+    /// sends the fee to the fee collector and the broker its cut
+    pub messages: Vec<BankMsg>,
+}
+
+/// Takes the protocol fee and the broker's cut from `recipient`'s payout of
+/// `side`'s leg, and counts the payout towards its volume. Legs held in an
+/// interchain account settle on their home chain and are not charged, and
+/// count once the account acknowledges their payout.
+pub fn charge(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    deal: &Deal,
+    side: Side,
+    recipient: &str,
+    payout: &Coin,
+) -> StdResult<Charges> {
+    let escrowed_here = match side {
+        Side::Seller => deal.seller_leg.is_none(),
+        Side::Buyer => deal.buyer_leg.is_none(),
+    };
+    let fee = if escrowed_here {
+        record_volume(storage, now, recipient, payout)?;
+        settlement_fee(storage, now, deal, recipient, payout)?
+    } else {
+        Coin::new(0, &payout.denom)
+    };
+
+    let mut messages: Vec<_> = collect(storage, &fee)?.into_iter().collect();
+    let mut broker_fee = Coin::new(0, &payout.denom);
+    if let (Some(broker), true) = (&deal.broker, escrowed_here) {
+        broker_fee.amount = payout
+            .amount
+            .multiply_ratio(deal.broker_fee_bps, BPS_DENOMINATOR)
+            .min(payout.amount - fee.amount);
+        if !broker_fee.amount.is_zero() {
+            BROKER_EARNINGS.update(storage, (broker, &payout.denom), |earned| -> StdResult<_> {
+                Ok(earned.unwrap_or_default() + broker_fee.amount)
+            })?;
+            messages.push(BankMsg::Send {
+                to_address: broker.clone(),
+                amount: vec![broker_fee.clone()],
+            });
+        }
+    }
+
+    Ok(Charges {
+        net: Coin {
+            denom: payout.denom.clone(),
+            amount: payout.amount - fee.amount - broker_fee.amount,
+        },
+        fee,
+        broker_fee,
+        messages,
+    })
+}
+
+fn settlement_fee(
+    storage: &dyn Storage,
+    now: Timestamp,
+    deal: &Deal,
+    recipient: &str,
+    payout: &Coin,
+) -> StdResult<Coin> {
+    let config = CONFIG.may_load(storage)?.unwrap_or_default();
+    let rate = config
+        .denom_fees
+        .iter()
+        .find(|fee| fee.denom == payout.denom)
+        .map_or(&config.fee, |fee| &fee.rate);
+    let discount = discount_bps(storage, &config, now, deal, recipient, &payout.denom)?;
+    Ok(Coin {
+        denom: payout.denom.clone(),
+        amount: fee_amount(rate, payout.amount)
+            .multiply_ratio(BPS_DENOMINATOR - discount, BPS_DENOMINATOR),
+    })
+}
+
+// Sends a fee to the fee collector, or accrues it to be claimed later.
+fn collect(storage: &mut dyn Storage, fee: &Coin) -> StdResult<Option<BankMsg>> {
+    if fee.amount.is_zero() {
+        return Ok(None);
+    }
+    if let Some(collector) = CONFIG.may_load(storage)?.unwrap_or_default().fee_collector {
+        return Ok(Some(BankMsg::Send {
+            to_address: collector,
+            amount: vec![fee.clone()],
+        }));
+    }
+    ACCRUED_FEES.update(storage, &fee.denom, |accrued| -> StdResult<_> {
+        Ok(accrued.unwrap_or_default() + fee.amount)
+    })?;
+    Ok(None)
+}
+
+/// Counts a settlement payout towards the recipient's volume tier.
+pub fn record_volume(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    address: &str,
+    payout: &Coin,
+) -> StdResult<()> {
+    let config = CONFIG.may_load(storage)?.unwrap_or_default();
+    let key = (address, payout.denom.as_str());
+    let mut volume = current_volume(&config, now, VOLUMES.may_load(storage, key)?);
+    volume.amount += payout.amount;
+    VOLUMES.save(storage, key, &volume)
+}
+
+/// Volume an account settled in the current window, starting over once it ended.
+pub fn current_volume(config: &Config, now: Timestamp, volume: Option<Volume>) -> Volume {
+    match volume {
+        Some(volume)
+            if config.volume_window == 0
+                || now.seconds() < volume.window_start + config.volume_window =>
+        {
+            volume
+        }
+        _ => Volume {
+            window_start: now.seconds(),
+            amount: Uint128::zero(),
+        },
+    }
+}
+
+// The best discount `recipient` qualifies for: as the deal's maker, by a
+// negotiated rate, or by the volume it settled in `denom`.
+fn discount_bps(
+    storage: &dyn Storage,
+    config: &Config,
+    now: Timestamp,
+    deal: &Deal,
+    recipient: &str,
+    denom: &str,
+) -> StdResult<u16> {
+    let mut discount = ACCOUNT_DISCOUNTS
+        .may_load(storage, recipient)?
+        .unwrap_or_default();
+    if recipient == deal.creator {
+        discount = discount.max(config.maker_discount_bps);
+    }
+
+    let volume = current_volume(config, now, VOLUMES.may_load(storage, (recipient, denom))?);
+    for tier in &config.volume_tiers {
+        if tier.denom == denom && volume.amount >= tier.min_volume {
+            discount = discount.max(tier.discount_bps);
+        }
+    }
+    Ok(discount.min(BPS_DENOMINATOR))
+}
+
+// The bps share of `amount`, raised to the minimum but never above `amount`.
+fn fee_amount(rate: &FeeRate, amount: Uint128) -> Uint128 {
+    amount
+        .multiply_ratio(rate.bps, BPS_DENOMINATOR)
+        .max(rate.min)
+        .min(amount)
+}
@@ -0,0 +1,119 @@
+use cosmwasm_std::{Coin, Empty, Env, IbcMsg, StdError, StdResult, Storage};
+use otc_ibc::{BalanceProof, IbcHostMsg};
+
+use crate::notify::notify;
+use crate::state::{Deal, FundingCheck, FUNDING_CHECKS, PENDING_FUNDING_CHECKS, STATE};
+use crate::ContractError;
+
+/// Asks the agents of a deal's remote parties for their balance of the leg they
+/// have to escrow here. Parties whose last check passed, or who have a query
+/// still in flight, are not asked again.
+pub fn request_checks(
+    storage: &mut dyn Storage,
+    env: &Env,
+    deal_id: u64,
+    deal: &Deal,
+) -> StdResult<Vec<IbcMsg>> {
+    let mut packets = vec![];
+    for (party, coin, channel_id) in remote_escrows(deal) {
+        if passed(storage, deal_id, party)? || PENDING_FUNDING_CHECKS.has(storage, (deal_id, party))
+        {
+            continue;
+        }
+        PENDING_FUNDING_CHECKS.save(storage, (deal_id, party), &Empty {})?;
+        packets.extend(notify(
+            storage,
+            env,
+            &[channel_id],
+            &IbcHostMsg::QueryBalance {
+                deal_id,
+                address: party.to_string(),
+                denom: coin.denom.clone(),
+            },
+        )?);
+    }
+    Ok(packets)
+}
+
+/// Clears the query for `party`'s leg once its packet was acked or timed out,
+/// so the check can be run again.
+pub fn query_settled(storage: &mut dyn Storage, deal_id: u64, party: &str) {
+    PENDING_FUNDING_CHECKS.remove(storage, (deal_id, party));
+}
+
+/// Stores the balance an agent reported over `channel_id` against the leg it
+/// was asked about. Proofs that match no remote party of the deal are ignored.
+pub fn record_proof(
+    storage: &mut dyn Storage,
+    deal_id: u64,
+    channel_id: &str,
+    proof: BalanceProof,
+) -> StdResult<()> {
+    let state = STATE.load(storage)?;
+    let deal = state.deal(deal_id)?;
+
+    let leg = remote_escrows(deal)
+        .into_iter()
+        .find(|(party, coin, channel)| {
+            *party == proof.address && coin.denom == proof.denom && *channel == channel_id
+        });
+    if let Some((party, coin, _)) = leg {
+        let check = FundingCheck {
+            party: party.to_string(),
+            required: coin.clone(),
+            available: proof.amount,
+            height: proof.height,
+            sufficient: proof.amount >= coin.amount,
+        };
+        FUNDING_CHECKS.save(storage, (deal_id, party), &check)?;
+    }
+    Ok(())
+}
+
+/// Fails until every remote party of a deal that opted into funding checks has
+/// shown it holds its leg.
+pub fn assert_funding_checked(
+    storage: &dyn Storage,
+    deal_id: u64,
+    deal: &Deal,
+) -> Result<(), ContractError> {
+    if !deal.funding_check {
+        return Ok(());
+    }
+    for (party, _, _) in remote_escrows(deal) {
+        if !passed(storage, deal_id, party)? {
+            return Err(ContractError::Std(StdError::generic_err(format!(
+                "Funding check for {} has not passed",
+                party
+            ))));
+        }
+    }
+    Ok(())
+}
+
+// Remote parties that escrow their leg here rather than in an interchain
+// account, with the coin they owe and the channel they are reached over.
+fn remote_escrows(deal: &Deal) -> Vec<(&str, &Coin, &str)> {
+    let mut escrows = vec![];
+    if !deal.channel_id_recieved_a.is_empty() && deal.seller_leg.is_none() {
+        escrows.push((
+            deal.seller.as_str(),
+            &deal.coin_a,
+            deal.channel_id_recieved_a.as_str(),
+        ));
+    }
+    if !deal.channel_id_recieved_b.is_empty() && deal.buyer_leg.is_none() {
+        escrows.push((
+            deal.buyer.as_str(),
+            &deal.coin_b,
+            deal.channel_id_recieved_b.as_str(),
+        ));
+    }
+    escrows
+}
+
+fn passed(storage: &dyn Storage, deal_id: u64, party: &str) -> StdResult<bool> {
+    Ok(FUNDING_CHECKS
+        .may_load(storage, (deal_id, party))?
+        .is_some_and(|check| check.sufficient))
+}
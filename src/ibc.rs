@@ -1,44 +1,82 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    from_json, DepsMut, Env, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
-    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse, IbcOrder, IbcPacketAckMsg,
-    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse,
+    from_json, Addr, Deps, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannel,
+    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse, IbcMsg,
+    IbcOrder, IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg,
+    IbcReceiveResponse, Order, StdError, Storage,
 };
 
 use crate::{
-    ack::{make_ack_fail, make_ack_success},
-    contract::execute::{deal_complete_ibc, deposit_ibc, withdraw_ibc},
+    ack::{make_ack_fail, make_ack_result, Ack},
+    contract::execute::{
+        accept_deal_ibc, assert_not_paused, cancel_deal_ibc, create_deal_ibc, deal_complete_ibc,
+        lock_remote_leg_ibc, remote_leg_result, set_payout_route_ibc, withdraw_ibc,
+    },
     error::Never,
+    funding::{query_settled, record_proof},
+    ica::{self, ICA_HOST_PORT},
     msg::IbcExecuteMsg,
-    state::Ics20Packet,
+    notify::record_delivery,
+    state::{
+        ChannelInfo, DealProposal, DeliveryStatus, Ics20Packet, PauseScope, PayoutRoute,
+        ALLOWED_COUNTERPARTIES, CHANNELS, INTERCHAIN_ACCOUNTS,
+    },
     ContractError,
 };
+use otc_ibc::{hooks, BalanceProof, IbcHostMsg};
 
-pub const IBC_VERSION: &str = "counter-1";
+/// The app protocol version we propose when opening a channel.
+pub use otc_ibc::IBC_VERSION;
+
+/// Every app protocol version this contract can speak, newest first. A
+/// channel keeps the version negotiated during its handshake, so older
+/// encodings stay usable on existing channels when a new one is added here.
+pub const COMPATIBLE_VERSIONS: &[&str] = &[IBC_VERSION];
 
 /// Handles the `OpenInit` and `OpenTry` parts of the IBC handshake.
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_channel_open(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelOpenMsg,
 ) -> Result<IbcChannelOpenResponse, ContractError> {
-    validate_order_and_version(msg.channel(), msg.counterparty_version())?;
-    Ok(IbcChannelOpenResponse::default())
+    if msg.channel().counterparty_endpoint.port_id == ICA_HOST_PORT {
+        let version = validate_interchain_account(deps.as_ref(), &msg)?;
+        return Ok(Some(Ibc3ChannelOpenResponse { version }));
+    }
+    let version = validate_order_and_version(msg.channel(), msg.counterparty_version())?;
+    validate_counterparty(deps.as_ref(), msg.channel())?;
+    Ok(Some(Ibc3ChannelOpenResponse { version }))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_channel_connect(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelConnectMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
-    validate_order_and_version(msg.channel(), msg.counterparty_version())?;
+    if msg.channel().counterparty_endpoint.port_id == ICA_HOST_PORT {
+        return connect_interchain_account(deps, msg);
+    }
+    let version = validate_order_and_version(msg.channel(), msg.counterparty_version())?;
+    let transfer_channel = validate_counterparty(deps.as_ref(), msg.channel())?;
 
-    // Initialize the count for this channel to zero.
+    // Remember the channel so incoming packets can be checked against it.
     let channel = msg.channel().endpoint.channel_id.clone();
-    // CONNECTION_COUNTS.save(deps.storage, channel.clone(), &0)?;
+    CHANNELS.save(
+        deps.storage,
+        &channel,
+        &ChannelInfo {
+            channel_id: channel.clone(),
+            connection_id: msg.channel().connection_id.clone(),
+            counterparty_port_id: msg.channel().counterparty_endpoint.port_id.clone(),
+            counterparty_channel_id: msg.channel().counterparty_endpoint.channel_id.clone(),
+            version,
+            transfer_channel,
+            frozen: false,
+        },
+    )?;
 
     Ok(IbcBasicResponse::new()
         .add_attribute("method", "ibc_channel_connect")
@@ -47,13 +85,18 @@ pub fn ibc_channel_connect(
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_channel_close(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelCloseMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     let channel = msg.channel().endpoint.channel_id.clone();
     // Reset the state for the channel.
-    // CONNECTION_COUNTS.remove(deps.storage, channel.clone());
+    CHANNELS.remove(deps.storage, &channel);
+    if let Some(mut account) = ica::account_on_channel(deps.storage, &channel)? {
+        // Registering again reopens a channel to the same account
+        account.channel_id = None;
+        INTERCHAIN_ACCOUNTS.save(deps.storage, &account.connection_id.clone(), &account)?;
+    }
     Ok(IbcBasicResponse::new()
         .add_attribute("method", "ibc_channel_close")
         .add_attribute("channel", channel))
@@ -73,7 +116,7 @@ pub fn ibc_packet_receive(
         Err(error) => Ok(IbcReceiveResponse::new()
             .add_attribute("method", "ibc_packet_receive")
             .add_attribute("error", error.to_string())
-            .set_ack(make_ack_fail(error.to_string()))),
+            .set_ack(make_ack_fail(error.code(), error.to_string()))),
     }
 }
 
@@ -85,83 +128,207 @@ pub fn do_ibc_packet_receive(
     // The channel this packet is being relayed along on this chain.
     let channel = msg.packet.dest.channel_id;
     // let opposite_channel = msg.packet.src.channel_id;
+    validate_channel_enabled(deps.as_ref(), &channel)?;
     let msg: IbcExecuteMsg = from_json(&msg.packet.data)?;
+    // Remote parties can still get their deposits back while paused
+    if !matches!(msg, IbcExecuteMsg::Withdraw { .. }) {
+        assert_not_paused(deps.storage, PauseScope::IbcReceive)?;
+    }
 
     match msg {
-        IbcExecuteMsg::Deposit { packet20 } => execute_deposit(deps, env, channel, packet20),
-        IbcExecuteMsg::Withdraw { packet20 } => execute_withdraw(deps, env, channel, packet20),
-        IbcExecuteMsg::CompleteDeal { packet20 } => execute_deal(deps, env, channel, packet20),
+        IbcExecuteMsg::CreateDeal { sender, proposal } => {
+            execute_create_deal(deps, env, channel, sender, proposal)
+        }
+        IbcExecuteMsg::AcceptDeal { deal_id, sender } => {
+            execute_accept_deal(deps, env, channel, deal_id, sender)
+        }
+        IbcExecuteMsg::CancelDeal { deal_id, sender } => {
+            execute_cancel_deal(deps, env, channel, deal_id, sender)
+        }
+        IbcExecuteMsg::Withdraw { deal_id, packet20 } => {
+            execute_withdraw(deps, env, channel, deal_id, packet20)
+        }
+        IbcExecuteMsg::CompleteDeal { deal_id, packet20 } => {
+            execute_deal(deps, env, channel, deal_id, packet20)
+        }
+        IbcExecuteMsg::LockRemoteLeg { deal_id, sender } => {
+            execute_lock_remote_leg(deps, env, channel, deal_id, sender)
+        }
+        IbcExecuteMsg::SetPayoutRoute {
+            deal_id,
+            sender,
+            route,
+        } => execute_set_payout_route(deps, env, channel, deal_id, sender, route),
     }
 }
 
-fn execute_deposit(
+fn execute_create_deal(
     deps: DepsMut,
     env: Env,
     channel: String,
-    packet: Ics20Packet,
+    sender: String,
+    proposal: DealProposal,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    deposit_ibc(deps, env, channel, packet)?;
+    let res = create_deal_ibc(deps, env, channel, sender, proposal)?;
     Ok(IbcReceiveResponse::new()
-        .add_attribute("method", "execute_deposit")
-        .set_ack(make_ack_success()))
+        .add_submessages(res.messages)
+        .add_attributes(res.attributes)
+        .add_attribute("method", "execute_create_deal")
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
+}
+
+fn execute_accept_deal(
+    deps: DepsMut,
+    env: Env,
+    channel: String,
+    deal_id: u64,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let res = accept_deal_ibc(deps, env, channel, deal_id, sender)?;
+    Ok(IbcReceiveResponse::new()
+        .add_attributes(res.attributes)
+        .add_attribute("method", "execute_accept_deal")
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
+}
+
+fn execute_cancel_deal(
+    deps: DepsMut,
+    env: Env,
+    channel: String,
+    deal_id: u64,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let res = cancel_deal_ibc(deps, env, channel, deal_id, sender)?;
+    Ok(IbcReceiveResponse::new()
+        .add_submessages(res.messages)
+        .add_attributes(res.attributes)
+        .add_attribute("method", "execute_cancel_deal")
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
 }
 
 fn execute_withdraw(
     deps: DepsMut,
     env: Env,
     channel: String,
+    deal_id: u64,
     packet: Ics20Packet,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    withdraw_ibc(deps, env, channel, packet)?;
+    let res = withdraw_ibc(deps, env, channel, deal_id, packet)?;
     Ok(IbcReceiveResponse::new()
+        .add_submessages(res.messages)
+        .add_attributes(res.attributes)
         .add_attribute("method", "execute_withdraw")
-        .set_ack(make_ack_success()))
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
 }
 
 fn execute_deal(
     deps: DepsMut,
     env: Env,
     channel: String,
+    deal_id: u64,
     packet: Ics20Packet,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    deal_complete_ibc(deps, env, channel, packet)?;
+    let res = deal_complete_ibc(deps, env, channel, deal_id, packet)?;
     Ok(IbcReceiveResponse::new()
+        .add_submessages(res.messages)
+        .add_attributes(res.attributes)
         .add_attribute("method", "execute_deal")
-        .set_ack(make_ack_success()))
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
+}
+
+fn execute_lock_remote_leg(
+    deps: DepsMut,
+    env: Env,
+    channel: String,
+    deal_id: u64,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let res = lock_remote_leg_ibc(deps, env, channel, deal_id, sender)?;
+    Ok(IbcReceiveResponse::new()
+        .add_submessages(res.messages)
+        .add_attributes(res.attributes)
+        .add_attribute("method", "execute_lock_remote_leg")
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
+}
+
+fn execute_set_payout_route(
+    deps: DepsMut,
+    env: Env,
+    channel: String,
+    deal_id: u64,
+    sender: String,
+    route: Option<PayoutRoute>,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let res = set_payout_route_ibc(deps, env, channel, deal_id, sender, route)?;
+    Ok(IbcReceiveResponse::new()
+        .add_attributes(res.attributes)
+        .add_attribute("method", "execute_set_payout_route")
+        .set_ack(make_ack_result(res.data.unwrap_or_default())))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_packet_ack(
-    _deps: DepsMut,
-    _env: Env,
-    _ack: IbcPacketAckMsg,
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketAckMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
-    // Nothing to do here. We don't keep any state about the other
-    // chain, just deliver messages so nothing to update.
-    //
-    // If we did care about how the other chain received our message
-    // we could deserialize the data field into an `Ack` and inspect
-    // it.
-    Ok(IbcBasicResponse::new().add_attribute("method", "ibc_packet_ack"))
+    // Interchain account hosts acknowledge with the same success and error encoding.
+    let ack: Ack = from_json(&msg.acknowledgement.data)?;
+    let packets = packet_settled(deps.storage, &env, &msg.original_packet, Some(ack))?;
+    Ok(IbcBasicResponse::new()
+        .add_attribute("method", "ibc_packet_ack")
+        .add_messages(packets))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn ibc_packet_timeout(
-    _deps: DepsMut,
-    _env: Env,
-    _msg: IbcPacketTimeoutMsg,
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
-    // As with ack above, nothing to do here. If we cared about
-    // keeping track of state between the two chains then we'd want to
-    // respond to this likely as it means that the packet in question
-    // isn't going anywhere.
-    Ok(IbcBasicResponse::new().add_attribute("method", "ibc_packet_timeout"))
+    let packets = packet_settled(deps.storage, &env, &msg.packet, None)?;
+    Ok(IbcBasicResponse::new()
+        .add_attribute("method", "ibc_packet_timeout")
+        .add_messages(packets))
+}
+
+// Applies interchain account transaction results to their deal, and tracks
+// whether the remote agent accepted our deal notifications and balance queries.
+fn packet_settled(
+    storage: &mut dyn Storage,
+    env: &Env,
+    packet: &IbcPacket,
+    ack: Option<Ack>,
+) -> Result<Vec<IbcMsg>, ContractError> {
+    let status = match ack {
+        Some(Ack::Result(_)) => DeliveryStatus::Delivered,
+        Some(Ack::Error(_)) => DeliveryStatus::Failed,
+        None => DeliveryStatus::TimedOut,
+    };
+    if ica::account_on_channel(storage, &packet.src.channel_id)?.is_some() {
+        let memo = ica::parse_memo(&packet.data)?;
+        let success = status == DeliveryStatus::Delivered;
+        return remote_leg_result(storage, env, memo, success);
+    }
+
+    // Agents answer balance queries in their ack
+    if let Ok(IbcHostMsg::QueryBalance {
+        deal_id, address, ..
+    }) = from_json(&packet.data)
+    {
+        query_settled(storage, deal_id, &address);
+        if let Some(proof) = ack.and_then(|ack| ack.outcome::<BalanceProof>().ok()) {
+            record_proof(storage, deal_id, &packet.src.channel_id, proof)?;
+        }
+    }
+    record_delivery(storage, packet, status)?;
+    Ok(vec![])
 }
 
 pub fn validate_order_and_version(
     channel: &IbcChannel,
     counterparty_version: Option<&str>,
-) -> Result<(), ContractError> {
+) -> Result<String, ContractError> {
     // We expect an unordered channel here. Ordered channels have the
     // property that if a message is lost the entire channel will stop
     // working until you start it again.
@@ -169,29 +336,124 @@ pub fn validate_order_and_version(
         return Err(ContractError::OrderedChannel {});
     }
 
-    if channel.version != IBC_VERSION {
-        return Err(ContractError::InvalidVersion {
-            actual: channel.version.to_string(),
-            expected: IBC_VERSION.to_string(),
-        });
-    }
-
     // Make sure that we're talking with a counterparty who speaks the
     // same "protocol" as us.
     //
     // For a connection between chain A and chain B being established
     // by chain A, chain B knows counterparty information during
     // `OpenTry` and chain A knows counterparty information during
-    // `OpenAck`. We verify it when we have it but when we don't it's
-    // alright.
+    // `OpenAck`. On `OpenTry` we answer with the version the
+    // counterparty proposed, as long as we speak it.
     if let Some(counterparty_version) = counterparty_version {
-        if counterparty_version != IBC_VERSION {
+        validate_version(counterparty_version)?;
+        if !channel.version.is_empty() && channel.version != counterparty_version {
             return Err(ContractError::InvalidVersion {
                 actual: counterparty_version.to_string(),
-                expected: IBC_VERSION.to_string(),
+                expected: channel.version.clone(),
             });
         }
+        return Ok(counterparty_version.to_string());
     }
 
+    // On `OpenInit` the relayer may leave the version empty, in which
+    // case we propose our latest one.
+    if channel.version.is_empty() {
+        return Ok(IBC_VERSION.to_string());
+    }
+    validate_version(&channel.version)?;
+    Ok(channel.version.clone())
+}
+
+fn validate_version(version: &str) -> Result<(), ContractError> {
+    if !COMPATIBLE_VERSIONS.contains(&version) {
+        return Err(ContractError::InvalidVersion {
+            actual: version.to_string(),
+            expected: COMPATIBLE_VERSIONS.join(", "),
+        });
+    }
     Ok(())
 }
+
+/// Checks that the counterparty of a channel being opened is on the admin's
+/// allowlist, returning the transfer channel configured for it.
+pub fn validate_counterparty(deps: Deps, channel: &IbcChannel) -> Result<String, ContractError> {
+    let connection_id = channel.connection_id.as_str();
+    let port_id = channel.counterparty_endpoint.port_id.as_str();
+    ALLOWED_COUNTERPARTIES
+        .may_load(deps.storage, (connection_id, port_id))?
+        .ok_or_else(|| ContractError::CounterpartyNotAllowed {
+            connection_id: connection_id.to_string(),
+            port_id: port_id.to_string(),
+        })
+}
+
+/// Rejects packets on channels that are unknown, frozen, or whose
+/// counterparty has since been removed from the allowlist.
+pub fn validate_channel_enabled(deps: Deps, channel_id: &str) -> Result<(), ContractError> {
+    let disabled = || ContractError::ChannelDisabled {
+        channel_id: channel_id.to_string(),
+    };
+    let channel = CHANNELS
+        .may_load(deps.storage, channel_id)?
+        .ok_or_else(disabled)?;
+    let allowed = ALLOWED_COUNTERPARTIES.has(
+        deps.storage,
+        (&channel.connection_id, &channel.counterparty_port_id),
+    );
+    if channel.frozen || !allowed {
+        return Err(disabled());
+    }
+    Ok(())
+}
+
+/// The channel of the remote agent ibc-hooks is calling the contract for,
+/// found from the intermediary address it calls from.
+pub fn hook_channel(deps: Deps, sender: &Addr) -> Result<String, ContractError> {
+    for item in CHANNELS.range(deps.storage, None, None, Order::Ascending) {
+        let (channel_id, channel) = item?;
+        let Some(agent) = channel.counterparty_port_id.strip_prefix("wasm.") else {
+            continue;
+        };
+        if hooks::is_intermediate_sender(sender.as_str(), &channel.transfer_channel, agent) {
+            return Ok(channel_id);
+        }
+    }
+    Err(ContractError::Unauthorized {})
+}
+
+/// Accepts an interchain account channel the admin registered, opened from our side.
+fn validate_interchain_account(
+    deps: Deps,
+    msg: &IbcChannelOpenMsg,
+) -> Result<String, ContractError> {
+    let channel = msg.channel();
+    if msg.counterparty_version().is_some()
+        || !INTERCHAIN_ACCOUNTS.has(deps.storage, &channel.connection_id)
+    {
+        return Err(ContractError::CounterpartyNotAllowed {
+            connection_id: channel.connection_id.clone(),
+            port_id: channel.counterparty_endpoint.port_id.clone(),
+        });
+    }
+    ica::validate_open(channel)
+}
+
+/// Records the account address the host chain assigned us.
+fn connect_interchain_account(
+    deps: DepsMut,
+    msg: IbcChannelConnectMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel = msg.channel();
+    let address = ica::account_address(msg.counterparty_version())?;
+    let mut account = INTERCHAIN_ACCOUNTS
+        .may_load(deps.storage, &channel.connection_id)?
+        .ok_or_else(|| StdError::generic_err("Interchain account not registered"))?;
+    account.channel_id = Some(channel.endpoint.channel_id.clone());
+    account.address = address.clone();
+    INTERCHAIN_ACCOUNTS.save(deps.storage, &channel.connection_id, &account)?;
+
+    Ok(IbcBasicResponse::new()
+        .add_attribute("method", "ibc_channel_connect")
+        .add_attribute("channel_id", &channel.endpoint.channel_id)
+        .add_attribute("interchain_account", address))
+}
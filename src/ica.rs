@@ -0,0 +1,238 @@
+use cosmwasm_std::{
+    from_json, to_json_binary, to_json_string, Binary, Coin, CosmosMsg, Env, IbcChannel, IbcMsg,
+    Order, StdError, StdResult, Storage,
+};
+use otc_ibc::proto;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::notify::PACKET_LIFETIME;
+use crate::state::{InterchainAccount, Side, INTERCHAIN_ACCOUNTS};
+use crate::ContractError;
+
+/// Port of the ICS-27 host module on the counterparty chain.
+pub const ICA_HOST_PORT: &str = "icahost";
+pub const ICA_VERSION: &str = "ics27-1";
+/// Transactions are encoded as proto3 JSON so they can be built without protobuf.
+pub const ICA_ENCODING: &str = "proto3json";
+pub const ICA_TX_TYPE: &str = "sdk_multi_msg";
+
+/// The ICS-27 channel version, exchanged as JSON during the handshake.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct IcaMetadata {
+    pub version: String,
+    pub controller_connection_id: String,
+    pub host_connection_id: String,
+    /// the interchain account address, filled in by the host on `OpenTry`
+    #[serde(default)]
+    pub address: String,
+    pub encoding: String,
+    pub tx_type: String,
+}
+
+/// What an interchain account transaction does for a deal leg, carried in
+/// the packet memo so its acknowledgement can be matched back to the deal.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IcaAction {
+    /// pulls the leg into the account from the party that owns it
+    Lock,
+    /// pays the leg out to the counterparty
+    Payout,
+    /// returns the leg to the party that locked it
+    Refund,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct IcaMemo {
+    pub deal_id: u64,
+    pub side: Side,
+    pub action: IcaAction,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IcaPacketData {
+    #[serde(rename = "type")]
+    kind: String,
+    data: Binary,
+    memo: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CosmosTx {
+    messages: Vec<TxMsg>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TxMsg {
+    Send(MsgSend),
+    Exec(MsgExec),
+}
+
+// Runs `msgs` under authz grants given to `grantee`.
+#[derive(Serialize, Deserialize)]
+struct MsgExec {
+    #[serde(rename = "@type")]
+    type_url: String,
+    grantee: String,
+    msgs: Vec<MsgSend>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MsgSend {
+    #[serde(rename = "@type")]
+    type_url: String,
+    from_address: String,
+    to_address: String,
+    amount: Vec<Coin>,
+}
+
+/// Builds the `MsgChannelOpenInit` that opens a controller channel for
+/// `account` from our port, proposing an ordered channel.
+pub fn register(env: &Env, account: &InterchainAccount) -> StdResult<CosmosMsg> {
+    let metadata = IcaMetadata {
+        version: ICA_VERSION.to_string(),
+        controller_connection_id: account.connection_id.clone(),
+        host_connection_id: account.host_connection_id.clone(),
+        address: String::new(),
+        encoding: ICA_ENCODING.to_string(),
+        tx_type: ICA_TX_TYPE.to_string(),
+    };
+
+    let mut counterparty = vec![];
+    proto::string(&mut counterparty, 1, ICA_HOST_PORT);
+
+    let mut channel = vec![];
+    // STATE_INIT and ORDER_ORDERED
+    proto::uint(&mut channel, 1, 1);
+    proto::uint(&mut channel, 2, 2);
+    proto::message(&mut channel, 3, &counterparty);
+    proto::string(&mut channel, 4, &account.connection_id);
+    proto::string(&mut channel, 5, &to_json_string(&metadata)?);
+
+    let contract = env.contract.address.as_str();
+    let mut msg = vec![];
+    proto::string(&mut msg, 1, &format!("wasm.{}", contract));
+    proto::message(&mut msg, 2, &channel);
+    proto::string(&mut msg, 3, contract);
+
+    Ok(CosmosMsg::Stargate {
+        type_url: "/ibc.core.channel.v1.MsgChannelOpenInit".to_string(),
+        value: msg.into(),
+    })
+}
+
+/// Checks the version proposed on `OpenInit` of an interchain account
+/// channel, returning the version to answer with.
+pub fn validate_open(channel: &IbcChannel) -> Result<String, ContractError> {
+    // Either ordering is fine: ibc-go hosts from v8.1 also accept unordered channels.
+    let metadata: IcaMetadata = from_json(channel.version.as_bytes())?;
+    if metadata.version != ICA_VERSION
+        || metadata.controller_connection_id != channel.connection_id
+        || metadata.encoding != ICA_ENCODING
+        || metadata.tx_type != ICA_TX_TYPE
+    {
+        return Err(ContractError::InvalidVersion {
+            actual: channel.version.clone(),
+            expected: format!("{} with {} encoding", ICA_VERSION, ICA_ENCODING),
+        });
+    }
+    Ok(channel.version.clone())
+}
+
+/// Reads the interchain account address the host put in its version on `OpenAck`.
+pub fn account_address(counterparty_version: Option<&str>) -> Result<String, ContractError> {
+    let version = counterparty_version
+        .ok_or_else(|| StdError::generic_err("interchain account host sent no version"))?;
+    let metadata: IcaMetadata = from_json(version.as_bytes())?;
+    if metadata.address.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err(
+            "interchain account host sent no address",
+        )));
+    }
+    Ok(metadata.address)
+}
+
+/// Builds a packet making `account` send `amount` on its chain.
+pub fn send_tx(
+    env: &Env,
+    account: &InterchainAccount,
+    to_address: &str,
+    amount: Coin,
+    memo: &IcaMemo,
+) -> StdResult<IbcMsg> {
+    let send = msg_send(&account.address, to_address, amount);
+    execute_tx(env, account, TxMsg::Send(send), memo)
+}
+
+/// Builds a packet making `account` take `amount` from `from_address` on its
+/// chain, under the send authorization `from_address` granted it.
+pub fn pull_tx(
+    env: &Env,
+    account: &InterchainAccount,
+    from_address: &str,
+    amount: Coin,
+    memo: &IcaMemo,
+) -> StdResult<IbcMsg> {
+    let exec = MsgExec {
+        type_url: "/cosmos.authz.v1beta1.MsgExec".to_string(),
+        grantee: account.address.clone(),
+        msgs: vec![msg_send(from_address, &account.address, amount)],
+    };
+    execute_tx(env, account, TxMsg::Exec(exec), memo)
+}
+
+fn msg_send(from_address: &str, to_address: &str, amount: Coin) -> MsgSend {
+    MsgSend {
+        type_url: "/cosmos.bank.v1beta1.MsgSend".to_string(),
+        from_address: from_address.to_string(),
+        to_address: to_address.to_string(),
+        amount: vec![amount],
+    }
+}
+
+fn execute_tx(
+    env: &Env,
+    account: &InterchainAccount,
+    msg: TxMsg,
+    memo: &IcaMemo,
+) -> StdResult<IbcMsg> {
+    let channel_id = account
+        .channel_id
+        .as_deref()
+        .ok_or_else(|| StdError::generic_err("Interchain account has no open channel"))?;
+    let tx = CosmosTx {
+        messages: vec![msg],
+    };
+    let packet = IcaPacketData {
+        kind: "TYPE_EXECUTE_TX".to_string(),
+        data: to_json_binary(&tx)?,
+        memo: to_json_string(memo)?,
+    };
+    Ok(IbcMsg::SendPacket {
+        channel_id: channel_id.to_string(),
+        data: to_json_binary(&packet)?,
+        timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+    })
+}
+
+/// Recovers the deal leg an interchain account packet was sent for.
+pub fn parse_memo(data: &Binary) -> StdResult<IcaMemo> {
+    let packet: IcaPacketData = from_json(data)?;
+    from_json(packet.memo.as_bytes())
+}
+
+/// Finds the interchain account controlled over `channel_id`, if any.
+pub fn account_on_channel(
+    storage: &dyn Storage,
+    channel_id: &str,
+) -> StdResult<Option<InterchainAccount>> {
+    for item in INTERCHAIN_ACCOUNTS.range(storage, None, None, Order::Ascending) {
+        let (_, account) = item?;
+        if account.channel_id.as_deref() == Some(channel_id) {
+            return Ok(Some(account));
+        }
+    }
+    Ok(None)
+}
@@ -72,13 +72,34 @@ mod tests {
 
     mod assignment {
         use super::*;
-        use crate::msg::ExecuteMsg;
+        use crate::msg::{DealTerms, ExecuteMsg};
+
+        #[test]
+        fn baseline_create_deal_still_parses() {
+            // A create_deal from clients predating the optional terms
+            let json = r#"{"create_deal":{"seller":"seller","buyer":"buyer",
+                "coin_a":{"denom":"token1","amount":"100"},
+                "coin_b":{"denom":"token2","amount":"200"},
+                "expiry":1704400324}}"#;
+            let msg: ExecuteMsg = cosmwasm_std::from_json(json.as_bytes()).unwrap();
+            assert_eq!(
+                msg,
+                ExecuteMsg::CreateDeal(DealTerms {
+                    seller: "seller".to_string(),
+                    buyer: "buyer".to_string(),
+                    coin_a: Coin::new(100u128, "token1"),
+                    coin_b: Coin::new(200u128, "token2"),
+                    expiry: 1704400324,
+                    ..DealTerms::default()
+                })
+            );
+        }
 
         #[test]
         fn deal_succeeds() {
             let (mut app, cw_template_contract) = proper_instantiate();
 
-            let msg = ExecuteMsg::CreateDeal {
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
                 seller: SELLER.to_string(),
                 buyer: BUYER.to_string(),
                 coin_a: Coin {
@@ -90,11 +111,12 @@ mod tests {
                     amount: Uint128::new(200),
                 },
                 expiry: 1704400324,
-            };
+                ..DealTerms::default()
+            });
             let cosmos_msg = cw_template_contract.call(msg).unwrap();
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
 
-            let msg = ExecuteMsg::Deposit {};
+            let msg = ExecuteMsg::Deposit { deal_id: 0 };
 
             app.execute_contract(
                 Addr::unchecked(SELLER),
@@ -113,7 +135,7 @@ mod tests {
             .unwrap();
 
             // Complete deal
-            let msg = ExecuteMsg::CompleteDeal {};
+            let msg = ExecuteMsg::CompleteDeal { deal_id: 0 };
             app.execute_contract(
                 Addr::unchecked(SELLER),
                 cw_template_contract.addr(),
@@ -161,7 +183,7 @@ mod tests {
         fn withdrawal_fails_if_no_deposits() {
             let (mut app, cw_template_contract) = proper_instantiate();
 
-            let msg = ExecuteMsg::CreateDeal {
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
                 seller: SELLER.to_string(),
                 buyer: BUYER.to_string(),
                 coin_a: Coin {
@@ -173,11 +195,12 @@ mod tests {
                     amount: Uint128::new(200),
                 },
                 expiry: 1704400324,
-            };
+                ..DealTerms::default()
+            });
             let cosmos_msg = cw_template_contract.call(msg).unwrap();
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
 
-            let msg = ExecuteMsg::Withdraw {};
+            let msg = ExecuteMsg::Withdraw { deal_id: 0 };
 
             let res = app.execute_contract(
                 Addr::unchecked(SELLER),
@@ -187,14 +210,14 @@ mod tests {
             );
 
             // Withdraw before deposit should fail
-            assert_eq!(res.is_err(), true);
+            assert!(res.is_err());
         }
 
         #[test]
         fn withdrawal_fails_if_both_deposit() {
             let (mut app, cw_template_contract) = proper_instantiate();
 
-            let msg = ExecuteMsg::CreateDeal {
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
                 seller: SELLER.to_string(),
                 buyer: BUYER.to_string(),
                 coin_a: Coin {
@@ -206,11 +229,12 @@ mod tests {
                     amount: Uint128::new(200),
                 },
                 expiry: 1704400324,
-            };
+                ..DealTerms::default()
+            });
             let cosmos_msg = cw_template_contract.call(msg).unwrap();
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
 
-            let msg = ExecuteMsg::Deposit {};
+            let msg = ExecuteMsg::Deposit { deal_id: 0 };
             app.execute_contract(
                 Addr::unchecked(SELLER),
                 cw_template_contract.addr(),
@@ -227,7 +251,7 @@ mod tests {
             )
             .unwrap();
 
-            let msg = ExecuteMsg::Withdraw {};
+            let msg = ExecuteMsg::Withdraw { deal_id: 0 };
 
             let res = app.execute_contract(
                 Addr::unchecked(SELLER),
@@ -237,14 +261,14 @@ mod tests {
             );
 
             // Withdraw after both deposits should also fail
-            assert_eq!(res.is_err(), true);
+            assert!(res.is_err());
         }
 
         #[test]
         fn withdrawal_succeeds_if_one_party_deposits() {
             let (mut app, cw_template_contract) = proper_instantiate();
 
-            let msg = ExecuteMsg::CreateDeal {
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
                 seller: SELLER.to_string(),
                 buyer: BUYER.to_string(),
                 coin_a: Coin {
@@ -256,11 +280,12 @@ mod tests {
                     amount: Uint128::new(200),
                 },
                 expiry: 1704400324,
-            };
+                ..DealTerms::default()
+            });
             let cosmos_msg = cw_template_contract.call(msg).unwrap();
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
 
-            let msg = ExecuteMsg::Deposit {};
+            let msg = ExecuteMsg::Deposit { deal_id: 0 };
             app.execute_contract(
                 Addr::unchecked(SELLER),
                 cw_template_contract.addr(),
@@ -269,7 +294,7 @@ mod tests {
             )
             .unwrap();
 
-            let msg = ExecuteMsg::Withdraw {};
+            let msg = ExecuteMsg::Withdraw { deal_id: 0 };
 
             let res = app.execute_contract(
                 Addr::unchecked(SELLER),
@@ -279,9 +304,9 @@ mod tests {
             );
 
             // Withdrawal after one party deposits, should succeed
-            assert_eq!(res.is_ok(), true);
+            assert!(res.is_ok());
 
-            let msg = ExecuteMsg::Deposit {};
+            let msg = ExecuteMsg::Deposit { deal_id: 0 };
             app.execute_contract(
                 Addr::unchecked(BUYER),
                 cw_template_contract.addr(),
@@ -290,7 +315,7 @@ mod tests {
             )
             .unwrap();
 
-            let msg = ExecuteMsg::Withdraw {};
+            let msg = ExecuteMsg::Withdraw { deal_id: 0 };
 
             let res = app.execute_contract(
                 Addr::unchecked(BUYER),
@@ -299,7 +324,3062 @@ mod tests {
                 &[],
             );
 
-            assert_eq!(res.is_ok(), true);
+            assert!(res.is_ok());
+        }
+
+        #[test]
+        fn deposit_without_coins_fails() {
+            let (mut app, cw_template_contract) = proper_instantiate();
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            });
+            let cosmos_msg = cw_template_contract.call(msg).unwrap();
+            app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
+
+            let err = app
+                .execute_contract(
+                    Addr::unchecked(SELLER),
+                    cw_template_contract.addr(),
+                    &ExecuteMsg::Deposit { deal_id: 0 },
+                    &[],
+                )
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "Generic error: Exactly one coin must be deposited"
+            );
+        }
+
+        #[test]
+        fn instructions_apply_to_the_named_deal() {
+            let (mut app, cw_template_contract) = proper_instantiate();
+
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            };
+            for buyer in [BUYER, USER] {
+                let msg = ExecuteMsg::CreateDeal(DealTerms {
+                    buyer: buyer.to_string(),
+                    ..terms.clone()
+                });
+                let cosmos_msg = cw_template_contract.call(msg).unwrap();
+                app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
+            }
+
+            let seller = |app: &mut App, msg: ExecuteMsg, funds: Vec<Coin>| {
+                app.execute_contract(
+                    Addr::unchecked(SELLER),
+                    cw_template_contract.addr(),
+                    &msg,
+                    &funds,
+                )
+            };
+            let coin_a = Coin::new(100u128, NATIVE_DENOM1);
+            let msg = ExecuteMsg::Deposit { deal_id: 1 };
+            let err = seller(&mut app, msg, vec![Coin::new(50u128, NATIVE_DENOM1)]).unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "Generic error: Incorrect coin deposited"
+            );
+            let msg = ExecuteMsg::Deposit { deal_id: 1 };
+            seller(&mut app, msg, vec![coin_a.clone()]).unwrap();
+
+            // Only the second deal was funded
+            let msg = ExecuteMsg::Withdraw { deal_id: 0 };
+            seller(&mut app, msg, vec![]).unwrap_err();
+            let msg = ExecuteMsg::Withdraw { deal_id: 1 };
+            seller(&mut app, msg, vec![]).unwrap();
+            let msg = ExecuteMsg::Deposit { deal_id: 2 };
+            seller(&mut app, msg, vec![coin_a]).unwrap_err();
+        }
+    }
+    mod channels {
+        use super::*;
+        use crate::ack::{Ack, AckError, AckResult, ErrorCode};
+        use crate::contract::{execute, instantiate, query};
+        use crate::ibc::{ibc_channel_connect, ibc_channel_open, ibc_packet_receive, IBC_VERSION};
+        use crate::msg::ChannelsResponse;
+        use crate::msg::{ExecuteMsg, IbcExecuteMsg, QueryMsg};
+        use crate::state::Ics20Packet;
+        use crate::ContractError;
+        use cosmwasm_std::testing::{
+            mock_dependencies, mock_env, mock_ibc_channel, mock_ibc_channel_connect_ack,
+            mock_ibc_channel_open_init, mock_ibc_channel_open_try, mock_ibc_packet_recv, mock_info,
+            MockApi, MockQuerier, MockStorage,
+        };
+        use cosmwasm_std::{
+            from_json, Ibc3ChannelOpenResponse, IbcChannelConnectMsg, IbcOrder, OwnedDeps, Response,
+        };
+        use otc_ibc::hooks::intermediate_sender;
+
+        pub const CHANNEL: &str = "channel-1";
+        // Connection and port used by cosmwasm_std::testing::mock_ibc_channel
+        const CONNECTION: &str = "connection-2";
+        const COUNTERPARTY_PORT: &str = "their_port";
+        pub const TRANSFER_CHANNEL: &str = "channel-0";
+
+        pub fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+            let mut deps = mock_dependencies();
+            instantiate(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADMIN, &[]),
+                InstantiateMsg {},
+            )
+            .unwrap();
+            deps
+        }
+
+        pub fn allow_counterparty(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>) {
+            let msg = ExecuteMsg::AddChannel {
+                connection_id: CONNECTION.to_string(),
+                counterparty_port_id: COUNTERPARTY_PORT.to_string(),
+                transfer_channel: TRANSFER_CHANNEL.to_string(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        }
+
+        /// Allows the remote agent contract `agent` and connects `channel` to it.
+        pub fn connect_agent(
+            deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+            channel: &str,
+            agent: &str,
+        ) {
+            let port = format!("wasm.{}", agent);
+            let msg = ExecuteMsg::AddChannel {
+                connection_id: CONNECTION.to_string(),
+                counterparty_port_id: port.clone(),
+                transfer_channel: TRANSFER_CHANNEL.to_string(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+            let mut ibc_channel = mock_ibc_channel(channel, IbcOrder::Unordered, IBC_VERSION);
+            ibc_channel.counterparty_endpoint.port_id = port;
+            let connect = IbcChannelConnectMsg::new_ack(ibc_channel, IBC_VERSION);
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+        }
+
+        /// Delivers `coin` sent by `sender` for deal `deal_id` through `agent`
+        /// the way ibc-hooks does.
+        pub fn deposit_ibc(
+            deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+            agent: &str,
+            deal_id: u64,
+            sender: &str,
+            coin: Coin,
+        ) -> Result<Response, ContractError> {
+            let hook_sender = intermediate_sender("cosmwasm", TRANSFER_CHANNEL, agent).unwrap();
+            let msg = ExecuteMsg::DepositIbc {
+                deal_id,
+                sender: sender.to_string(),
+            };
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(&hook_sender, &[coin]),
+                msg,
+            )
+        }
+
+        #[test]
+        fn handshake_requires_allowlisted_counterparty() {
+            let mut deps = setup();
+
+            let open = mock_ibc_channel_open_init(CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+            let err = ibc_channel_open(deps.as_mut(), mock_env(), open.clone()).unwrap_err();
+            assert!(matches!(err, ContractError::CounterpartyNotAllowed { .. }));
+
+            // Only the admin may change the allowlist
+            let msg = ExecuteMsg::AddChannel {
+                connection_id: CONNECTION.to_string(),
+                counterparty_port_id: COUNTERPARTY_PORT.to_string(),
+                transfer_channel: TRANSFER_CHANNEL.to_string(),
+            };
+            let err = execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::Unauthorized {}));
+
+            allow_counterparty(&mut deps);
+            ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap();
+        }
+
+        #[test]
+        fn handshake_negotiates_app_version() {
+            let mut deps = setup();
+            allow_counterparty(&mut deps);
+
+            // An empty version on OpenInit gets our current protocol proposed
+            let open = mock_ibc_channel_open_init(CHANNEL, IbcOrder::Unordered, "");
+            let res = ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap();
+            assert_eq!(
+                res,
+                Some(Ibc3ChannelOpenResponse {
+                    version: IBC_VERSION.to_string()
+                })
+            );
+
+            // The template's old version is not spoken anymore
+            let open = mock_ibc_channel_open_try(CHANNEL, IbcOrder::Unordered, "counter-1");
+            let err = ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidVersion { .. }));
+
+            let open = mock_ibc_channel_open_init(CHANNEL, IbcOrder::Ordered, IBC_VERSION);
+            let err = ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap_err();
+            assert!(matches!(err, ContractError::OrderedChannel {}));
+
+            // The negotiated version is stored with the channel
+            let connect = mock_ibc_channel_connect_ack(CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+            let res = query(deps.as_ref(), mock_env(), QueryMsg::Channels {}).unwrap();
+            let res: ChannelsResponse = from_json(res).unwrap();
+            assert_eq!(res.channels[0].version, IBC_VERSION);
+        }
+
+        #[test]
+        fn packets_rejected_on_frozen_or_removed_channel() {
+            let mut deps = setup();
+            allow_counterparty(&mut deps);
+
+            let connect = mock_ibc_channel_connect_ack(CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+
+            let packet = IbcExecuteMsg::CompleteDeal {
+                deal_id: 0,
+                packet20: Ics20Packet::default(),
+            };
+            let disabled = AckError {
+                code: ErrorCode::ChannelDisabled,
+                message: ContractError::ChannelDisabled {
+                    channel_id: CHANNEL.to_string(),
+                }
+                .to_string(),
+            };
+
+            // Packets on an unknown channel are rejected
+            let recv = mock_ibc_packet_recv("channel-9", &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert!(matches!(ack, Ack::Error(_)));
+
+            let msg = ExecuteMsg::FreezeChannel {
+                channel_id: CHANNEL.to_string(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+
+            let recv = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv.clone()).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert_eq!(ack.outcome::<AckResult>(), Err(disabled.clone()));
+
+            // Unfreezing lets packets through to the deal logic again
+            let msg = ExecuteMsg::UnfreezeChannel {
+                channel_id: CHANNEL.to_string(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv.clone()).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert_eq!(
+                ack.outcome::<AckResult>().unwrap_err().code,
+                ErrorCode::Rejected
+            );
+
+            // Removing the counterparty disables its open channels
+            let msg = ExecuteMsg::RemoveChannel {
+                connection_id: CONNECTION.to_string(),
+                counterparty_port_id: COUNTERPARTY_PORT.to_string(),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert_eq!(ack.outcome::<AckResult>(), Err(disabled));
+        }
+    }
+    mod notifications {
+        use super::channels::{allow_counterparty, setup, CHANNEL};
+        use super::*;
+        use crate::contract::{execute, query};
+        use crate::ibc::{ibc_channel_connect, ibc_packet_ack, IBC_VERSION};
+        use crate::msg::{DealTerms, ExecuteMsg, NotificationsResponse, QueryMsg};
+        use crate::state::DeliveryStatus;
+        use cosmwasm_std::testing::{
+            mock_env, mock_ibc_channel_connect_ack, mock_ibc_packet_ack, mock_info,
+        };
+        use cosmwasm_std::{from_json, CosmosMsg, IbcAcknowledgement, IbcMsg, IbcOrder};
+        use otc_ibc::ack::make_ack_success;
+        use otc_ibc::IbcHostMsg;
+
+        #[test]
+        fn remote_party_is_notified_and_delivery_tracked() {
+            let mut deps = setup();
+            allow_counterparty(&mut deps);
+            let connect = mock_ibc_channel_connect_ack(CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+
+            // The seller sits on the remote chain, the buyer on the host chain
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                buyer_channel: None,
+                ..DealTerms::default()
+            });
+            let res = execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+            let packet: IbcHostMsg = match &res.messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket {
+                    channel_id, data, ..
+                }) => {
+                    assert_eq!(channel_id, CHANNEL);
+                    from_json(data).unwrap()
+                }
+                msg => panic!("unexpected message {:?}", msg),
+            };
+            assert_eq!(packet.event(), "deal_created");
+
+            // The buyer funding is relayed to the remote seller
+            let info = mock_info(BUYER, &[Coin::new(200u128, NATIVE_DENOM2)]);
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap();
+            assert_eq!(res.messages.len(), 1);
+
+            let ack = IbcAcknowledgement::new(make_ack_success());
+            let msg = mock_ibc_packet_ack(CHANNEL, &packet, ack).unwrap();
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+
+            let res = query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Notifications { deal_id: 0 },
+            )
+            .unwrap();
+            let res: NotificationsResponse = from_json(res).unwrap();
+            let statuses: Vec<_> = res
+                .notifications
+                .iter()
+                .map(|notification| (notification.event.as_str(), notification.status))
+                .collect();
+            assert_eq!(
+                statuses,
+                vec![
+                    ("deal_created", DeliveryStatus::Delivered),
+                    ("counterparty_funded", DeliveryStatus::Pending)
+                ]
+            );
+        }
+    }
+    mod funding_checks {
+        use super::channels::{allow_counterparty, setup, CHANNEL};
+        use super::*;
+        use crate::contract::{execute, query};
+        use crate::ibc::{ibc_channel_connect, ibc_packet_ack, ibc_packet_timeout, IBC_VERSION};
+        use crate::msg::{DealTerms, ExecuteMsg, FundingChecksResponse, QueryMsg};
+        use crate::state::FundingCheck;
+        use cosmwasm_std::testing::{
+            mock_env, mock_ibc_channel_connect_ack, mock_ibc_packet_ack, mock_ibc_packet_timeout,
+            mock_info,
+        };
+        use cosmwasm_std::{
+            from_json, to_json_binary, CosmosMsg, Deps, DepsMut, IbcAcknowledgement, IbcMsg,
+            IbcOrder, Response,
+        };
+        use otc_ibc::ack::make_ack_result;
+        use otc_ibc::{BalanceProof, IbcHostMsg};
+
+        fn balance_query(res: &Response) -> IbcHostMsg {
+            let queries: Vec<IbcHostMsg> = res
+                .messages
+                .iter()
+                .filter_map(|msg| match &msg.msg {
+                    CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => from_json(data).ok(),
+                    _ => None,
+                })
+                .filter(|msg: &IbcHostMsg| msg.event() == "query_balance")
+                .collect();
+            assert_eq!(queries.len(), 1);
+            queries[0].clone()
+        }
+
+        #[test]
+        fn remote_party_proves_balance_before_deal_is_funded() {
+            let mut deps = setup();
+            allow_counterparty(&mut deps);
+            let connect = mock_ibc_channel_connect_ack(CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                funding_check: true,
+                ..DealTerms::default()
+            });
+            let res = execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+            let query_packet = balance_query(&res);
+            assert_eq!(
+                query_packet,
+                IbcHostMsg::QueryBalance {
+                    deal_id: 0,
+                    address: SELLER.to_string(),
+                    denom: NATIVE_DENOM1.to_string(),
+                }
+            );
+
+            let fund = |deps: DepsMut| {
+                let info = mock_info(BUYER, &[Coin::new(200u128, NATIVE_DENOM2)]);
+                execute(deps, mock_env(), info, ExecuteMsg::Deposit { deal_id: 0 })
+            };
+            let answer = |deps: DepsMut, packet: &IbcHostMsg, amount: u128, height: u64| {
+                let proof = BalanceProof {
+                    address: SELLER.to_string(),
+                    denom: NATIVE_DENOM1.to_string(),
+                    amount: Uint128::new(amount),
+                    height,
+                };
+                let ack = make_ack_result(to_json_binary(&proof).unwrap());
+                let msg =
+                    mock_ibc_packet_ack(CHANNEL, packet, IbcAcknowledgement::new(ack)).unwrap();
+                ibc_packet_ack(deps, mock_env(), msg).unwrap();
+            };
+            let checks = |deps: Deps| {
+                let res = query(deps, mock_env(), QueryMsg::FundingChecks { deal_id: 0 }).unwrap();
+                from_json::<FundingChecksResponse>(res).unwrap().checks
+            };
+            let check_funding = |deps: DepsMut| {
+                let msg = ExecuteMsg::CheckFunding { deal_id: 0 };
+                execute(deps, mock_env(), mock_info(USER, &[]), msg).unwrap()
+            };
+
+            // The seller is not asked again while a query is in flight
+            assert!(check_funding(deps.as_mut()).messages.is_empty());
+
+            // Nobody can fund the deal before the seller's balance is known
+            fund(deps.as_mut()).unwrap_err();
+
+            // A short balance is recorded but keeps the deal closed
+            answer(deps.as_mut(), &query_packet, 60, 41);
+            assert_eq!(
+                checks(deps.as_ref()),
+                vec![FundingCheck {
+                    party: SELLER.to_string(),
+                    required: Coin::new(100u128, NATIVE_DENOM1),
+                    available: Uint128::new(60),
+                    height: 41,
+                    sufficient: false,
+                }]
+            );
+            fund(deps.as_mut()).unwrap_err();
+
+            // After topping up, the seller is asked again, once the query
+            // that timed out is cleared, and passes
+            let res = check_funding(deps.as_mut());
+            assert!(check_funding(deps.as_mut()).messages.is_empty());
+            let timeout = mock_ibc_packet_timeout(CHANNEL, &balance_query(&res)).unwrap();
+            ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+            let res = check_funding(deps.as_mut());
+            answer(deps.as_mut(), &balance_query(&res), 150, 45);
+            let check = &checks(deps.as_ref())[0];
+            assert!(check.sufficient);
+            assert_eq!(check.height, 45);
+            fund(deps.as_mut()).unwrap();
+
+            // Passed checks are not repeated
+            assert!(check_funding(deps.as_mut()).messages.is_empty());
+        }
+    }
+    mod remote_parties {
+        use super::channels::{
+            allow_counterparty, connect_agent, deposit_ibc, setup, CHANNEL, TRANSFER_CHANNEL,
+        };
+        use super::*;
+        use crate::ack::Ack;
+        use crate::contract::{self, execute};
+        use crate::ibc::{ibc_channel_connect, ibc_packet_receive, IBC_VERSION};
+        use crate::msg::{
+            DealTerms, ExecuteMsg, FailedPayoutsResponse, IbcExecuteMsg, IbcLifecycleComplete,
+            QueryMsg, SudoMsg, UtilisationResponse,
+        };
+        use crate::pfm;
+        use crate::state::Ics20Packet;
+        use crate::ContractError;
+        use cosmwasm_std::testing::{
+            mock_env, mock_ibc_channel_connect_ack, mock_ibc_packet_recv, mock_info,
+            MOCK_CONTRACT_ADDR,
+        };
+        use cosmwasm_std::{
+            from_json, Binary, CosmosMsg, Deps, IbcOrder, Reply, ReplyOn, SubMsgResponse,
+            SubMsgResult,
+        };
+        use otc_ibc::ack::{AckResult, DealStatus};
+        use otc_ibc::{DealProposal, ForwardHop, PayoutRoute};
+
+        const OTHER_CHANNEL: &str = "channel-2";
+        const AGENT: &str = "agent";
+        const OTHER_AGENT: &str = "other-agent";
+
+        #[test]
+        fn instructions_only_accepted_on_registered_channel() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, AGENT);
+            connect_agent(&mut deps, OTHER_CHANNEL, OTHER_AGENT);
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                buyer_channel: None,
+                ..DealTerms::default()
+            });
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+            let rejected = |channel: &str, sender: &str| {
+                ContractError::SenderNotOnChannel {
+                    sender: sender.to_string(),
+                    channel_id: channel.to_string(),
+                }
+                .to_string()
+            };
+
+            // Remote deposits are only credited from an agent's transfer
+            let coin = Coin::new(100u128, NATIVE_DENOM1);
+            let msg = ExecuteMsg::DepositIbc {
+                deal_id: 0,
+                sender: SELLER.to_string(),
+            };
+            let info = mock_info(SELLER, std::slice::from_ref(&coin));
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert!(matches!(err, ContractError::Unauthorized {}));
+
+            // The seller cannot be impersonated over another channel
+            let err = deposit_ibc(&mut deps, OTHER_AGENT, 0, SELLER, coin.clone()).unwrap_err();
+            assert_eq!(err.to_string(), rejected(OTHER_CHANNEL, SELLER));
+
+            // A host chain party cannot be acted for over IBC at all
+            let coin_b = Coin::new(200u128, NATIVE_DENOM2);
+            let err = deposit_ibc(&mut deps, AGENT, 0, BUYER, coin_b).unwrap_err();
+            assert_eq!(err.to_string(), rejected(CHANNEL, BUYER));
+
+            let res = deposit_ibc(&mut deps, AGENT, 0, SELLER, coin).unwrap();
+            assert_eq!(
+                from_json(res.data.unwrap()),
+                Ok(AckResult {
+                    deal_id: 0,
+                    status: DealStatus::PartiallyFunded,
+                    credited: Some(Coin::new(100u128, NATIVE_DENOM1)),
+                    payout: None,
+                })
+            );
+        }
+
+        #[test]
+        fn deal_proposed_over_ibc_needs_acceptance() {
+            let mut deps = setup();
+            allow_counterparty(&mut deps);
+            let connect = mock_ibc_channel_connect_ack(CHANNEL, IbcOrder::Unordered, IBC_VERSION);
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+
+            let propose = IbcExecuteMsg::CreateDeal {
+                sender: SELLER.to_string(),
+                proposal: DealProposal {
+                    seller: SELLER.to_string(),
+                    buyer: BUYER.to_string(),
+                    coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                    coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                    expiry: mock_env().block.time.seconds() + 100,
+                    counterparty_channel: None,
+                },
+            };
+            for deal_id in [0u64, 1] {
+                let recv = mock_ibc_packet_recv(CHANNEL, &propose).unwrap();
+                let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+                let ack: Ack = from_json(res.acknowledgement).unwrap();
+                let result: AckResult = ack.outcome().unwrap();
+                assert_eq!(result.deal_id, deal_id);
+                assert_eq!(result.status, DealStatus::Proposed);
+            }
+
+            // The buyer cannot fund it before accepting
+            let funds = [Coin::new(200u128, NATIVE_DENOM2)];
+            let info = mock_info(BUYER, &funds);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap_err();
+
+            // Only the party it was proposed to can accept
+            let msg = ExecuteMsg::AcceptDeal { deal_id: 0 };
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap_err();
+            let msg = ExecuteMsg::AcceptDeal { deal_id: 0 };
+            execute(deps.as_mut(), mock_env(), mock_info(BUYER, &[]), msg).unwrap();
+            let info = mock_info(BUYER, &funds);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap();
+
+            // Funded deals can no longer be called off, others can
+            for (deal_id, cancelled) in [(0, false), (1, true)] {
+                let cancel = IbcExecuteMsg::CancelDeal {
+                    deal_id,
+                    sender: SELLER.to_string(),
+                };
+                let recv = mock_ibc_packet_recv(CHANNEL, &cancel).unwrap();
+                let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+                let ack: Ack = from_json(res.acknowledgement).unwrap();
+                assert_eq!(matches!(ack, Ack::Result(_)), cancelled);
+            }
+        }
+
+        #[test]
+        fn payout_forwarded_along_route() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, AGENT);
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                ..DealTerms::default()
+            });
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+            // Deliver to Osmosis via the Hub
+            let route = PayoutRoute {
+                hops: vec![
+                    ForwardHop {
+                        channel: "channel-12".to_string(),
+                        receiver: "cosmos1seller".to_string(),
+                        timeout_seconds: Some(600),
+                        retries: Some(2),
+                    },
+                    ForwardHop {
+                        channel: "channel-141".to_string(),
+                        receiver: "osmo1seller".to_string(),
+                        timeout_seconds: None,
+                        retries: None,
+                    },
+                ],
+            };
+            let memo = pfm::forward_memo(&route).unwrap();
+            assert_eq!(
+                memo,
+                r#"{"forward":{"receiver":"cosmos1seller","port":"transfer","channel":"channel-12","timeout":"600s","retries":2,"next":{"forward":{"receiver":"osmo1seller","port":"transfer","channel":"channel-141"}}}}"#
+            );
+
+            let packet = IbcExecuteMsg::SetPayoutRoute {
+                deal_id: 0,
+                sender: SELLER.to_string(),
+                route: Some(route),
+            };
+            let recv = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert!(matches!(ack, Ack::Result(_)));
+            deposit_ibc(
+                &mut deps,
+                AGENT,
+                0,
+                SELLER,
+                Coin::new(100u128, NATIVE_DENOM1),
+            )
+            .unwrap();
+            let info = mock_info(BUYER, &[Coin::new(200u128, NATIVE_DENOM2)]);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap();
+
+            let packet = IbcExecuteMsg::CompleteDeal {
+                deal_id: 0,
+                packet20: Ics20Packet {
+                    sender: SELLER.to_string(),
+                    ..Ics20Packet::default()
+                },
+            };
+            let recv = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            // ibc-hooks is asked to report back on the transfer as well
+            let memo = format!(
+                r#"{{"forward":{},"ibc_callback":"{}"}}"#,
+                &memo[r#"{"forward":"#.len()..memo.len() - 1],
+                MOCK_CONTRACT_ADDR
+            );
+            match &res.messages[0].msg {
+                CosmosMsg::Stargate { type_url, value } => {
+                    assert_eq!(type_url, "/ibc.applications.transfer.v1.MsgTransfer");
+                    let value = value.as_slice();
+                    assert!(value
+                        .windows(memo.len())
+                        .any(|window| window == memo.as_bytes()));
+                }
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+
+        #[test]
+        fn failed_payout_sent_again() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, AGENT);
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                ..DealTerms::default()
+            });
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+            deposit_ibc(
+                &mut deps,
+                AGENT,
+                0,
+                SELLER,
+                Coin::new(100u128, NATIVE_DENOM1),
+            )
+            .unwrap();
+            let info = mock_info(BUYER, &[Coin::new(200u128, NATIVE_DENOM2)]);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap();
+
+            // Nothing has failed yet
+            let retry = ExecuteMsg::RetryPayouts { deal_id: 0 };
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), retry).unwrap_err();
+
+            let packet = IbcExecuteMsg::CompleteDeal {
+                deal_id: 0,
+                packet20: Ics20Packet {
+                    sender: SELLER.to_string(),
+                    ..Ics20Packet::default()
+                },
+            };
+            let recv = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let transfer = &res.messages[0];
+            assert_eq!(transfer.reply_on, ReplyOn::Success);
+
+            // MsgTransfer answers with the packet sequence, which then times out
+            let reply = Reply {
+                id: transfer.id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: Some(Binary::from(vec![8, 3])),
+                }),
+            };
+            contract::reply(deps.as_mut(), mock_env(), reply).unwrap();
+            let timeout = SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout {
+                channel: TRANSFER_CHANNEL.to_string(),
+                sequence: 3,
+            });
+            contract::sudo(deps.as_mut(), mock_env(), timeout).unwrap();
+
+            // The returned coins are kept for the seller, and count as locked
+            let query_failed = |deps: Deps| -> FailedPayoutsResponse {
+                let msg = QueryMsg::FailedPayouts { deal_id: 0 };
+                from_json(contract::query(deps, mock_env(), msg).unwrap()).unwrap()
+            };
+            let failed = query_failed(deps.as_ref());
+            assert_eq!(failed.payouts.len(), 1);
+            assert_eq!(failed.payouts[0].party, SELLER);
+            assert_eq!(failed.payouts[0].amount, Coin::new(200u128, NATIVE_DENOM2));
+            let msg = QueryMsg::Utilisation { address: None };
+            let utilisation: UtilisationResponse =
+                from_json(contract::query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+            assert!(utilisation
+                .locked
+                .contains(&Coin::new(200u128, NATIVE_DENOM2)));
+
+            // Anyone can send it again
+            let retry = ExecuteMsg::RetryPayouts { deal_id: 0 };
+            let res = execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), retry).unwrap();
+            assert_eq!(res.messages.len(), 1);
+            assert_eq!(res.messages[0].reply_on, ReplyOn::Success);
+            assert!(query_failed(deps.as_ref()).payouts.is_empty());
+
+            // and once it arrives there is nothing left to retry
+            let reply = Reply {
+                id: res.messages[0].id,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: Some(Binary::from(vec![8, 4])),
+                }),
+            };
+            contract::reply(deps.as_mut(), mock_env(), reply).unwrap();
+            let ack = SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcAck {
+                channel: TRANSFER_CHANNEL.to_string(),
+                sequence: 4,
+                ack: String::new(),
+                success: true,
+            });
+            contract::sudo(deps.as_mut(), mock_env(), ack).unwrap();
+            assert!(query_failed(deps.as_ref()).payouts.is_empty());
+        }
+    }
+    mod interchain_accounts {
+        use super::channels::{connect_agent, setup, CHANNEL};
+        use super::*;
+        use crate::contract::{execute, query};
+        use crate::ibc::{
+            ibc_channel_connect, ibc_channel_open, ibc_packet_ack, ibc_packet_receive,
+        };
+        use crate::ica::{IcaMetadata, ICA_ENCODING, ICA_HOST_PORT, ICA_TX_TYPE, ICA_VERSION};
+        use crate::msg::{
+            DealTerms, ExecuteMsg, IbcExecuteMsg, InterchainAccountsResponse, QueryMsg,
+        };
+        use crate::state::{Deal, Ics20Packet, RemoteLeg, VOLUMES};
+        use cosmwasm_std::testing::{
+            mock_env, mock_ibc_packet_recv, mock_info, MockApi, MockQuerier, MockStorage,
+            MOCK_CONTRACT_ADDR,
+        };
+        use cosmwasm_std::{
+            from_json, to_json_binary, to_json_string, Binary, CosmosMsg, IbcAcknowledgement,
+            IbcChannel, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcMsg, IbcOrder,
+            IbcPacket, IbcPacketAckMsg, IbcTimeout, OwnedDeps, SubMsg,
+        };
+        use otc_ibc::ack::Ack;
+        use serde::Deserialize;
+
+        type Deps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+        #[derive(Deserialize)]
+        struct IcaPacket {
+            data: Binary,
+        }
+
+        #[derive(Deserialize)]
+        struct CosmosTx {
+            messages: Vec<TxMsg>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum TxMsg {
+            Exec { grantee: String, msgs: Vec<MsgSend> },
+            Send(MsgSend),
+        }
+
+        #[derive(Deserialize)]
+        struct MsgSend {
+            from_address: String,
+            to_address: String,
+            amount: Vec<Coin>,
+        }
+
+        // The connection the remote seller is reached over
+        const CONNECTION: &str = "connection-2";
+        const AGENT: &str = "agent";
+        const ICA_CHANNEL: &str = "channel-7";
+        const ICA_ADDRESS: &str = "cosmos1ica";
+
+        fn metadata(address: &str) -> String {
+            to_json_string(&IcaMetadata {
+                version: ICA_VERSION.to_string(),
+                controller_connection_id: CONNECTION.to_string(),
+                host_connection_id: "connection-0".to_string(),
+                address: address.to_string(),
+                encoding: ICA_ENCODING.to_string(),
+                tx_type: ICA_TX_TYPE.to_string(),
+            })
+            .unwrap()
+        }
+
+        fn ica_channel() -> IbcChannel {
+            IbcChannel::new(
+                IbcEndpoint {
+                    port_id: format!("wasm.{}", MOCK_CONTRACT_ADDR),
+                    channel_id: ICA_CHANNEL.to_string(),
+                },
+                IbcEndpoint {
+                    port_id: ICA_HOST_PORT.to_string(),
+                    channel_id: "channel-0".to_string(),
+                },
+                IbcOrder::Ordered,
+                metadata(""),
+                CONNECTION,
+            )
+        }
+
+        // Returns the packet data and the send the account was asked to run,
+        // directly or under an authz grant
+        fn ica_packet(messages: &[SubMsg]) -> (Binary, MsgSend) {
+            match &messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket {
+                    channel_id, data, ..
+                }) => {
+                    assert_eq!(channel_id, ICA_CHANNEL);
+                    let packet: IcaPacket = from_json(data).unwrap();
+                    let mut tx: CosmosTx = from_json(packet.data).unwrap();
+                    let send = match tx.messages.remove(0) {
+                        TxMsg::Exec { grantee, mut msgs } => {
+                            assert_eq!(grantee, ICA_ADDRESS);
+                            msgs.remove(0)
+                        }
+                        TxMsg::Send(send) => send,
+                    };
+                    (data.clone(), send)
+                }
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+
+        fn lock(deps: &mut Deps, sender: &str) -> Result<Vec<SubMsg>, String> {
+            let packet = IbcExecuteMsg::LockRemoteLeg {
+                deal_id: 0,
+                sender: sender.to_string(),
+            };
+            let recv = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            match from_json(res.acknowledgement).unwrap() {
+                Ack::Result(_) => Ok(res.messages),
+                Ack::Error(err) => Err(err),
+            }
+        }
+
+        fn acknowledge(deps: &mut Deps, data: Binary, ack: Binary) -> Vec<SubMsg> {
+            let packet = IbcPacket::new(
+                data,
+                IbcEndpoint {
+                    port_id: format!("wasm.{}", MOCK_CONTRACT_ADDR),
+                    channel_id: ICA_CHANNEL.to_string(),
+                },
+                IbcEndpoint {
+                    port_id: ICA_HOST_PORT.to_string(),
+                    channel_id: "channel-0".to_string(),
+                },
+                1,
+                IbcTimeout::with_timestamp(mock_env().block.time),
+            );
+            let msg = IbcPacketAckMsg::new(
+                IbcAcknowledgement::new(ack),
+                packet,
+                Addr::unchecked("relayer"),
+            );
+            ibc_packet_ack(deps.as_mut(), mock_env(), msg)
+                .unwrap()
+                .messages
+        }
+
+        fn deal(deps: &Deps) -> Deal {
+            let res = query(deps.as_ref(), mock_env(), QueryMsg::GetDeal { id: 0 }).unwrap();
+            let res: Binary = from_json(res).unwrap();
+            from_json(res).unwrap()
+        }
+
+        // Registering opens a controller channel from our port
+        fn register_account(deps: &mut Deps) {
+            let msg = ExecuteMsg::RegisterInterchainAccount {
+                connection_id: CONNECTION.to_string(),
+                host_connection_id: "connection-0".to_string(),
+            };
+            let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+            assert!(matches!(
+                &res.messages[0].msg,
+                CosmosMsg::Stargate { type_url, .. }
+                    if type_url == "/ibc.core.channel.v1.MsgChannelOpenInit"
+            ));
+
+            let open = IbcChannelOpenMsg::new_init(ica_channel());
+            ibc_channel_open(deps.as_mut(), mock_env(), open).unwrap();
+            let connect = IbcChannelConnectMsg::new_ack(ica_channel(), metadata(ICA_ADDRESS));
+            ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+        }
+
+        fn seller_leg_terms() -> DealTerms {
+            DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                seller_leg: Some(RemoteLeg {
+                    connection_id: CONNECTION.to_string(),
+                    payout_address: "cosmos1buyer".to_string(),
+                }),
+                ..DealTerms::default()
+            }
+        }
+
+        #[test]
+        fn remote_leg_locked_and_settled_by_interchain_account() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, AGENT);
+            register_account(&mut deps);
+
+            // The seller's leg stays on its home chain, where it is reached
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller_channel: None,
+                ..seller_leg_terms()
+            });
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap_err();
+            let msg = ExecuteMsg::CreateDeal(seller_leg_terms());
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+            let res = query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::InterchainAccounts { deal_id: 0 },
+            )
+            .unwrap();
+            let res: InterchainAccountsResponse = from_json(res).unwrap();
+            assert_eq!(res.seller.unwrap().address, ICA_ADDRESS);
+            assert_eq!(res.buyer, None);
+
+            // It cannot be escrowed here
+            let info = mock_info(SELLER, &[Coin::new(100u128, NATIVE_DENOM1)]);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap_err();
+
+            // Only the seller can lock it, which has the account take the leg
+            // from the seller
+            lock(&mut deps, BUYER).unwrap_err();
+            let messages = lock(&mut deps, SELLER).unwrap();
+            let (data, send) = ica_packet(&messages);
+            assert_eq!(send.from_address, SELLER);
+            assert_eq!(send.to_address, ICA_ADDRESS);
+            assert_eq!(send.amount, vec![Coin::new(100u128, NATIVE_DENOM1)]);
+            assert!(!deal(&deps).seller_deposited);
+            lock(&mut deps, SELLER).unwrap_err();
+            acknowledge(
+                &mut deps,
+                data,
+                to_json_binary(&Ack::Result(Binary::default())).unwrap(),
+            );
+            assert!(deal(&deps).seller_deposited);
+
+            let info = mock_info(BUYER, &[Coin::new(200u128, NATIVE_DENOM2)]);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap();
+
+            // The buyer is paid on the seller's chain, and may retry if that fails
+            let info = mock_info(BUYER, &[]);
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                ExecuteMsg::CompleteDeal { deal_id: 0 },
+            )
+            .unwrap();
+            let (data, send) = ica_packet(&res.messages);
+            assert_eq!(send.from_address, ICA_ADDRESS);
+            assert_eq!(send.to_address, "cosmos1buyer");
+            assert!(deal(&deps).buyer_withdrew);
+            let failed = to_json_binary(&Ack::Error("out of gas".to_string())).unwrap();
+            acknowledge(&mut deps, data, failed);
+            assert!(!deal(&deps).buyer_withdrew);
+
+            // Only the payout that went through counts toward the buyer's volume
+            let volume = |deps: &Deps| VOLUMES.may_load(&deps.storage, (BUYER, NATIVE_DENOM1));
+            assert_eq!(volume(&deps).unwrap(), None);
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(BUYER, &[]),
+                ExecuteMsg::CompleteDeal { deal_id: 0 },
+            )
+            .unwrap();
+            let (data, _) = ica_packet(&res.messages);
+            acknowledge(
+                &mut deps,
+                data,
+                to_json_binary(&Ack::Result(Binary::default())).unwrap(),
+            );
+            assert_eq!(volume(&deps).unwrap().unwrap().amount, Uint128::new(100));
+        }
+
+        #[test]
+        fn leg_locked_after_cancel_goes_back_to_owner() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, AGENT);
+            register_account(&mut deps);
+            let msg = ExecuteMsg::CreateDeal(seller_leg_terms());
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+            // The deal is called off while the leg is on its way in
+            let messages = lock(&mut deps, SELLER).unwrap();
+            let (data, _) = ica_packet(&messages);
+            let msg = ExecuteMsg::CancelDeal { deal_id: 0 };
+            execute(deps.as_mut(), mock_env(), mock_info(BUYER, &[]), msg).unwrap();
+            let ok = to_json_binary(&Ack::Result(Binary::default())).unwrap();
+            let messages = acknowledge(&mut deps, data, ok);
+            assert!(!deal(&deps).seller_deposited);
+
+            // so the account sends it back to the seller
+            let (data, send) = ica_packet(&messages);
+            assert_eq!(send.from_address, ICA_ADDRESS);
+            assert_eq!(send.to_address, SELLER);
+            assert_eq!(send.amount, vec![Coin::new(100u128, NATIVE_DENOM1)]);
+
+            // and should that fail, the seller can still withdraw it
+            let failed = to_json_binary(&Ack::Error("out of gas".to_string())).unwrap();
+            acknowledge(&mut deps, data, failed);
+            assert!(deal(&deps).seller_deposited);
+            let packet = IbcExecuteMsg::Withdraw {
+                deal_id: 0,
+                packet20: Ics20Packet {
+                    sender: SELLER.to_string(),
+                    ..Ics20Packet::default()
+                },
+            };
+            let recv = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let (_, send) = ica_packet(&res.messages);
+            assert_eq!(send.to_address, SELLER);
+            assert!(!deal(&deps).seller_deposited);
+        }
+    }
+
+    mod htlc {
+        use super::channels::setup;
+        use super::*;
+        use crate::contract::{execute, query};
+        use crate::msg::{ExecuteMsg, QueryMsg};
+        use crate::state::{Htlc, HtlcState};
+        use cosmwasm_std::testing::{mock_env, mock_info};
+        use cosmwasm_std::{from_json, BankMsg, CosmosMsg, HexBinary};
+
+        // sha256("secret")
+        const HASHLOCK: &str = "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+
+        #[test]
+        fn htlc_claimed_with_preimage_or_refunded_after_timelock() {
+            let mut deps = setup();
+            let timelock = mock_env().block.time.seconds() + 100;
+            let lock = || ExecuteMsg::LockHtlc {
+                recipient: BUYER.to_string(),
+                hashlock: HexBinary::from_hex(HASHLOCK).unwrap(),
+                timelock,
+            };
+            let info = mock_info(SELLER, &[Coin::new(100u128, NATIVE_DENOM1)]);
+            execute(deps.as_mut(), mock_env(), info.clone(), lock()).unwrap();
+            execute(deps.as_mut(), mock_env(), info, lock()).unwrap();
+
+            let claim = |preimage: &[u8]| ExecuteMsg::ClaimHtlc {
+                id: 0,
+                preimage: HexBinary::from(preimage),
+            };
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(USER, &[]),
+                claim(b"guess"),
+            )
+            .unwrap_err();
+
+            // Anyone revealing the preimage releases the coin to the buyer
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(USER, &[]),
+                claim(b"secret"),
+            )
+            .unwrap();
+            assert_eq!(
+                res.messages[0].msg,
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: BUYER.to_string(),
+                    amount: vec![Coin::new(100u128, NATIVE_DENOM1)],
+                })
+            );
+            let res = query(deps.as_ref(), mock_env(), QueryMsg::Htlc { id: 0 }).unwrap();
+            let htlc: Htlc = from_json(res).unwrap();
+            assert_eq!(
+                htlc.state,
+                HtlcState::Claimed {
+                    preimage: HexBinary::from(b"secret")
+                }
+            );
+
+            // The second lock goes back to the seller only after its timelock
+            let refund = ExecuteMsg::RefundHtlc { id: 1 };
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(USER, &[]),
+                refund.clone(),
+            )
+            .unwrap_err();
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(100);
+            let res = execute(deps.as_mut(), env.clone(), mock_info(USER, &[]), refund).unwrap();
+            assert_eq!(
+                res.messages[0].msg,
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: SELLER.to_string(),
+                    amount: vec![Coin::new(100u128, NATIVE_DENOM1)],
+                })
+            );
+            let claim = ExecuteMsg::ClaimHtlc {
+                id: 1,
+                preimage: HexBinary::from(b"secret"),
+            };
+            execute(deps.as_mut(), env, mock_info(USER, &[]), claim).unwrap_err();
+        }
+    }
+
+    mod fees {
+        use super::*;
+        use crate::msg::{
+            AccountFeesResponse, AccruedFeesResponse, BrokerEarningsResponse, DealTerms,
+            ExecuteMsg, QueryMsg,
+        };
+        use crate::state::{DenomFee, FeeRate};
+
+        #[test]
+        fn fees_taken_from_payouts_and_claimed() {
+            let (mut app, contract) = proper_instantiate();
+
+            // 1% by default, a flat 5 on payouts in denom2
+            let msg = ExecuteMsg::UpdateFees {
+                fee: FeeRate {
+                    bps: 100,
+                    min: Uint128::zero(),
+                },
+                denom_fees: vec![DenomFee {
+                    denom: NATIVE_DENOM2.to_string(),
+                    rate: FeeRate {
+                        bps: 0,
+                        min: Uint128::new(5),
+                    },
+                }],
+                fee_collector: None,
+            };
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: 1704400324,
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+            for (party, fee) in [(SELLER, "5denom2"), (BUYER, "1denom1")] {
+                let msg = ExecuteMsg::CompleteDeal { deal_id: 0 };
+                let res = app
+                    .execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[])
+                    .unwrap();
+                assert!(res.has_event(&cosmwasm_std::Event::new("wasm").add_attribute("fee", fee)));
+            }
+
+            let balance = |app: &App, address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM2), Uint128::new(195));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM1), Uint128::new(99));
+
+            let accrued: AccruedFeesResponse = app
+                .wrap()
+                .query_wasm_smart(contract.addr(), &QueryMsg::AccruedFees {})
+                .unwrap();
+            assert_eq!(
+                accrued.fees,
+                vec![
+                    Coin::new(1u128, NATIVE_DENOM1),
+                    Coin::new(5u128, NATIVE_DENOM2)
+                ]
+            );
+
+            let msg = ExecuteMsg::ClaimFees {
+                recipient: Some("treasury".to_string()),
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            assert_eq!(balance(&app, "treasury", NATIVE_DENOM1), Uint128::new(1));
+            assert_eq!(balance(&app, "treasury", NATIVE_DENOM2), Uint128::new(5));
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap_err();
+        }
+
+        #[test]
+        fn makers_and_negotiated_accounts_get_discounts() {
+            let (mut app, contract) = proper_instantiate();
+            let admin_msgs = [
+                ExecuteMsg::UpdateFees {
+                    fee: FeeRate {
+                        bps: 1000,
+                        min: Uint128::zero(),
+                    },
+                    denom_fees: vec![],
+                    fee_collector: None,
+                },
+                ExecuteMsg::UpdateFeeTiers {
+                    maker_discount_bps: 5000,
+                    volume_tiers: vec![],
+                    volume_window: 0,
+                },
+                ExecuteMsg::SetFeeDiscount {
+                    address: BUYER.to_string(),
+                    discount_bps: Some(2500),
+                },
+            ];
+            for msg in admin_msgs {
+                app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                    .unwrap();
+            }
+
+            // The seller creates the deal and pays maker fees
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: 1704400324,
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+            for party in [SELLER, BUYER] {
+                let msg = ExecuteMsg::CompleteDeal { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[])
+                    .unwrap();
+            }
+
+            // 10% of 200 halved for the maker, 10% of 100 less a quarter for the buyer
+            let balance = |address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(SELLER, NATIVE_DENOM2), Uint128::new(190));
+            assert_eq!(balance(BUYER, NATIVE_DENOM1), Uint128::new(93));
+
+            let fees: AccountFeesResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract.addr(),
+                    &QueryMsg::AccountFees {
+                        address: BUYER.to_string(),
+                    },
+                )
+                .unwrap();
+            assert_eq!(
+                fees,
+                AccountFeesResponse {
+                    discount_bps: Some(2500),
+                    volumes: vec![Coin::new(100u128, NATIVE_DENOM1)],
+                }
+            );
+        }
+
+        #[test]
+        fn broker_paid_commission_from_both_legs() {
+            let (mut app, contract) = proper_instantiate();
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: 1704400324,
+                broker: Some("broker".to_string()),
+                broker_fee_bps: 100,
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+            for party in [SELLER, BUYER] {
+                let msg = ExecuteMsg::CompleteDeal { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[])
+                    .unwrap();
+            }
+
+            let balance = |address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(SELLER, NATIVE_DENOM2), Uint128::new(198));
+            assert_eq!(balance(BUYER, NATIVE_DENOM1), Uint128::new(99));
+
+            let earned: BrokerEarningsResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract.addr(),
+                    &QueryMsg::BrokerEarnings {
+                        broker: "broker".to_string(),
+                    },
+                )
+                .unwrap();
+            assert_eq!(
+                earned.earnings,
+                vec![
+                    Coin::new(1u128, NATIVE_DENOM1),
+                    Coin::new(2u128, NATIVE_DENOM2)
+                ]
+            );
+            assert_eq!(balance("broker", NATIVE_DENOM2), Uint128::new(2));
+        }
+    }
+
+    mod pause {
+        use super::channels::{connect_agent, deposit_ibc, setup, CHANNEL};
+        use super::*;
+        use crate::ack::Ack;
+        use crate::contract::{execute, query};
+        use crate::ibc::ibc_packet_receive;
+        use crate::msg::{DealTerms, ExecuteMsg, IbcExecuteMsg, PauseStatusResponse, QueryMsg};
+        use crate::state::{Ics20Packet, PauseScope};
+        use crate::ContractError;
+        use cosmwasm_std::testing::{mock_env, mock_ibc_packet_recv, mock_info};
+        use cosmwasm_std::{coins, from_json};
+        use otc_ibc::ack::{AckResult, DealStatus};
+
+        const GUARDIAN: &str = "guardian";
+        const AGENT: &str = "agent";
+
+        fn packet20(sender: &str, coin: Coin) -> Ics20Packet {
+            Ics20Packet {
+                amount: coin.amount,
+                denom: coin.denom,
+                receiver: "contract".to_string(),
+                sender: sender.to_string(),
+                memo: None,
+            }
+        }
+
+        #[test]
+        fn guardian_pauses_deposits_but_not_refunds() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, AGENT);
+
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                buyer_channel: None,
+                ..DealTerms::default()
+            });
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+            let deposit = Coin::new(100u128, NATIVE_DENOM1);
+            deposit_ibc(&mut deps, AGENT, 0, SELLER, deposit.clone()).unwrap();
+
+            let pause = ExecuteMsg::Pause {
+                scopes: vec![PauseScope::Deposits, PauseScope::IbcReceive],
+            };
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(GUARDIAN, &[]),
+                pause.clone(),
+            )
+            .unwrap_err();
+            assert!(matches!(err, ContractError::Unauthorized {}));
+            let msg = ExecuteMsg::SetGuardian {
+                guardian: Some(GUARDIAN.to_string()),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+            execute(deps.as_mut(), mock_env(), mock_info(GUARDIAN, &[]), pause).unwrap();
+
+            let status: PauseStatusResponse =
+                from_json(query(deps.as_ref(), mock_env(), QueryMsg::PauseStatus {}).unwrap())
+                    .unwrap();
+            assert_eq!(
+                status,
+                PauseStatusResponse {
+                    paused: vec![PauseScope::Deposits, PauseScope::IbcReceive],
+                    guardian: Some(GUARDIAN.to_string()),
+                }
+            );
+
+            let funds = mock_info(BUYER, &coins(200, NATIVE_DENOM2));
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                funds,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap_err();
+            assert_eq!(err.to_string(), "deposits are paused");
+            let err = deposit_ibc(&mut deps, AGENT, 0, SELLER, deposit.clone()).unwrap_err();
+            assert!(matches!(err, ContractError::Paused { .. }));
+
+            // The seller can still take their deposit back
+            let withdraw = IbcExecuteMsg::Withdraw {
+                deal_id: 0,
+                packet20: packet20(SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+            };
+            let recv = mock_ibc_packet_recv(CHANNEL, &withdraw).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert_eq!(ack.outcome::<AckResult>().unwrap().status, DealStatus::Open);
+
+            let unpause = ExecuteMsg::Unpause {
+                scopes: vec![PauseScope::Deposits, PauseScope::IbcReceive],
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), unpause).unwrap();
+            let res = deposit_ibc(&mut deps, AGENT, 0, SELLER, deposit).unwrap();
+            let result: AckResult = from_json(res.data.unwrap()).unwrap();
+            assert_eq!(result.status, DealStatus::PartiallyFunded);
+        }
+    }
+
+    mod admin_resolution {
+        use super::*;
+        use crate::msg::{DealTerms, ExecuteMsg, QueryMsg};
+        use cosmwasm_std::{Binary, Event};
+
+        #[test]
+        fn stuck_deals_forced_to_refund_or_settle() {
+            let (mut app, contract) = proper_instantiate();
+            let balance = |app: &App, address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            let create = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &create, &[])
+                .unwrap();
+            let deposit = ExecuteMsg::Deposit { deal_id: 0 };
+            app.execute_contract(
+                Addr::unchecked(SELLER),
+                contract.addr(),
+                &deposit,
+                &[Coin::new(100u128, NATIVE_DENOM1)],
+            )
+            .unwrap();
+
+            // Resetting would strand the seller's deposit
+            let reset = ExecuteMsg::Reset {};
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &reset, &[])
+                .unwrap_err();
+
+            let msg = ExecuteMsg::ForceRefund { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            let res = app
+                .execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            assert!(res.has_event(
+                &Event::new("wasm-forced_action")
+                    .add_attribute("action", "force_refund")
+                    .add_attribute("admin", ADMIN)
+                    .add_attribute("refunded", format!("{} 100{}", SELLER, NATIVE_DENOM1))
+            ));
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM1), Uint128::new(1000));
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &reset, &[])
+                .unwrap();
+
+            // A funded deal left to expire can still be settled. Its id is
+            // not the dropped deal's, which late acks may still name
+            let res = app
+                .execute_contract(Addr::unchecked(USER), contract.addr(), &create, &[])
+                .unwrap();
+            assert!(res.has_event(&Event::new("wasm").add_attribute("deal_id", "1")));
+            let msg = QueryMsg::GetDeal { id: 0 };
+            app.wrap()
+                .query_wasm_smart::<Binary>(contract.addr(), &msg)
+                .unwrap_err();
+            let deposit = ExecuteMsg::Deposit { deal_id: 1 };
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &deposit, &[coin])
+                    .unwrap();
+            }
+            app.update_block(|block| block.time = block.time.plus_seconds(200));
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 1 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+
+            let msg = ExecuteMsg::ForceSettle { deal_id: 1 };
+            let res = app
+                .execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            assert!(res.has_event(
+                &Event::new("wasm-forced_action").add_attribute("action", "force_settle")
+            ));
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM2), Uint128::new(200));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM1), Uint128::new(100));
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap_err();
+        }
+    }
+
+    mod roles {
+        use super::*;
+        use crate::msg::{ExecuteMsg, QueryMsg, RoleMembersResponse, RolesResponse};
+        use crate::state::{FeeRate, Role};
+
+        const FEE_MANAGER: &str = "fees";
+
+        #[test]
+        fn duties_granted_and_revoked_separately() {
+            let (mut app, contract) = proper_instantiate();
+            let roles = |app: &App, address: &str| -> Vec<Role> {
+                let res: RolesResponse = app
+                    .wrap()
+                    .query_wasm_smart(
+                        contract.addr(),
+                        &QueryMsg::Roles {
+                            address: address.to_string(),
+                        },
+                    )
+                    .unwrap();
+                res.roles
+            };
+            assert_eq!(roles(&app, ADMIN), Role::ALL.to_vec());
+
+            let grant = ExecuteMsg::GrantRole {
+                role: Role::FeeManager,
+                address: FEE_MANAGER.to_string(),
+            };
+            app.execute_contract(Addr::unchecked(FEE_MANAGER), contract.addr(), &grant, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &grant, &[])
+                .unwrap();
+            assert_eq!(roles(&app, FEE_MANAGER), vec![Role::FeeManager]);
+
+            // A fee manager can change fees and nothing else
+            let update_fees = ExecuteMsg::UpdateFees {
+                fee: FeeRate {
+                    bps: 10,
+                    min: Uint128::zero(),
+                },
+                denom_fees: vec![],
+                fee_collector: None,
+            };
+            app.execute_contract(
+                Addr::unchecked(FEE_MANAGER),
+                contract.addr(),
+                &update_fees,
+                &[],
+            )
+            .unwrap();
+            for msg in [
+                ExecuteMsg::Reset {},
+                ExecuteMsg::FreezeChannel {
+                    channel_id: "channel-1".to_string(),
+                },
+                ExecuteMsg::ForceRefund { deal_id: 0 },
+            ] {
+                app.execute_contract(Addr::unchecked(FEE_MANAGER), contract.addr(), &msg, &[])
+                    .unwrap_err();
+            }
+
+            let revoke = ExecuteMsg::RevokeRole {
+                role: Role::FeeManager,
+                address: FEE_MANAGER.to_string(),
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &revoke, &[])
+                .unwrap();
+            app.execute_contract(
+                Addr::unchecked(FEE_MANAGER),
+                contract.addr(),
+                &update_fees,
+                &[],
+            )
+            .unwrap_err();
+
+            let members: RoleMembersResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract.addr(),
+                    &QueryMsg::RoleMembers {
+                        role: Role::FeeManager,
+                    },
+                )
+                .unwrap();
+            assert_eq!(members.members, vec![ADMIN.to_string()]);
+
+            // The contract always keeps an owner
+            let msg = ExecuteMsg::RevokeRole {
+                role: Role::Owner,
+                address: ADMIN.to_string(),
+            };
+            let err = app
+                .execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "Generic error: Cannot revoke the last owner"
+            );
+        }
+    }
+
+    mod disputes {
+        use super::price_band::setup_oracle;
+        use super::*;
+        use crate::msg::{DealTerms, ExecuteMsg};
+        use cosmwasm_std::Decimal;
+
+        const ARBITRATOR: &str = "arbitrator";
+
+        #[test]
+        fn arbitrator_splits_disputed_deal() {
+            let (mut app, contract) = proper_instantiate();
+            let balance = |app: &App, address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                arbitrator: Some(ARBITRATOR.to_string()),
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let dispute = ExecuteMsg::RaiseDispute {
+                deal_id: 0,
+                reason: "tokens still vesting".to_string(),
+            };
+            // Nothing to dispute before the deal is funded
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &dispute, &[])
+                .unwrap_err();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &dispute, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &dispute, &[])
+                .unwrap();
+
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+
+            let ruling = |seller_denom1: u128, buyer_denom1: u128| ExecuteMsg::Resolve {
+                deal_id: 0,
+                seller_gets: vec![
+                    Coin::new(seller_denom1, NATIVE_DENOM1),
+                    Coin::new(100u128, NATIVE_DENOM2),
+                ],
+                buyer_gets: vec![
+                    Coin::new(buyer_denom1, NATIVE_DENOM1),
+                    Coin::new(100u128, NATIVE_DENOM2),
+                ],
+            };
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &ruling(60, 40), &[])
+                .unwrap_err();
+            // The split has to account for every escrowed coin
+            for (seller, buyer) in [(60, 30), (60, 50)] {
+                app.execute_contract(
+                    Addr::unchecked(ARBITRATOR),
+                    contract.addr(),
+                    &ruling(seller, buyer),
+                    &[],
+                )
+                .unwrap_err();
+            }
+            let res = app
+                .execute_contract(
+                    Addr::unchecked(ARBITRATOR),
+                    contract.addr(),
+                    &ruling(60, 40),
+                    &[],
+                )
+                .unwrap();
+            // One payout per award drawn from each leg
+            let payout_ids = res
+                .events
+                .iter()
+                .flat_map(|event| &event.attributes)
+                .filter(|attribute| attribute.key == "payout_id")
+                .count();
+            assert_eq!(payout_ids, 4);
+
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM1), Uint128::new(960));
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM2), Uint128::new(100));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM1), Uint128::new(40));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM2), Uint128::new(400));
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+        }
+
+        #[test]
+        fn disputed_deal_cannot_be_withdrawn() {
+            let (mut app, contract) = proper_instantiate();
+            let oracle = setup_oracle(&mut app, Decimal::percent(200));
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some(oracle.to_string()),
+                band_bps: 500,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                arbitrator: Some(ARBITRATOR.to_string()),
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+            let dispute = ExecuteMsg::RaiseDispute {
+                deal_id: 0,
+                reason: "tokens still vesting".to_string(),
+            };
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &dispute, &[])
+                .unwrap();
+
+            // Leaving the band would free the deposits, but the arbitrator rules first
+            app.execute_contract(Addr::unchecked(ADMIN), oracle, &Decimal::percent(300), &[])
+                .unwrap();
+            let withdraw = ExecuteMsg::Withdraw { deal_id: 0 };
+            for party in [SELLER, BUYER] {
+                let err = app
+                    .execute_contract(Addr::unchecked(party), contract.addr(), &withdraw, &[])
+                    .unwrap_err();
+                assert!(err.root_cause().to_string().contains("disputed"));
+            }
+        }
+    }
+
+    mod approvals {
+        use super::*;
+        use crate::msg::{DealTerms, ExecuteMsg};
+        use cosmwasm_schema::cw_serde;
+        use cosmwasm_std::{
+            to_json_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+        };
+        use cw_storage_plus::Item;
+
+        const MEMBERS: Item<Vec<String>> = Item::new("members");
+
+        #[cw_serde]
+        enum GroupQueryMsg {
+            Member {
+                addr: String,
+                at_height: Option<u64>,
+            },
+        }
+
+        #[cw_serde]
+        struct MemberResponse {
+            weight: Option<u64>,
+        }
+
+        // Stands in for a cw4 group, answering membership of the instantiated members
+        fn group_contract() -> Box<dyn Contract<Empty>> {
+            fn instantiate(
+                deps: DepsMut,
+                _: Env,
+                _: MessageInfo,
+                members: Vec<String>,
+            ) -> StdResult<Response> {
+                MEMBERS.save(deps.storage, &members)?;
+                Ok(Response::new())
+            }
+            fn execute(_: DepsMut, _: Env, _: MessageInfo, _: Empty) -> StdResult<Response> {
+                Ok(Response::new())
+            }
+            fn query(deps: Deps, _: Env, msg: GroupQueryMsg) -> StdResult<Binary> {
+                let GroupQueryMsg::Member { addr, .. } = msg;
+                let members = MEMBERS.load(deps.storage)?;
+                to_json_binary(&MemberResponse {
+                    weight: members.contains(&addr).then_some(1),
+                })
+            }
+            Box::new(ContractWrapper::new(execute, instantiate, query))
+        }
+
+        #[test]
+        fn large_deals_settle_after_group_approvals() {
+            let (mut app, contract) = proper_instantiate();
+            let group_id = app.store_code(group_contract());
+            let group = app
+                .instantiate_contract(
+                    group_id,
+                    Addr::unchecked(ADMIN),
+                    &vec!["approver1".to_string(), "approver2".to_string()],
+                    &[],
+                    "group",
+                    None,
+                )
+                .unwrap();
+
+            let msg = ExecuteMsg::UpdateApprovalPolicy {
+                large_deal_thresholds: vec![Coin::new(100u128, NATIVE_DENOM1)],
+                approver_group: Some(group.to_string()),
+                group_approvals_required: 2,
+            };
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            };
+            // More approvals than approvers can never be met
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                approvers: vec!["approver1".to_string()],
+                approvals_required: 2,
+                ..terms.clone()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            let msg = ExecuteMsg::CreateDeal(terms);
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            let approve = ExecuteMsg::Approve { deal_id: 0 };
+            app.execute_contract(Addr::unchecked("approver1"), contract.addr(), &approve, &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked("approver1"), contract.addr(), &approve, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &approve, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+
+            app.execute_contract(Addr::unchecked("approver2"), contract.addr(), &approve, &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap();
+            let balance = app.wrap().query_balance(SELLER, NATIVE_DENOM2).unwrap();
+            assert_eq!(balance.amount, Uint128::new(200));
+        }
+
+        #[test]
+        fn listed_approvers_add_to_group_policy() {
+            let (mut app, contract) = proper_instantiate();
+            let group_id = app.store_code(group_contract());
+            let group = app
+                .instantiate_contract(
+                    group_id,
+                    Addr::unchecked(ADMIN),
+                    &vec!["approver1".to_string(), SELLER.to_string()],
+                    &[],
+                    "group",
+                    None,
+                )
+                .unwrap();
+            let msg = ExecuteMsg::UpdateApprovalPolicy {
+                large_deal_thresholds: vec![Coin::new(100u128, NATIVE_DENOM1)],
+                approver_group: Some(group.to_string()),
+                group_approvals_required: 1,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                approvers: vec!["friend".to_string()],
+                approvals_required: 1,
+                ..DealTerms::default()
+            };
+            // A party cannot vouch for its own deal
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                approvers: vec![BUYER.to_string()],
+                ..terms.clone()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            let msg = ExecuteMsg::CreateDeal(terms);
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            // The creator's approver does not stand in for the group
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            let approve = ExecuteMsg::Approve { deal_id: 0 };
+            app.execute_contract(Addr::unchecked("friend"), contract.addr(), &approve, &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+            // Nor does a party that happens to be in the group
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &approve, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked("approver1"), contract.addr(), &approve, &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap();
+        }
+    }
+
+    mod traders {
+        use super::*;
+        use crate::msg::{DealTerms, ExecuteMsg, QueryMsg, TraderResponse};
+        use crate::state::TraderAllowlist;
+
+        #[test]
+        fn only_allowlisted_traders_create_and_fund_deals() {
+            let (mut app, contract) = proper_instantiate();
+            let msg = ExecuteMsg::SetTraderAllowlist {
+                traders: TraderAllowlist::Internal,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            let update = |add: &[&str], remove: &[&str]| ExecuteMsg::UpdateTraders {
+                add: add.iter().map(|address| address.to_string()).collect(),
+                remove: remove.iter().map(|address| address.to_string()).collect(),
+            };
+            app.execute_contract(
+                Addr::unchecked(ADMIN),
+                contract.addr(),
+                &update(&[SELLER], &[]),
+                &[],
+            )
+            .unwrap();
+
+            let create = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            });
+            let err = app
+                .execute_contract(Addr::unchecked(USER), contract.addr(), &create, &[])
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                format!("{} is not an allowed trader", BUYER)
+            );
+            app.execute_contract(
+                Addr::unchecked(ADMIN),
+                contract.addr(),
+                &update(&[BUYER], &[]),
+                &[],
+            )
+            .unwrap();
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &create, &[])
+                .unwrap();
+
+            // Traders dropped from the list can no longer fund their deals
+            app.execute_contract(
+                Addr::unchecked(ADMIN),
+                contract.addr(),
+                &update(&[], &[BUYER]),
+                &[],
+            )
+            .unwrap();
+            let allowed = |app: &App, address: &str| {
+                let res: TraderResponse = app
+                    .wrap()
+                    .query_wasm_smart(
+                        contract.addr(),
+                        &QueryMsg::Trader {
+                            address: address.to_string(),
+                        },
+                    )
+                    .unwrap();
+                res.allowed
+            };
+            assert!(allowed(&app, SELLER));
+            assert!(!allowed(&app, BUYER));
+
+            let deposit = ExecuteMsg::Deposit { deal_id: 0 };
+            app.execute_contract(
+                Addr::unchecked(BUYER),
+                contract.addr(),
+                &deposit,
+                &[Coin::new(200u128, NATIVE_DENOM2)],
+            )
+            .unwrap_err();
+            app.execute_contract(
+                Addr::unchecked(SELLER),
+                contract.addr(),
+                &deposit,
+                &[Coin::new(100u128, NATIVE_DENOM1)],
+            )
+            .unwrap();
+
+            // Nor be paid by an HTLC
+            let lock = ExecuteMsg::LockHtlc {
+                recipient: BUYER.to_string(),
+                hashlock: cosmwasm_std::HexBinary::from([0u8; 32]),
+                timelock: app.block_info().time.seconds() + 100,
+            };
+            let err = app
+                .execute_contract(
+                    Addr::unchecked(SELLER),
+                    contract.addr(),
+                    &lock,
+                    &[Coin::new(100u128, NATIVE_DENOM1)],
+                )
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                format!("{} is not an allowed trader", BUYER)
+            );
+        }
+    }
+
+    mod screening {
+        use super::channels::{connect_agent, deposit_ibc, setup, CHANNEL};
+        use super::*;
+        use crate::contract::execute;
+        use crate::msg::{DealTerms, ExecuteMsg};
+        use crate::screening::{ScreenResponse, ScreeningQueryMsg};
+        use crate::ContractError;
+        use cosmwasm_std::testing::{mock_env, mock_info};
+        use cosmwasm_std::{from_json, to_json_binary, ContractResult, SystemResult, WasmQuery};
+
+        const SCREENING: &str = "screening";
+
+        #[test]
+        fn flagged_parties_rejected_on_both_chains() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, "agent");
+            // Flags the seller, who trades from another chain
+            deps.querier.update_wasm(|query| {
+                let WasmQuery::Smart { contract_addr, msg } = query else {
+                    panic!("unexpected query {:?}", query);
+                };
+                assert_eq!(contract_addr, SCREENING);
+                let ScreeningQueryMsg::Screen { address } = from_json(msg).unwrap();
+                let res = ScreenResponse {
+                    flagged: address == SELLER,
+                    reason: Some("sanctioned".to_string()),
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&res).unwrap()))
+            });
+
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: mock_env().block.time.seconds() + 100,
+                seller_channel: Some(CHANNEL.to_string()),
+                ..DealTerms::default()
+            };
+            let msg = ExecuteMsg::CreateDeal(terms.clone());
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+
+            let msg = ExecuteMsg::SetScreening {
+                contract: Some(SCREENING.to_string()),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg.clone()).unwrap_err();
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+
+            let msg = ExecuteMsg::CreateDeal(terms);
+            let err = execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::AddressFlagged { .. }));
+
+            // The deal created before screening was set up cannot be funded either
+            let deposit = Coin::new(100u128, NATIVE_DENOM1);
+            let err = deposit_ibc(&mut deps, "agent", 0, SELLER, deposit).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("{} was flagged by screening: sanctioned", SELLER)
+            );
+
+            let funds = mock_info(BUYER, &[Coin::new(200u128, NATIVE_DENOM2)]);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                funds,
+                ExecuteMsg::Deposit { deal_id: 0 },
+            )
+            .unwrap();
+
+            // Nor can an HTLC pay them, or be locked by them
+            let lock = |recipient: &str| ExecuteMsg::LockHtlc {
+                recipient: recipient.to_string(),
+                hashlock: cosmwasm_std::HexBinary::from([0u8; 32]),
+                timelock: mock_env().block.time.seconds() + 100,
+            };
+            for (sender, recipient) in [(BUYER, SELLER), (SELLER, BUYER)] {
+                let funds = mock_info(sender, &[Coin::new(100u128, NATIVE_DENOM1)]);
+                let err = execute(deps.as_mut(), mock_env(), funds, lock(recipient)).unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    format!("{} was flagged by screening: sanctioned", SELLER)
+                );
+            }
+        }
+    }
+
+    mod limits {
+        use super::*;
+        use crate::msg::{DealTerms, ExecuteMsg, QueryMsg, UtilisationResponse};
+
+        #[test]
+        fn deal_sizes_open_deals_and_locked_coins_capped() {
+            let (mut app, contract) = proper_instantiate();
+            let msg = ExecuteMsg::UpdateLimits {
+                max_deal_amounts: vec![Coin::new(150u128, NATIVE_DENOM1)],
+                max_open_deals: 1,
+                max_locked: vec![
+                    Coin::new(100u128, NATIVE_DENOM1),
+                    Coin::new(150u128, NATIVE_DENOM2),
+                ],
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(200u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            };
+            let msg = ExecuteMsg::CreateDeal(terms.clone());
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            let terms = DealTerms {
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                ..terms
+            };
+            let msg = ExecuteMsg::CreateDeal(terms.clone());
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            // The seller already has a deal open
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                buyer: "other".to_string(),
+                ..terms
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+
+            let deposit = ExecuteMsg::Deposit { deal_id: 0 };
+            app.execute_contract(
+                Addr::unchecked(BUYER),
+                contract.addr(),
+                &deposit,
+                &[Coin::new(200u128, NATIVE_DENOM2)],
+            )
+            .unwrap_err();
+            app.execute_contract(
+                Addr::unchecked(SELLER),
+                contract.addr(),
+                &deposit,
+                &[Coin::new(100u128, NATIVE_DENOM1)],
+            )
+            .unwrap();
+
+            let utilisation: UtilisationResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract.addr(),
+                    &QueryMsg::Utilisation {
+                        address: Some(SELLER.to_string()),
+                    },
+                )
+                .unwrap();
+            assert_eq!(utilisation.locked, vec![Coin::new(100u128, NATIVE_DENOM1)]);
+            assert_eq!(utilisation.open_deals, Some(1));
+            assert_eq!(utilisation.max_open_deals, 1);
+        }
+
+        #[test]
+        fn locked_htlcs_count_toward_caps() {
+            let (mut app, contract) = proper_instantiate();
+            let msg = ExecuteMsg::UpdateLimits {
+                max_deal_amounts: vec![],
+                max_open_deals: 1,
+                max_locked: vec![Coin::new(150u128, NATIVE_DENOM2)],
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            // sha256("secret")
+            let hashlock = cosmwasm_std::HexBinary::from_hex(
+                "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b",
+            )
+            .unwrap();
+            let timelock = app.block_info().time.seconds() + 100;
+            let lock = |recipient: &str| ExecuteMsg::LockHtlc {
+                recipient: recipient.to_string(),
+                hashlock: hashlock.clone(),
+                timelock,
+            };
+            let funds = [Coin::new(100u128, NATIVE_DENOM2)];
+            let msg = lock(SELLER);
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &msg, &funds)
+                .unwrap();
+            let utilisation: UtilisationResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract.addr(),
+                    &QueryMsg::Utilisation {
+                        address: Some(SELLER.to_string()),
+                    },
+                )
+                .unwrap();
+            assert_eq!(utilisation.locked, funds.to_vec());
+            assert_eq!(utilisation.open_deals, Some(1));
+
+            // Over the locked cap, then over the open deal cap
+            let msg = lock("other");
+            let err = app
+                .execute_contract(Addr::unchecked(BUYER), contract.addr(), &msg, &funds)
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                format!(
+                    "Generic error: Deposit would take locked {} over the limit",
+                    NATIVE_DENOM2
+                )
+            );
+            let funds = [Coin::new(50u128, NATIVE_DENOM2)];
+            let err = app
+                .execute_contract(Addr::unchecked(BUYER), contract.addr(), &msg, &funds)
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                format!("Generic error: {} has too many open deals", BUYER)
+            );
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: "other".to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(100u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            };
+            let msg = ExecuteMsg::CreateDeal(terms);
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+
+            // Claiming the HTLC frees both
+            let claim = ExecuteMsg::ClaimHtlc {
+                id: 0,
+                preimage: cosmwasm_std::HexBinary::from(b"secret"),
+            };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &claim, &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        #[test]
+        fn totals_follow_deals_as_they_settle_and_expire() {
+            let (mut app, contract) = proper_instantiate();
+            let utilisation = |app: &App, address: &str| -> UtilisationResponse {
+                let msg = QueryMsg::Utilisation {
+                    address: Some(address.to_string()),
+                };
+                app.wrap().query_wasm_smart(contract.addr(), &msg).unwrap()
+            };
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            };
+            for terms in [
+                terms.clone(),
+                DealTerms {
+                    buyer: "other".to_string(),
+                    ..terms
+                },
+            ] {
+                let msg = ExecuteMsg::CreateDeal(terms);
+                app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                    .unwrap();
+            }
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+            let utilisation_now = utilisation(&app, SELLER);
+            assert_eq!(
+                utilisation_now.locked,
+                vec![
+                    Coin::new(100u128, NATIVE_DENOM1),
+                    Coin::new(200u128, NATIVE_DENOM2)
+                ]
+            );
+            assert_eq!(utilisation_now.open_deals, Some(2));
+
+            // Each payout releases the leg it pays
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap();
+            assert_eq!(
+                utilisation(&app, SELLER).locked,
+                vec![Coin::new(100u128, NATIVE_DENOM1)]
+            );
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &complete, &[])
+                .unwrap();
+            let utilisation_now = utilisation(&app, SELLER);
+            assert_eq!(utilisation_now.locked, vec![]);
+            assert_eq!(utilisation_now.open_deals, Some(1));
+
+            // A deal left unfunded stops counting once it expires
+            app.update_block(|block| block.time = block.time.plus_seconds(101));
+            assert_eq!(utilisation(&app, SELLER).open_deals, Some(0));
+        }
+    }
+
+    mod price_band {
+        use super::*;
+        use crate::msg::{DealTerms, ExecuteMsg};
+        use crate::oracle::{OracleQueryMsg, PriceResponse};
+        use cosmwasm_std::{
+            to_json_binary, Binary, Decimal, Deps, DepsMut, Env, Event, MessageInfo, Response,
+            StdResult,
+        };
+        use cw_storage_plus::Item;
+
+        const PRICE: Item<Decimal> = Item::new("price");
+
+        // An admin-fed oracle quoting the same price for every pair
+        pub fn oracle_contract() -> Box<dyn Contract<Empty>> {
+            fn set_price(
+                deps: DepsMut,
+                _: Env,
+                _: MessageInfo,
+                price: Decimal,
+            ) -> StdResult<Response> {
+                PRICE.save(deps.storage, &price)?;
+                Ok(Response::new())
+            }
+            fn query(deps: Deps, _: Env, _: OracleQueryMsg) -> StdResult<Binary> {
+                to_json_binary(&PriceResponse {
+                    price: PRICE.load(deps.storage)?,
+                })
+            }
+            Box::new(ContractWrapper::new(set_price, set_price, query))
+        }
+
+        pub fn setup_oracle(app: &mut App, price: Decimal) -> Addr {
+            let code_id = app.store_code(oracle_contract());
+            app.instantiate_contract(code_id, Addr::unchecked(ADMIN), &price, &[], "oracle", None)
+                .unwrap()
+        }
+
+        #[test]
+        fn funded_deal_refunded_once_price_leaves_band() {
+            let (mut app, contract) = proper_instantiate();
+            let oracle = setup_oracle(&mut app, Decimal::percent(200));
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some(oracle.to_string()),
+                band_bps: 500,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            let withdraw = ExecuteMsg::Withdraw { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &withdraw, &[])
+                .unwrap_err();
+            app.execute_contract(Addr::unchecked(ADMIN), oracle, &Decimal::percent(300), &[])
+                .unwrap();
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+
+            // Neither leg is kept locked in a deal that cannot settle
+            for party in [SELLER, BUYER] {
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &withdraw, &[])
+                    .unwrap();
+            }
+            let balance = |address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(SELLER, NATIVE_DENOM1), Uint128::new(1000));
+            assert_eq!(balance(BUYER, NATIVE_DENOM2), Uint128::new(500));
+        }
+
+        #[test]
+        fn prices_checked_against_oracle_band() {
+            let (mut app, contract) = proper_instantiate();
+            let oracle = setup_oracle(&mut app, Decimal::percent(200));
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some(oracle.to_string()),
+                band_bps: 500,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let terms = DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                expiry: app.block_info().time.seconds() + 100,
+                ..DealTerms::default()
+            };
+            let msg = ExecuteMsg::CreateDeal(terms.clone());
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+
+            // A fat-fingered price needs the parties to accept it as off-market
+            let fat_finger = DealTerms {
+                seller: "alice".to_string(),
+                buyer: "bob".to_string(),
+                coin_b: Coin::new(300u128, NATIVE_DENOM2),
+                ..terms
+            };
+            let msg = ExecuteMsg::CreateDeal(fat_finger.clone());
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            let msg = ExecuteMsg::CreateDeal(DealTerms {
+                off_market: true,
+                ..fat_finger
+            });
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap_err();
+            let res = app
+                .execute_contract(Addr::unchecked("alice"), contract.addr(), &msg, &[])
+                .unwrap();
+            assert!(res.has_event(&Event::new("wasm").add_attribute("off_market", "true")));
+            // Until the other party accepts it as well, unless an operator set it
+            let res = app
+                .execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            assert!(res.has_event(&Event::new("wasm").add_attribute("off_market", "true")));
+            for (deal_id, accepted) in [(1, true), (2, false)] {
+                let msg = ExecuteMsg::AcceptDeal { deal_id };
+                let res = app.execute_contract(Addr::unchecked("bob"), contract.addr(), &msg, &[]);
+                assert_eq!(res.is_ok(), accepted);
+            }
+
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(200u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            let set_price = |app: &mut App, price: Decimal| {
+                app.execute_contract(Addr::unchecked(ADMIN), oracle.clone(), &price, &[])
+                    .unwrap();
+            };
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            set_price(&mut app, Decimal::percent(300));
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+            set_price(&mut app, Decimal::percent(205));
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap();
+            // Once one leg is paid out the other always can be
+            set_price(&mut app, Decimal::percent(300));
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &complete, &[])
+                .unwrap();
+        }
+    }
+
+    mod pegged {
+        use super::channels::{connect_agent, deposit_ibc, setup, CHANNEL};
+        use super::price_band::setup_oracle;
+        use super::*;
+        use crate::ack::Ack;
+        use crate::contract::execute;
+        use crate::ibc::ibc_packet_receive;
+        use crate::msg::{DealTerms, ExecuteMsg, IbcExecuteMsg, QueryMsg};
+        use crate::oracle::PriceResponse;
+        use crate::state::{Deal, Ics20Packet, Peg, STATE};
+        use cosmwasm_std::testing::{mock_env, mock_ibc_packet_recv, mock_info};
+        use cosmwasm_std::{
+            from_json, to_json_binary, BankMsg, Binary, ContractResult, CosmosMsg, Decimal,
+            SystemResult,
+        };
+
+        fn pegged_deal(expiry: u64, seller_channel: Option<String>) -> ExecuteMsg {
+            ExecuteMsg::CreateDeal(DealTerms {
+                seller: SELLER.to_string(),
+                buyer: BUYER.to_string(),
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(250u128, NATIVE_DENOM2),
+                expiry,
+                seller_channel,
+                peg_spread_bps: Some(100),
+                ..DealTerms::default()
+            })
+        }
+
+        #[test]
+        fn buyer_leg_priced_from_oracle_at_settlement() {
+            let (mut app, contract) = proper_instantiate();
+            let oracle = setup_oracle(&mut app, Decimal::percent(250));
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some(oracle.to_string()),
+                band_bps: 0,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            // The buyer pays at most 250 for the oracle price plus 1%
+            let msg = pegged_deal(app.block_info().time.seconds() + 100, None);
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(250u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            let set_price = |app: &mut App, price: Decimal| {
+                app.execute_contract(Addr::unchecked(ADMIN), oracle.clone(), &price, &[])
+                    .unwrap();
+            };
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            // 252.5 is more than the buyer put up
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+            set_price(&mut app, Decimal::percent(200));
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap();
+
+            let balance = |app: &App, address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM2), Uint128::new(202));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM2), Uint128::new(298));
+            let deal: Binary = app
+                .wrap()
+                .query_wasm_smart(contract.addr(), &QueryMsg::GetDeal { id: 0 })
+                .unwrap();
+            let deal: Deal = from_json(deal).unwrap();
+            assert_eq!(deal.coin_b, Coin::new(202u128, NATIVE_DENOM2));
+            assert_eq!(
+                deal.peg,
+                Some(Peg {
+                    spread_bps: 100,
+                    price: Some(Decimal::permille(2020)),
+                })
+            );
+
+            // The price stays fixed for the other payout
+            set_price(&mut app, Decimal::percent(300));
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &complete, &[])
+                .unwrap();
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM1), Uint128::new(100));
+        }
+
+        #[test]
+        fn deposits_refunded_while_pegged_price_over_cap() {
+            let (mut app, contract) = proper_instantiate();
+            let oracle = setup_oracle(&mut app, Decimal::percent(200));
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some(oracle.to_string()),
+                band_bps: 0,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            let msg = pegged_deal(app.block_info().time.seconds() + 100, None);
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(250u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            let withdraw = ExecuteMsg::Withdraw { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &withdraw, &[])
+                .unwrap_err();
+            // 252.5 is more than the buyer put up, so the deal cannot settle
+            app.execute_contract(Addr::unchecked(ADMIN), oracle, &Decimal::percent(250), &[])
+                .unwrap();
+            let complete = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &complete, &[])
+                .unwrap_err();
+            for party in [SELLER, BUYER] {
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &withdraw, &[])
+                    .unwrap();
+            }
+            let balance = |address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(SELLER, NATIVE_DENOM1), Uint128::new(1000));
+            assert_eq!(balance(BUYER, NATIVE_DENOM2), Uint128::new(500));
+        }
+
+        #[test]
+        fn forced_settlement_fixes_price_and_refunds_buyer() {
+            let (mut app, contract) = proper_instantiate();
+            let oracle = setup_oracle(&mut app, Decimal::percent(200));
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some(oracle.to_string()),
+                band_bps: 0,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            let msg = pegged_deal(app.block_info().time.seconds() + 100, None);
+            app.execute_contract(Addr::unchecked(USER), contract.addr(), &msg, &[])
+                .unwrap();
+            for (party, coin) in [
+                (SELLER, Coin::new(100u128, NATIVE_DENOM1)),
+                (BUYER, Coin::new(250u128, NATIVE_DENOM2)),
+            ] {
+                let msg = ExecuteMsg::Deposit { deal_id: 0 };
+                app.execute_contract(Addr::unchecked(party), contract.addr(), &msg, &[coin])
+                    .unwrap();
+            }
+
+            let msg = ExecuteMsg::ForceSettle { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+            let balance = |app: &App, address: &str, denom: &str| {
+                app.wrap().query_balance(address, denom).unwrap().amount
+            };
+            assert_eq!(balance(&app, SELLER, NATIVE_DENOM2), Uint128::new(202));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM2), Uint128::new(298));
+            assert_eq!(balance(&app, BUYER, NATIVE_DENOM1), Uint128::new(100));
+            assert_eq!(
+                balance(&app, contract.addr().as_str(), NATIVE_DENOM2),
+                Uint128::zero()
+            );
+        }
+
+        #[test]
+        fn rejected_remote_settlement_leaves_price_open() {
+            let mut deps = setup();
+            connect_agent(&mut deps, CHANNEL, "agent");
+            deps.querier.update_wasm(|_| {
+                let res = PriceResponse {
+                    price: Decimal::percent(200),
+                };
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&res).unwrap()))
+            });
+            let msg = ExecuteMsg::UpdatePriceOracle {
+                oracle: Some("oracle".to_string()),
+                band_bps: 0,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+            let expiry = mock_env().block.time.seconds() + 100;
+            let msg = pegged_deal(expiry, Some(CHANNEL.to_string()));
+            execute(deps.as_mut(), mock_env(), mock_info(USER, &[]), msg).unwrap();
+            let coin_a = Coin::new(100u128, NATIVE_DENOM1);
+            deposit_ibc(&mut deps, "agent", 0, SELLER, coin_a).unwrap();
+            let info = mock_info(BUYER, &[Coin::new(250u128, NATIVE_DENOM2)]);
+            let msg = ExecuteMsg::Deposit { deal_id: 0 };
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+            let complete = |sender: &str| IbcExecuteMsg::CompleteDeal {
+                deal_id: 0,
+                packet20: Ics20Packet {
+                    sender: sender.to_string(),
+                    ..Ics20Packet::default()
+                },
+            };
+            // The buyer is on the host chain and cannot settle over the channel
+            let funded = STATE.load(&deps.storage).unwrap();
+            let recv = mock_ibc_packet_recv(CHANNEL, &complete(BUYER)).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let ack: Ack = from_json(res.acknowledgement).unwrap();
+            assert!(matches!(ack, Ack::Error(_)));
+            assert_eq!(STATE.load(&deps.storage).unwrap(), funded);
+
+            let recv = mock_ibc_packet_recv(CHANNEL, &complete(SELLER)).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), recv).unwrap();
+            let refund = CosmosMsg::Bank(BankMsg::Send {
+                to_address: BUYER.to_string(),
+                amount: vec![Coin::new(48u128, NATIVE_DENOM2)],
+            });
+            assert!(res.messages.iter().any(|sub| sub.msg == refund));
+            let deal = &STATE.load(&deps.storage).unwrap().deals[0];
+            assert_eq!(deal.coin_b, Coin::new(202u128, NATIVE_DENOM2));
+        }
+    }
+
+    mod rfq {
+        use super::*;
+        use crate::msg::{ExecuteMsg, InventoryResponse, QueryMsg, Quote};
+        use crate::rfq::quote_hash;
+        use crate::state::Side;
+        use cosmwasm_std::Binary;
+        use k256::ecdsa::signature::hazmat::PrehashSigner;
+        use k256::ecdsa::{Signature, SigningKey};
+
+        fn sign(key: &SigningKey, quote: &Quote) -> Binary {
+            let signature: Signature = key.sign_prehash(&quote_hash(quote).unwrap()).unwrap();
+            Binary::from(signature.to_bytes().as_slice())
+        }
+
+        #[test]
+        fn taker_fills_signed_quote_from_inventory() {
+            let (mut app, contract) = proper_instantiate();
+            let key = SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+            let pubkey = Binary::from(key.verifying_key().to_encoded_point(true).as_bytes());
+
+            let maker = Addr::unchecked(SELLER);
+            let msg = ExecuteMsg::RegisterQuoteKey {
+                pubkey: pubkey.clone(),
+            };
+            app.execute_contract(maker.clone(), contract.addr(), &msg, &[])
+                .unwrap();
+            let msg = ExecuteMsg::DepositInventory {};
+            app.execute_contract(
+                maker.clone(),
+                contract.addr(),
+                &msg,
+                &[Coin::new(300u128, NATIVE_DENOM1)],
+            )
+            .unwrap();
+
+            let quote = Quote {
+                contract: contract.addr().into_string(),
+                maker: SELLER.to_string(),
+                taker: None,
+                maker_side: Side::Seller,
+                coin_a: Coin::new(100u128, NATIVE_DENOM1),
+                coin_b: Coin::new(200u128, NATIVE_DENOM2),
+                nonce: 1,
+                valid_until: app.block_info().time.seconds() + 60,
+                deal_expiry: app.block_info().time.seconds() + 100,
+            };
+            let take = |quote: &Quote, signature: Binary| ExecuteMsg::TakeQuote {
+                quote: quote.clone(),
+                signature,
+                maker_pubkey: pubkey.clone(),
+            };
+            let funds = [Coin::new(200u128, NATIVE_DENOM2)];
+
+            // Signed terms cannot be changed
+            let mut altered = quote.clone();
+            altered.coin_a.amount = Uint128::new(300);
+            let err = app
+                .execute_contract(
+                    Addr::unchecked(BUYER),
+                    contract.addr(),
+                    &take(&altered, sign(&key, &quote)),
+                    &funds,
+                )
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "quote is not signed by its maker"
+            );
+
+            // Nor can the deal expire before the quote does
+            let now = app.block_info().time.seconds();
+            for deal_expiry in [now, quote.valid_until - 1] {
+                let quote = Quote {
+                    deal_expiry,
+                    ..quote.clone()
+                };
+                let err = app
+                    .execute_contract(
+                        Addr::unchecked(BUYER),
+                        contract.addr(),
+                        &take(&quote, sign(&key, &quote)),
+                        &funds,
+                    )
+                    .unwrap_err();
+                assert_eq!(
+                    err.root_cause().to_string(),
+                    "Generic error: Deal must not expire before the quote"
+                );
+            }
+
+            app.execute_contract(
+                Addr::unchecked(BUYER),
+                contract.addr(),
+                &take(&quote, sign(&key, &quote)),
+                &funds,
+            )
+            .unwrap();
+            // Nor taken twice
+            app.execute_contract(
+                Addr::unchecked(BUYER),
+                contract.addr(),
+                &take(&quote, sign(&key, &quote)),
+                &funds,
+            )
+            .unwrap_err();
+
+            let inventory: InventoryResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract.addr(),
+                    &QueryMsg::Inventory {
+                        maker: SELLER.to_string(),
+                    },
+                )
+                .unwrap();
+            assert_eq!(inventory.coins, vec![Coin::new(200u128, NATIVE_DENOM1)]);
+
+            // Both legs are in, so the deal settles right away
+            let msg = ExecuteMsg::CompleteDeal { deal_id: 0 };
+            app.execute_contract(Addr::unchecked(BUYER), contract.addr(), &msg, &[])
+                .unwrap();
+            let balance = app.wrap().query_balance(BUYER, NATIVE_DENOM1).unwrap();
+            assert_eq!(balance.amount, Uint128::new(100));
+        }
+
+        #[test]
+        fn inventory_counts_toward_locked_cap() {
+            let (mut app, contract) = proper_instantiate();
+            let msg = ExecuteMsg::UpdateLimits {
+                max_deal_amounts: vec![],
+                max_open_deals: 0,
+                max_locked: vec![Coin::new(300u128, NATIVE_DENOM1)],
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+                .unwrap();
+
+            let deposit = ExecuteMsg::DepositInventory {};
+            let funds = [Coin::new(200u128, NATIVE_DENOM1)];
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &deposit, &funds)
+                .unwrap();
+            let err = app
+                .execute_contract(Addr::unchecked(SELLER), contract.addr(), &deposit, &funds)
+                .unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                format!(
+                    "Generic error: Deposit would take locked {} over the limit",
+                    NATIVE_DENOM1
+                )
+            );
+
+            // Withdrawing makes room again
+            let msg = ExecuteMsg::WithdrawInventory {
+                coins: vec![Coin::new(100u128, NATIVE_DENOM1)],
+            };
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &msg, &[])
+                .unwrap();
+            app.execute_contract(Addr::unchecked(SELLER), contract.addr(), &deposit, &funds)
+                .unwrap();
         }
     }
 }
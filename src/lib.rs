@@ -1,10 +1,21 @@
-pub mod ack;
+pub mod approvals;
 pub mod contract;
 mod error;
+pub mod fees;
+pub mod funding;
 pub mod helpers;
 pub mod ibc;
+pub mod ica;
 pub mod integration_tests;
+pub mod limits;
 pub mod msg;
+pub mod notify;
+pub mod oracle;
+pub mod pfm;
+pub mod rfq;
+pub mod screening;
 pub mod state;
+pub mod traders;
 
 pub use crate::error::ContractError;
+pub use otc_ibc::ack;
@@ -0,0 +1,216 @@
+use cosmwasm_std::{
+    Coin, Empty, Order, OverflowError, OverflowOperation, StdError, StdResult, Storage, Timestamp,
+    Uint128,
+};
+
+use crate::state::{
+    Config, Deal, DealStatus, Htlc, HtlcState, State, LOCKED, OPEN_DEALS, OPEN_HTLCS,
+};
+use crate::ContractError;
+
+/// Coins currently escrowed here across all deals, locked HTLCs and makers'
+/// inventory, by denom. Legs held in interchain accounts are not counted.
+pub fn locked(storage: &dyn Storage) -> StdResult<Vec<Coin>> {
+    LOCKED
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+        .collect()
+}
+
+/// Deals `address` is a party to that are still running, or expired with a
+/// leg not yet returned, plus the HTLCs it locked or is paid by that are
+/// still locked.
+pub fn open_deals(
+    storage: &dyn Storage,
+    state: &State,
+    now: Timestamp,
+    address: &str,
+) -> StdResult<u32> {
+    let mut open = OPEN_HTLCS.may_load(storage, address)?.unwrap_or_default();
+    // Deals that expired unfunded since they were last touched are left in
+    // the index, so each is checked again
+    for id in OPEN_DEALS
+        .prefix(address)
+        .keys(storage, None, None, Order::Ascending)
+    {
+        if state.deal(id?).is_ok_and(|deal| is_open(deal, now)) {
+            open += 1;
+        }
+    }
+    Ok(open)
+}
+
+/// Moves the running totals from what deal `deal_id` held and whether it was
+/// open `before` a change to `after` it. `before` is None for a new deal.
+pub fn track_deal(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    deal_id: u64,
+    before: Option<&Deal>,
+    after: &Deal,
+) -> StdResult<()> {
+    for coin in before.into_iter().flat_map(held).flatten() {
+        release(storage, coin)?;
+    }
+    for coin in held(after).into_iter().flatten() {
+        lock(storage, coin)?;
+    }
+    let open = is_open(after, now);
+    for party in [&after.seller, &after.buyer] {
+        if open {
+            OPEN_DEALS.save(storage, (party, deal_id), &Empty {})?;
+        } else {
+            OPEN_DEALS.remove(storage, (party, deal_id));
+        }
+    }
+    Ok(())
+}
+
+/// Counts a newly locked HTLC toward the totals, or drops one that was
+/// claimed or refunded from them.
+pub fn track_htlc(storage: &mut dyn Storage, htlc: &Htlc) -> StdResult<()> {
+    let locking = htlc.state == HtlcState::Locked;
+    if locking {
+        lock(storage, &htlc.amount)?;
+    } else {
+        release(storage, &htlc.amount)?;
+    }
+    let mut parties = vec![&htlc.sender];
+    if htlc.recipient != htlc.sender {
+        parties.push(&htlc.recipient);
+    }
+    for party in parties {
+        let open = OPEN_HTLCS.may_load(storage, party)?.unwrap_or_default();
+        let open = if locking {
+            open.checked_add(1)
+                .ok_or_else(|| OverflowError::new(OverflowOperation::Add, open, 1))?
+        } else {
+            open.checked_sub(1)
+                .ok_or_else(|| OverflowError::new(OverflowOperation::Sub, open, 1))?
+        };
+        OPEN_HTLCS.save(storage, party, &open)?;
+    }
+    Ok(())
+}
+
+/// Rejects a new deal with a leg over its denom's cap, or with a party that
+/// already has as many open deals as allowed.
+pub fn assert_deal_allowed(
+    storage: &dyn Storage,
+    state: &State,
+    config: &Config,
+    now: Timestamp,
+    deal: &Deal,
+) -> Result<(), ContractError> {
+    for coin in [&deal.coin_a, &deal.coin_b] {
+        if coin.amount > cap(&config.max_deal_amounts, &coin.denom) {
+            return Err(ContractError::Std(StdError::generic_err(format!(
+                "Deal amount of {} is over the limit",
+                coin.denom
+            ))));
+        }
+    }
+    assert_open_deals_allowed(storage, state, config, now, [&deal.seller, &deal.buyer])
+}
+
+/// Rejects a new HTLC that would take the coins locked in its denom over the
+/// cap, or with a party that already has as many open deals as allowed.
+pub fn assert_htlc_allowed(
+    storage: &dyn Storage,
+    state: &State,
+    config: &Config,
+    now: Timestamp,
+    htlc: &Htlc,
+) -> Result<(), ContractError> {
+    assert_deposit_allowed(storage, config, &htlc.amount)?;
+    assert_open_deals_allowed(storage, state, config, now, [&htlc.sender, &htlc.recipient])
+}
+
+/// Rejects a deposit that would take the coins locked in its denom over the cap.
+pub fn assert_deposit_allowed(
+    storage: &dyn Storage,
+    config: &Config,
+    deposit: &Coin,
+) -> Result<(), ContractError> {
+    let locked = LOCKED
+        .may_load(storage, &deposit.denom)?
+        .unwrap_or_default();
+    let over = locked.checked_add(deposit.amount).map_or(true, |total| {
+        total > cap(&config.max_locked, &deposit.denom)
+    });
+    if over {
+        return Err(ContractError::Std(StdError::generic_err(format!(
+            "Deposit would take locked {} over the limit",
+            deposit.denom
+        ))));
+    }
+    Ok(())
+}
+
+fn assert_open_deals_allowed(
+    storage: &dyn Storage,
+    state: &State,
+    config: &Config,
+    now: Timestamp,
+    parties: [&String; 2],
+) -> Result<(), ContractError> {
+    if config.max_open_deals > 0 {
+        for party in parties {
+            if open_deals(storage, state, now, party)? >= config.max_open_deals {
+                return Err(ContractError::Std(StdError::generic_err(format!(
+                    "{} has too many open deals",
+                    party
+                ))));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The legs of a deal escrowed here that nobody has been paid yet.
+fn held(deal: &Deal) -> [Option<&Coin>; 2] {
+    [
+        (deal.seller_deposited && !deal.buyer_withdrew && deal.seller_leg.is_none())
+            .then_some(&deal.coin_a),
+        (deal.buyer_deposited && !deal.seller_withdrew && deal.buyer_leg.is_none())
+            .then_some(&deal.coin_b),
+    ]
+}
+
+// Whether a deal still counts toward its parties' open deals.
+fn is_open(deal: &Deal, now: Timestamp) -> bool {
+    match deal.status(now) {
+        DealStatus::Settled | DealStatus::Cancelled => false,
+        DealStatus::Expired => deal.seller_deposited || deal.buyer_deposited,
+        _ => true,
+    }
+}
+
+/// Counts coins set aside here outside a deal or HTLC toward the totals.
+pub fn lock(storage: &mut dyn Storage, coin: &Coin) -> StdResult<()> {
+    LOCKED.update(storage, &coin.denom, |locked| -> StdResult<_> {
+        Ok(locked.unwrap_or_default().checked_add(coin.amount)?)
+    })?;
+    Ok(())
+}
+
+/// Drops coins counted with `lock` from the totals once they leave.
+pub fn release(storage: &mut dyn Storage, coin: &Coin) -> StdResult<()> {
+    let left = LOCKED
+        .may_load(storage, &coin.denom)?
+        .unwrap_or_default()
+        .checked_sub(coin.amount)?;
+    if left.is_zero() {
+        LOCKED.remove(storage, &coin.denom);
+    } else {
+        LOCKED.save(storage, &coin.denom, &left)?;
+    }
+    Ok(())
+}
+
+// Denoms without a cap are unlimited.
+fn cap(caps: &[Coin], denom: &str) -> Uint128 {
+    caps.iter()
+        .find(|cap| cap.denom == denom)
+        .map_or(Uint128::MAX, |cap| cap.amount)
+}
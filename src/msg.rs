@@ -1,31 +1,284 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Coin;
+use cosmwasm_std::{Binary, Coin, HexBinary};
 
-use crate::state::{Deal, Ics20Packet};
+use crate::state::{
+    ChannelInfo, Config, Deal, DenomFee, FeeRate, FundingCheck, Htlc, IbcPayout, InterchainAccount,
+    Notification, PauseScope, RemoteLeg, Role, Side, TraderAllowlist, VolumeTier,
+};
+
+pub use otc_ibc::hooks::IbcLifecycleComplete;
+pub use otc_ibc::IbcExecuteMsg;
 
 #[cw_serde]
 pub struct InstantiateMsg {}
 
+/// Terms of a new deal: coin_a belongs to the seller and coin_b to the buyer.
 #[cw_serde]
-pub enum ExecuteMsg {
-    CreateDeal {
-        seller: String,
-        buyer: String,
-        coin_a: Coin,
-        coin_b: Coin,
-        expiry: u64,
-    },
-    Deposit {},
-    CompleteDeal {},
-    Withdraw {},
-    Reset {},
+#[derive(Default)]
+pub struct DealTerms {
+    pub seller: String,
+    pub buyer: String,
+    pub coin_a: Coin,
+    pub coin_b: Coin,
+    pub expiry: u64,
+    /// channel a remote seller acts and is paid out over. Without it the seller
+    /// must be an address on the host chain.
+    pub seller_channel: Option<String>,
+    /// channel a remote buyer acts and is paid out over. Without it the buyer
+    /// must be an address on the host chain.
+    pub buyer_channel: Option<String>,
+    /// keeps the seller's leg on its home chain, locked in the contract's
+    /// interchain account there until settlement
+    pub seller_leg: Option<RemoteLeg>,
+    /// keeps the buyer's leg on its home chain, locked in the contract's
+    /// interchain account there until settlement
+    pub buyer_leg: Option<RemoteLeg>,
+    /// has the agents of remote parties report their balance, and keeps the
+    /// deal from being funded until each party holds its leg
+    #[serde(default)]
+    pub funding_check: bool,
+    /// partner that introduced the deal, paid a commission from each leg at settlement
+    pub broker: Option<String>,
+    /// the broker's commission in basis points of each leg
+    #[serde(default)]
+    pub broker_fee_bps: u16,
+    /// can rule on disputes over the deal once it is funded
+    pub arbitrator: Option<String>,
+    /// accounts that have to approve the deal before it settles, on top of the
+    /// approver group large deals need. Neither party can be one.
+    #[serde(default)]
+    pub approvers: Vec<String>,
+    /// how many of `approvers` have to approve
+    #[serde(default)]
+    pub approvals_required: u32,
+    /// lets the deal through at a price outside the oracle band. It is
+    /// flagged as off-market instead. Unless an operator creates the deal,
+    /// its creator has to be a party and the other party has to accept it.
+    #[serde(default)]
+    pub off_market: bool,
+    /// settles the buyer leg at the oracle price plus this spread in basis
+    /// points. `coin_b` is then the most the buyer deposits, and whatever is
+    /// left over is refunded.
+    pub peg_spread_bps: Option<i16>,
 }
 
 #[cw_serde]
-pub enum IbcExecuteMsg {
-    Deposit { packet20: Ics20Packet },
-    Withdraw { packet20: Ics20Packet },
-    CompleteDeal { packet20: Ics20Packet },
+#[allow(clippy::large_enum_variant)]
+pub enum ExecuteMsg {
+    CreateDeal(DealTerms),
+    // `Deposit`, `CompleteDeal` and `Withdraw` name their deal by `deal_id`.
+    // They used to take no fields and find the sender's deal by address and
+    // denom, which picked the wrong deal once a party had several open, so
+    // clients sending `{}` must now pass the id returned by `CreateDeal`.
+    /// Funds the sender's leg of deal `deal_id` with the attached coin.
+    Deposit {
+        deal_id: u64,
+    },
+    /// Pays the sender out the other leg of deal `deal_id` once both are funded.
+    CompleteDeal {
+        deal_id: u64,
+    },
+    /// Refunds the sender's leg of deal `deal_id` while the other is unfunded.
+    Withdraw {
+        deal_id: u64,
+    },
+    /// Credits the attached coin to remote party `sender`'s leg of deal
+    /// `deal_id`. Called by ibc-hooks when a remote agent's ICS-20 transfer
+    /// arrives, from the intermediary address derived from the agent and the
+    /// transfer channel. Failing it fails the transfer, which returns the
+    /// coin to the agent.
+    DepositIbc {
+        deal_id: u64,
+        sender: String,
+    },
+    /// Drops all deals. Fails while any deal still holds a deposit.
+    Reset {},
+    /// Returns the deposited legs of a deal that nobody has been paid from to
+    /// their depositors and cancels it. Operator only.
+    ForceRefund {
+        deal_id: u64,
+    },
+    /// Pays out both legs of a funded deal, even after expiry. Operator only.
+    ForceSettle {
+        deal_id: u64,
+    },
+    /// Accepts a deal proposed to the sender over IBC.
+    AcceptDeal {
+        deal_id: u64,
+    },
+    /// Calls off a deal of the sender's before either leg is deposited.
+    CancelDeal {
+        deal_id: u64,
+    },
+    /// Tells the remote parties of an expired, unfinished deal that it expired.
+    NotifyExpired {
+        deal_id: u64,
+    },
+    /// Allows channels from `counterparty_port_id` on `connection_id` to be opened.
+    /// Payouts to parties reached over those channels go over `transfer_channel`.
+    AddChannel {
+        connection_id: String,
+        counterparty_port_id: String,
+        transfer_channel: String,
+    },
+    /// Removes a counterparty from the allowlist. Channels already open with it
+    /// stop accepting packets.
+    RemoveChannel {
+        connection_id: String,
+        counterparty_port_id: String,
+    },
+    /// Rejects all packets arriving on an open channel until unfrozen.
+    FreezeChannel {
+        channel_id: String,
+    },
+    UnfreezeChannel {
+        channel_id: String,
+    },
+    /// Opens an interchain account controller channel on `connection_id`.
+    /// `host_connection_id` is the same connection as seen from the host chain.
+    RegisterInterchainAccount {
+        connection_id: String,
+        host_connection_id: String,
+    },
+    /// Asks remote parties that have not yet passed the funding check of a
+    /// deal, and have no query in flight, for their balance again.
+    CheckFunding {
+        deal_id: u64,
+    },
+    /// Locks the attached coin for `recipient` as one leg of an atomic swap.
+    /// `hashlock` is the sha256 of a secret only the sender knows, `timelock`
+    /// the unix time in seconds from which the sender can take it back.
+    LockHtlc {
+        recipient: String,
+        hashlock: HexBinary,
+        timelock: u64,
+    },
+    /// Releases an HTLC to its recipient. Anyone holding the preimage can claim.
+    ClaimHtlc {
+        id: u64,
+        preimage: HexBinary,
+    },
+    /// Returns an unclaimed HTLC to its sender once its timelock has passed.
+    RefundHtlc {
+        id: u64,
+    },
+    /// Replaces the protocol fees taken from settlement payouts.
+    UpdateFees {
+        fee: FeeRate,
+        denom_fees: Vec<DenomFee>,
+        fee_collector: Option<String>,
+    },
+    /// Sets the fee discounts for deal creators and for settled volume.
+    UpdateFeeTiers {
+        maker_discount_bps: u16,
+        volume_tiers: Vec<VolumeTier>,
+        volume_window: u64,
+    },
+    /// Grants `address` a negotiated fee discount, or removes it if unset.
+    SetFeeDiscount {
+        address: String,
+        discount_bps: Option<u16>,
+    },
+    /// Sends the accrued fees to `recipient`, or to the sender if unset.
+    ClaimFees {
+        recipient: Option<String>,
+    },
+    /// Stops `scopes` until they are unpaused. Operator or guardian only.
+    Pause {
+        scopes: Vec<PauseScope>,
+    },
+    Unpause {
+        scopes: Vec<PauseScope>,
+    },
+    /// Sets or removes the account that may pause the contract besides operators.
+    SetGuardian {
+        guardian: Option<String>,
+    },
+    /// Freezes payouts and refunds of a funded deal of the sender's until its
+    /// arbitrator rules.
+    RaiseDispute {
+        deal_id: u64,
+        reason: String,
+    },
+    /// Splits the escrowed legs of a disputed deal between its parties. The
+    /// split must add up to both legs. Arbitrator or operator only.
+    Resolve {
+        deal_id: u64,
+        seller_gets: Vec<Coin>,
+        buyer_gets: Vec<Coin>,
+    },
+    /// Approves settling a deal the sender is an approver of.
+    Approve {
+        deal_id: u64,
+    },
+    /// Sets which deals count as large and who approves them. Owner only.
+    UpdateApprovalPolicy {
+        large_deal_thresholds: Vec<Coin>,
+        approver_group: Option<String>,
+        group_approvals_required: u32,
+    },
+    /// Caps deal sizes, open deals per address and coins escrowed per denom.
+    /// Owner only.
+    UpdateLimits {
+        max_deal_amounts: Vec<Coin>,
+        max_open_deals: u32,
+        max_locked: Vec<Coin>,
+    },
+    /// Sets or removes the oracle deal prices are checked against at creation
+    /// and settlement, and how far from its price they can be. Owner only.
+    UpdatePriceOracle {
+        oracle: Option<String>,
+        band_bps: u16,
+    },
+    /// Sets or removes the contract parties are screened with, answering
+    /// `ScreeningQueryMsg`. Owner only.
+    SetScreening {
+        contract: Option<String>,
+    },
+    /// Sets who may create and fund deals. Owner only.
+    SetTraderAllowlist {
+        traders: TraderAllowlist,
+    },
+    /// Edits the internal trader allowlist. Owner only.
+    UpdateTraders {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Sets the attached coins aside for the sender's quotes.
+    DepositInventory {},
+    WithdrawInventory {
+        coins: Vec<Coin>,
+    },
+    /// Sets the secp256k1 public key the sender signs quotes with.
+    RegisterQuoteKey {
+        pubkey: Binary,
+    },
+    /// Keeps the sender's quote with `nonce` from being taken.
+    CancelQuote {
+        nonce: u64,
+    },
+    /// Takes a quote signed by its maker, creating a deal funded from the
+    /// maker's inventory and the taker's leg, which has to be attached.
+    TakeQuote {
+        quote: Quote,
+        signature: Binary,
+        maker_pubkey: Binary,
+    },
+    /// Gives `address` a role. Owner only.
+    GrantRole {
+        role: Role,
+        address: String,
+    },
+    /// Takes a role from `address`. The last owner cannot be revoked.
+    RevokeRole {
+        role: Role,
+        address: String,
+    },
+    /// Sends the payouts of a deal whose IBC transfer failed or timed out
+    /// again, along the parties' current payout routes. Anyone may retry them.
+    RetryPayouts {
+        deal_id: u64,
+    },
 }
 
 #[cw_serde]
@@ -38,6 +291,119 @@ pub struct DealResponse {
     deal: Deal,
 }
 
+#[cw_serde]
+pub struct AllowedCounterparty {
+    pub connection_id: String,
+    pub counterparty_port_id: String,
+    pub transfer_channel: String,
+}
+
+#[cw_serde]
+pub struct AllowedCounterpartiesResponse {
+    pub counterparties: Vec<AllowedCounterparty>,
+}
+
+#[cw_serde]
+pub struct ChannelsResponse {
+    pub channels: Vec<ChannelInfo>,
+}
+
+#[cw_serde]
+pub struct NotificationsResponse {
+    pub notifications: Vec<Notification>,
+}
+
+/// The interchain accounts holding the legs of a deal that stay on their home chain.
+#[cw_serde]
+pub struct InterchainAccountsResponse {
+    pub seller: Option<InterchainAccount>,
+    pub buyer: Option<InterchainAccount>,
+}
+
+#[cw_serde]
+pub struct AccruedFeesResponse {
+    pub fees: Vec<Coin>,
+}
+
+/// What `address` currently pays fees at.
+#[cw_serde]
+pub struct AccountFeesResponse {
+    pub discount_bps: Option<u16>,
+    /// volume settled in the current window, by denom
+    pub volumes: Vec<Coin>,
+}
+
+/// Deal terms a maker signs off-chain for a taker to submit. The maker signs
+/// the sha256 hash of its JSON encoding.
+#[cw_serde]
+pub struct Quote {
+    /// contract the quote can be taken on
+    pub contract: String,
+    pub maker: String,
+    /// the only account that can take the quote, anyone if unset
+    pub taker: Option<String>,
+    /// whether the maker is the seller, giving `coin_a`, or the buyer
+    pub maker_side: Side,
+    pub coin_a: Coin,
+    pub coin_b: Coin,
+    /// used once per maker
+    pub nonce: u64,
+    /// last block time, in seconds, the quote can be taken at
+    pub valid_until: u64,
+    /// expiry of the deal taking the quote creates, no earlier than `valid_until`
+    pub deal_expiry: u64,
+}
+
+#[cw_serde]
+pub struct InventoryResponse {
+    pub coins: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct UtilisationResponse {
+    pub locked: Vec<Coin>,
+    pub max_locked: Vec<Coin>,
+    /// open deals and locked HTLCs of the queried address
+    pub open_deals: Option<u32>,
+    pub max_open_deals: u32,
+}
+
+#[cw_serde]
+pub struct TraderResponse {
+    pub allowed: bool,
+}
+
+#[cw_serde]
+pub struct RolesResponse {
+    pub roles: Vec<Role>,
+}
+
+#[cw_serde]
+pub struct RoleMembersResponse {
+    pub members: Vec<String>,
+}
+
+#[cw_serde]
+pub struct BrokerEarningsResponse {
+    pub earnings: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct PauseStatusResponse {
+    pub paused: Vec<PauseScope>,
+    pub guardian: Option<String>,
+}
+
+#[cw_serde]
+pub struct FundingChecksResponse {
+    pub checks: Vec<FundingCheck>,
+}
+
+#[cw_serde]
+pub struct FailedPayoutsResponse {
+    pub payouts: Vec<IbcPayout>,
+}
+
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
@@ -45,4 +411,48 @@ pub enum QueryMsg {
     GetBalances { address: String },
     #[returns(DealResponse)]
     GetDeal { id: u64 },
+    #[returns(AllowedCounterpartiesResponse)]
+    AllowedCounterparties {},
+    #[returns(ChannelsResponse)]
+    Channels {},
+    #[returns(NotificationsResponse)]
+    Notifications { deal_id: u64 },
+    #[returns(InterchainAccountsResponse)]
+    InterchainAccounts { deal_id: u64 },
+    #[returns(FundingChecksResponse)]
+    FundingChecks { deal_id: u64 },
+    #[returns(Htlc)]
+    Htlc { id: u64 },
+    #[returns(Config)]
+    Config {},
+    #[returns(AccruedFeesResponse)]
+    AccruedFees {},
+    #[returns(AccountFeesResponse)]
+    AccountFees { address: String },
+    #[returns(BrokerEarningsResponse)]
+    BrokerEarnings { broker: String },
+    #[returns(PauseStatusResponse)]
+    PauseStatus {},
+    #[returns(InventoryResponse)]
+    Inventory { maker: String },
+    /// Coins escrowed against their limits, and the open deals of `address`.
+    #[returns(UtilisationResponse)]
+    Utilisation { address: Option<String> },
+    /// Whether `address` may trade under the current allowlist.
+    #[returns(TraderResponse)]
+    Trader { address: String },
+    /// Roles held by `address`.
+    #[returns(RolesResponse)]
+    Roles { address: String },
+    #[returns(RoleMembersResponse)]
+    RoleMembers { role: Role },
+    /// Payouts of a deal waiting to be retried after their transfer failed.
+    #[returns(FailedPayoutsResponse)]
+    FailedPayouts { deal_id: u64 },
+}
+
+/// ibc-hooks reports back on the transfers remote parties are paid with.
+#[cw_serde]
+pub enum SudoMsg {
+    IbcLifecycleComplete(IbcLifecycleComplete),
 }
@@ -0,0 +1,75 @@
+use cosmwasm_std::{from_json, to_json_binary, Env, IbcMsg, IbcPacket, StdResult, Storage};
+use otc_ibc::IbcHostMsg;
+
+use crate::state::{DeliveryStatus, Notification, CHANNELS, NOTIFICATIONS};
+
+/// Seconds an outgoing packet or payout stays valid.
+pub const PACKET_LIFETIME: u64 = 100;
+
+/// Builds a packet telling the remote party on each of `channels` about `msg`
+/// and records it as pending until it is acknowledged. Parties on the host
+/// chain (empty channel) and channels that are closed or frozen are skipped.
+pub fn notify(
+    storage: &mut dyn Storage,
+    env: &Env,
+    channels: &[&str],
+    msg: &IbcHostMsg,
+) -> StdResult<Vec<IbcMsg>> {
+    let mut sent = NOTIFICATIONS
+        .may_load(storage, msg.deal_id())?
+        .unwrap_or_default();
+    let mut packets = vec![];
+
+    for (i, channel_id) in channels.iter().enumerate() {
+        // Both parties may be reached over the same channel
+        if channel_id.is_empty() || channels[..i].contains(channel_id) {
+            continue;
+        }
+        match CHANNELS.may_load(storage, channel_id)? {
+            Some(channel) if !channel.frozen => {}
+            _ => continue,
+        }
+
+        packets.push(IbcMsg::SendPacket {
+            channel_id: channel_id.to_string(),
+            data: to_json_binary(msg)?,
+            timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+        });
+        sent.push(Notification {
+            event: msg.event().to_string(),
+            channel_id: channel_id.to_string(),
+            status: DeliveryStatus::Pending,
+        });
+    }
+
+    if !packets.is_empty() {
+        NOTIFICATIONS.save(storage, msg.deal_id(), &sent)?;
+    }
+    Ok(packets)
+}
+
+/// Records how a notification packet we sent ended up. Returns false if the
+/// packet was not a notification.
+pub fn record_delivery(
+    storage: &mut dyn Storage,
+    packet: &IbcPacket,
+    status: DeliveryStatus,
+) -> StdResult<bool> {
+    let Ok(msg) = from_json::<IbcHostMsg>(&packet.data) else {
+        return Ok(false);
+    };
+
+    let mut sent = NOTIFICATIONS
+        .may_load(storage, msg.deal_id())?
+        .unwrap_or_default();
+    let pending = sent.iter_mut().find(|notification| {
+        notification.status == DeliveryStatus::Pending
+            && notification.event == msg.event()
+            && notification.channel_id == packet.src.channel_id
+    });
+    if let Some(notification) = pending {
+        notification.status = status;
+        NOTIFICATIONS.save(storage, msg.deal_id(), &sent)?;
+    }
+    Ok(true)
+}
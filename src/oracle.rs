@@ -0,0 +1,129 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal, Deps, StdError, StdResult, Uint128};
+
+use crate::state::{Config, Deal};
+use crate::ContractError;
+
+/// Query a price oracle has to answer, for a Pyth adapter or an admin-fed
+/// price contract alike.
+#[cw_serde]
+pub enum OracleQueryMsg {
+    /// units of `quote` one unit of `base` is worth
+    Price { base: String, quote: String },
+}
+
+#[cw_serde]
+pub struct PriceResponse {
+    pub price: Decimal,
+}
+
+/// Price of `base` in `quote` according to `oracle`.
+pub fn price(deps: Deps, oracle: &str, base: &str, quote: &str) -> StdResult<Decimal> {
+    let res: PriceResponse = deps.querier.query_wasm_smart(
+        oracle,
+        &OracleQueryMsg::Price {
+            base: base.to_string(),
+            quote: quote.to_string(),
+        },
+    )?;
+    Ok(res.price)
+}
+
+/// Rejects a deal whose price, `coin_b` per `coin_a`, is further from the
+/// oracle price than the configured band, unless its parties accepted an
+/// off-market price. Passes when no oracle is configured.
+pub fn assert_price_in_band(deps: Deps, config: &Config, deal: &Deal) -> Result<(), ContractError> {
+    if let Some((implied, market)) = outside_band(deps, config, deal)? {
+        return Err(ContractError::Std(StdError::generic_err(format!(
+            "Deal price {} is outside the band around the oracle price {}",
+            implied, market
+        ))));
+    }
+    Ok(())
+}
+
+/// Whether `assert_price_in_band` would reject the deal.
+pub fn is_outside_band(deps: Deps, config: &Config, deal: &Deal) -> Result<bool, ContractError> {
+    Ok(outside_band(deps, config, deal)?.is_some())
+}
+
+// The deal's implied price and the oracle price, if they are too far apart.
+fn outside_band(
+    deps: Deps,
+    config: &Config,
+    deal: &Deal,
+) -> Result<Option<(Decimal, Decimal)>, ContractError> {
+    let Some(oracle) = &config.price_oracle else {
+        return Ok(None);
+    };
+    // Pegged deals have no price until they settle
+    if deal.off_market || deal.peg.is_some() {
+        return Ok(None);
+    }
+    let market = price(deps, oracle, &deal.coin_a.denom, &deal.coin_b.denom)?;
+    let implied = Decimal::checked_from_ratio(deal.coin_b.amount, deal.coin_a.amount)
+        .map_err(|_| StdError::generic_err("Deal legs cannot be priced"))?;
+    let deviation = if implied > market {
+        implied - market
+    } else {
+        market - implied
+    };
+    if deviation > market * Decimal::bps(config.price_band_bps.into()) {
+        return Ok(Some((implied, market)));
+    }
+    Ok(None)
+}
+
+/// What the buyer of a pegged deal owes at the oracle price plus the deal's
+/// spread, along with that price. Fails if it is more than the buyer deposited.
+pub fn pegged_amount(
+    deps: Deps,
+    config: &Config,
+    deal: &Deal,
+    spread_bps: i16,
+) -> Result<(Uint128, Decimal), ContractError> {
+    let oracle = config
+        .price_oracle
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("Pegged deals need a price oracle"))?;
+    let (owed, price) = pegged_owed(deps, oracle, deal, spread_bps)?;
+    if owed > deal.coin_b.amount {
+        return Err(ContractError::Std(StdError::generic_err(format!(
+            "Buyer owes {} at price {}, more than the {} deposited",
+            owed, price, deal.coin_b
+        ))));
+    }
+    Ok((owed, price))
+}
+
+/// Whether a pegged deal whose price is not fixed yet cannot settle now,
+/// because the buyer would owe more than the `coin_b` it deposited or no
+/// oracle is left to price it.
+pub fn is_pegged_over_cap(deps: Deps, config: &Config, deal: &Deal) -> Result<bool, ContractError> {
+    let Some(peg) = deal.peg.as_ref().filter(|peg| peg.price.is_none()) else {
+        return Ok(false);
+    };
+    let Some(oracle) = &config.price_oracle else {
+        return Ok(true);
+    };
+    let (owed, _) = pegged_owed(deps, oracle, deal, peg.spread_bps)?;
+    Ok(owed > deal.coin_b.amount)
+}
+
+// What the buyer of a pegged deal owes at the oracle price plus the spread,
+// along with that price.
+fn pegged_owed(
+    deps: Deps,
+    oracle: &str,
+    deal: &Deal,
+    spread_bps: i16,
+) -> Result<(Uint128, Decimal), ContractError> {
+    let market = price(deps, oracle, &deal.coin_a.denom, &deal.coin_b.denom)?;
+    let spread = market * Decimal::bps(spread_bps.unsigned_abs().into());
+    let price = if spread_bps < 0 {
+        market - spread
+    } else {
+        market + spread
+    };
+    Ok((deal.coin_a.amount.mul_floor(price), price))
+}
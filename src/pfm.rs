@@ -0,0 +1,94 @@
+use cosmwasm_std::{to_json_string, Coin, CosmosMsg, Env, StdError, StdResult};
+use otc_ibc::{proto, PayoutRoute};
+use serde::Serialize;
+
+use crate::notify::PACKET_LIFETIME;
+
+#[derive(Serialize)]
+struct ForwardMemo {
+    forward: Forward,
+}
+
+// A payout's memo also asks ibc-hooks to report how its transfer went.
+#[derive(Serialize)]
+struct PayoutMemo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forward: Option<Forward>,
+    ibc_callback: String,
+}
+
+#[derive(Serialize)]
+struct Forward {
+    receiver: String,
+    port: &'static str,
+    channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retries: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<Box<ForwardMemo>>,
+}
+
+/// Rejects routes packet-forward-middleware could not follow.
+pub fn validate_route(route: &PayoutRoute) -> StdResult<()> {
+    if route.hops.is_empty() {
+        return Err(StdError::generic_err("Payout route has no hops"));
+    }
+    if route
+        .hops
+        .iter()
+        .any(|hop| hop.channel.is_empty() || hop.receiver.is_empty())
+    {
+        return Err(StdError::generic_err(
+            "Every hop needs a channel and a receiver",
+        ));
+    }
+    Ok(())
+}
+
+/// The packet-forward-middleware memo that forwards a transfer along `route`.
+pub fn forward_memo(route: &PayoutRoute) -> StdResult<String> {
+    let memo = forward(route).ok_or_else(|| StdError::generic_err("Payout route has no hops"))?;
+    to_json_string(&memo)
+}
+
+/// An ICS-20 payout of `amount` to `receiver` over `channel_id`, forwarded
+/// along `route` from there if it has one. ibc-hooks reports back to the
+/// contract once the transfer is acknowledged or times out.
+pub fn payout_transfer(
+    env: &Env,
+    channel_id: &str,
+    receiver: &str,
+    amount: &Coin,
+    route: Option<&PayoutRoute>,
+) -> StdResult<CosmosMsg> {
+    let memo = PayoutMemo {
+        forward: route.and_then(forward).map(|memo| memo.forward),
+        ibc_callback: env.contract.address.to_string(),
+    };
+    Ok(proto::transfer(
+        channel_id,
+        env.contract.address.as_str(),
+        receiver,
+        amount,
+        env.block.time.plus_seconds(PACKET_LIFETIME),
+        &to_json_string(&memo)?,
+    ))
+}
+
+fn forward(route: &PayoutRoute) -> Option<Box<ForwardMemo>> {
+    // Nest from the last hop outwards
+    route.hops.iter().rev().fold(None, |next, hop| {
+        Some(Box::new(ForwardMemo {
+            forward: Forward {
+                receiver: hop.receiver.clone(),
+                port: "transfer",
+                channel: hop.channel.clone(),
+                timeout: hop.timeout_seconds.map(|seconds| format!("{}s", seconds)),
+                retries: hop.retries,
+                next,
+            },
+        }))
+    })
+}
@@ -0,0 +1,29 @@
+use cosmwasm_std::{to_json_vec, Binary, Deps, StdResult};
+use sha2::{Digest, Sha256};
+
+use crate::msg::Quote;
+use crate::ContractError;
+
+/// Hash a maker signs to quote: sha256 of the quote's JSON encoding.
+pub fn quote_hash(quote: &Quote) -> StdResult<[u8; 32]> {
+    Ok(Sha256::digest(to_json_vec(quote)?).into())
+}
+
+/// Rejects a quote not signed with `pubkey`.
+pub fn verify(
+    deps: Deps,
+    quote: &Quote,
+    signature: &Binary,
+    pubkey: &Binary,
+) -> Result<(), ContractError> {
+    let hash = quote_hash(quote)?;
+    // Malformed signatures and keys are as good as wrong ones
+    if !deps
+        .api
+        .secp256k1_verify(&hash, signature, pubkey)
+        .unwrap_or(false)
+    {
+        return Err(ContractError::InvalidQuoteSignature {});
+    }
+    Ok(())
+}
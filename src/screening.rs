@@ -0,0 +1,39 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{to_json_binary, Deps, QueryRequest, WasmQuery};
+
+use crate::state::CONFIG;
+use crate::ContractError;
+
+/// Query a screening contract has to answer. Addresses of parties on other
+/// chains are screened too, in their own chain's format.
+#[cw_serde]
+pub enum ScreeningQueryMsg {
+    Screen { address: String },
+}
+
+#[cw_serde]
+pub struct ScreenResponse {
+    pub flagged: bool,
+    pub reason: Option<String>,
+}
+
+/// Rejects `address` if the configured screening contract flags it.
+pub fn assert_not_flagged(deps: Deps, address: &str) -> Result<(), ContractError> {
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let Some(screening_contract) = config.screening_contract else {
+        return Ok(());
+    };
+    let res: ScreenResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: screening_contract,
+        msg: to_json_binary(&ScreeningQueryMsg::Screen {
+            address: address.to_string(),
+        })?,
+    }))?;
+    if res.flagged {
+        return Err(ContractError::AddressFlagged {
+            address: address.to_string(),
+            reason: res.reason.unwrap_or_default(),
+        });
+    }
+    Ok(())
+}
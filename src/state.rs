@@ -1,22 +1,11 @@
-use cosmwasm_std::{Coin, Uint128};
-use cw_storage_plus::Item;
+use cosmwasm_std::{Addr, Binary, Coin, Decimal, Empty, StdError, StdResult, Timestamp, Uint128};
+use cw_storage_plus::{Item, Map};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema, Debug, Default)]
-pub struct Ics20Packet {
-    /// amount of tokens to transfer is encoded as a string, but limited to u64 max
-    pub amount: Uint128,
-    /// the token denomination to be transferred
-    pub denom: String,
-    /// the recipient address on the destination chain
-    pub receiver: String,
-    /// the sender address
-    pub sender: String,
-    /// optional memo for the IBC transfer
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub memo: Option<String>,
-}
+pub use otc_ibc::ack::DealStatus;
+pub use otc_ibc::htlc::{Htlc, HtlcState};
+pub use otc_ibc::{DealProposal, Ics20Packet, PayoutRoute};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct Deal {
@@ -32,11 +21,465 @@ pub struct Deal {
     pub buyer_withdrew: bool,
     pub channel_id_recieved_a: String,
     pub channel_id_recieved_b: String,
+    /// set when the seller's leg stays locked in an interchain account on its home chain
+    #[serde(default)]
+    pub seller_leg: Option<RemoteLeg>,
+    /// set when the buyer's leg stays locked in an interchain account on its home chain
+    #[serde(default)]
+    pub buyer_leg: Option<RemoteLeg>,
+    /// forwards the remote seller's IBC payouts past its own chain
+    #[serde(default)]
+    pub seller_route: Option<PayoutRoute>,
+    /// forwards the remote buyer's IBC payouts past its own chain
+    #[serde(default)]
+    pub buyer_route: Option<PayoutRoute>,
+    /// party that still has to accept a deal proposed over IBC before it can be funded
+    #[serde(default)]
+    pub awaiting_acceptance: Option<String>,
+    /// called off by a party before any leg was deposited; cancelled deals are also finished
+    #[serde(default)]
+    pub cancelled: bool,
+    /// remote parties escrowing here must prove they hold their leg before it can be funded
+    #[serde(default)]
+    pub funding_check: bool,
+    /// the account that created the deal, which pays maker fees when it is a party
+    #[serde(default)]
+    pub creator: String,
+    /// introduced the deal and takes `broker_fee_bps` of each leg paid out here
+    #[serde(default)]
+    pub broker: Option<String>,
+    #[serde(default)]
+    pub broker_fee_bps: u16,
+    /// may rule on a dispute over the deal, splitting its escrowed legs
+    #[serde(default)]
+    pub arbitrator: Option<String>,
+    #[serde(default)]
+    pub dispute: Option<Dispute>,
+    /// accounts that can approve the deal
+    #[serde(default)]
+    pub approvers: Vec<String>,
+    /// cw4 group whose members can approve the deal
+    #[serde(default)]
+    pub approver_group: Option<String>,
+    /// approvals needed from `approvers` before the deal can settle
+    #[serde(default)]
+    pub approvals_required: u32,
+    /// approvals needed from members of `approver_group` as well
+    #[serde(default)]
+    pub group_approvals_required: u32,
+    #[serde(default)]
+    pub approvals: Vec<String>,
+    /// the parties accepted a price outside the oracle band
+    #[serde(default)]
+    pub off_market: bool,
+    /// settles the buyer leg at the oracle price, with `coin_b` as the most
+    /// the buyer pays
+    #[serde(default)]
+    pub peg: Option<Peg>,
+}
+
+impl Deal {
+    /// Whether payouts and refunds are frozen waiting for the arbitrator.
+    pub fn disputed(&self) -> bool {
+        self.dispute
+            .as_ref()
+            .is_some_and(|dispute| dispute.ruling.is_none())
+    }
+
+    pub fn status(&self, now: Timestamp) -> DealStatus {
+        if self.cancelled {
+            DealStatus::Cancelled
+        } else if self.finished {
+            DealStatus::Settled
+        } else if now > Timestamp::from_seconds(self.expiry) {
+            DealStatus::Expired
+        } else if self.awaiting_acceptance.is_some() {
+            DealStatus::Proposed
+        } else if self.seller_deposited && self.buyer_deposited {
+            DealStatus::Funded
+        } else if self.seller_deposited || self.buyer_deposited {
+            DealStatus::PartiallyFunded
+        } else {
+            DealStatus::Open
+        }
+    }
+}
+
+/// Prices the buyer leg of a deal from the oracle when it settles.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Peg {
+    /// added to the oracle price, in basis points of it; negative for a discount
+    pub spread_bps: i16,
+    /// price fixed at the first payout, after which `coin_b` is what the buyer pays
+    pub price: Option<Decimal>,
+}
+
+/// A party's objection to a funded deal, which freezes it until ruled on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Dispute {
+    pub raised_by: String,
+    pub reason: String,
+    pub raised_at: Timestamp,
+    pub ruling: Option<Ruling>,
+}
+
+/// How the arbitrator split the escrowed legs between the parties.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Ruling {
+    pub seller_gets: Vec<Coin>,
+    pub buyer_gets: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Seller,
+    Buyer,
+}
+
+/// A deal leg held by the contract's interchain account on another chain
+/// instead of being escrowed here.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct RemoteLeg {
+    /// connection to the chain the leg stays on
+    pub connection_id: String,
+    /// receives the leg on that chain when the deal settles. The leg is
+    /// refunded to its owner, who it was taken from.
+    pub payout_address: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct State {
     pub deals: Vec<Deal>,
+    /// id of `deals[0]`. A reset drops the deals but not their ids, so acks
+    /// still in flight for them never land on a newer deal.
+    #[serde(default)]
+    pub first_deal_id: u64,
+}
+
+impl State {
+    pub fn deal(&self, deal_id: u64) -> StdResult<&Deal> {
+        self.index(deal_id)
+            .and_then(|index| self.deals.get(index))
+            .ok_or_else(|| StdError::generic_err("No deal found"))
+    }
+
+    pub fn deal_mut(&mut self, deal_id: u64) -> StdResult<&mut Deal> {
+        self.index(deal_id)
+            .and_then(|index| self.deals.get_mut(index))
+            .ok_or_else(|| StdError::generic_err("No deal found"))
+    }
+
+    /// Id the next deal created is given.
+    pub fn next_deal_id(&self) -> u64 {
+        self.first_deal_id + self.deals.len() as u64
+    }
+
+    fn index(&self, deal_id: u64) -> Option<usize> {
+        let index = deal_id.checked_sub(self.first_deal_id)?;
+        usize::try_from(index).ok()
+    }
 }
 
 pub const STATE: Item<State> = Item::new("AWESOME");
+
+/// Id of the last payout made, see `otc_ibc::ack::Payout`.
+pub const LAST_PAYOUT_ID: Item<u64> = Item::new("payout_sequence");
+
+/// A payout sent to a remote party over ICS-20.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct IbcPayout {
+    pub deal_id: u64,
+    pub party: String,
+    /// the app channel the party is reached on
+    pub channel_id: String,
+    pub amount: Coin,
+}
+
+/// Payouts whose transfer is being sent, keyed by the id of the reply that
+/// reports its packet sequence.
+pub const SENDING_PAYOUTS: Map<u64, IbcPayout> = Map::new("sending_payouts");
+
+/// Payouts in flight, keyed by (transfer channel, packet sequence) until
+/// ibc-hooks reports how the transfer went.
+pub const PAYOUT_TRANSFERS: Map<(&str, u64), IbcPayout> = Map::new("payout_transfers");
+
+/// Payouts whose transfer failed or timed out, keyed by (deal_id, transfer
+/// channel, packet sequence). ICS-20 returned their coins here, and anyone
+/// may send them again.
+pub const FAILED_PAYOUTS: Map<(u64, &str, u64), IbcPayout> = Map::new("failed_payouts");
+
+/// An open channel with a counterparty that passed the allowlist check during the handshake.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ChannelInfo {
+    pub channel_id: String,
+    pub connection_id: String,
+    pub counterparty_port_id: String,
+    pub counterparty_channel_id: String,
+    /// the app protocol version negotiated during the handshake
+    pub version: String,
+    /// the ICS-20 channel used to pay out parties reached over this channel
+    pub transfer_channel: String,
+    /// frozen channels stay open but have all incoming packets rejected
+    pub frozen: bool,
+}
+
+/// (connection_id, counterparty_port_id) pairs channel managers allow to open channels,
+/// mapped to the ICS-20 channel payouts to that counterparty's chain go over.
+pub const ALLOWED_COUNTERPARTIES: Map<(&str, &str), String> = Map::new("allowed_counterparties");
+
+/// Channels that completed the handshake, keyed by our channel id.
+pub const CHANNELS: Map<&str, ChannelInfo> = Map::new("channels");
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+    TimedOut,
+}
+
+/// A deal state change packet sent to a remote party.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Notification {
+    pub event: String,
+    pub channel_id: String,
+    pub status: DeliveryStatus,
+}
+
+/// Notifications sent for each deal, in the order they were sent.
+pub const NOTIFICATIONS: Map<u64, Vec<Notification>> = Map::new("notifications");
+
+/// The contract's interchain account on another chain. ICS-27 derives the
+/// account address from the connection and our port, so every channel opened
+/// on the same connection controls the same account.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct InterchainAccount {
+    pub connection_id: String,
+    pub host_connection_id: String,
+    /// the open controller channel, if any
+    pub channel_id: Option<String>,
+    /// empty until the first channel completes its handshake
+    pub address: String,
+    /// a leg being taken into the account is awaiting its acknowledgement
+    pub lock_pending: bool,
+}
+
+/// Interchain accounts registered by channel managers, keyed by our connection id.
+pub const INTERCHAIN_ACCOUNTS: Map<&str, InterchainAccount> = Map::new("interchain_accounts");
+
+/// The latest balance a remote party's agent reported for its leg of a deal.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct FundingCheck {
+    pub party: String,
+    pub required: Coin,
+    pub available: Uint128,
+    /// block height on the party's chain the balance was read at
+    pub height: u64,
+    pub sufficient: bool,
+}
+
+/// Funding checks keyed by (deal_id, party).
+pub const FUNDING_CHECKS: Map<(u64, &str), FundingCheck> = Map::new("funding_checks");
+
+/// Balance queries sent and not yet acked or timed out, keyed by (deal_id, party).
+pub const PENDING_FUNDING_CHECKS: Map<(u64, &str), Empty> = Map::new("pending_funding_checks");
+
+/// Hashed time-locked legs of cross-chain atomic swaps, keyed by id.
+pub const HTLCS: Map<u64, Htlc> = Map::new("htlcs");
+
+/// Number of HTLCs created, the id of the next one.
+pub const HTLC_COUNT: Item<u64> = Item::new("htlc_count");
+
+/// A protocol fee on the legs paid out at settlement.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct FeeRate {
+    /// share of the payout in basis points
+    pub bps: u16,
+    /// charged instead when the share is smaller, in the payout's denom
+    pub min: Uint128,
+}
+
+/// A fee rate that replaces the default one for payouts in `denom`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct DenomFee {
+    pub denom: String,
+    pub rate: FeeRate,
+}
+
+/// A discount for parties that settled at least `min_volume` of `denom` in
+/// the current volume window.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VolumeTier {
+    pub denom: String,
+    pub min_volume: Uint128,
+    pub discount_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Config {
+    pub fee: FeeRate,
+    pub denom_fees: Vec<DenomFee>,
+    /// receives fees as they are taken; without one they accrue until claimed
+    pub fee_collector: Option<String>,
+    /// share of the fee waived for the party that created the deal, in basis points
+    #[serde(default)]
+    pub maker_discount_bps: u16,
+    #[serde(default)]
+    pub volume_tiers: Vec<VolumeTier>,
+    /// seconds settled volume is counted for before it starts over, forever if zero
+    #[serde(default)]
+    pub volume_window: u64,
+    /// a deal with a leg at or above the threshold for its denom needs approvals
+    #[serde(default)]
+    pub large_deal_thresholds: Vec<Coin>,
+    /// cw4 group whose members approve large deals, on top of any approvers
+    /// the deal lists
+    #[serde(default)]
+    pub approver_group: Option<String>,
+    #[serde(default)]
+    pub group_approvals_required: u32,
+    /// who may trade; anyone unless set
+    #[serde(default)]
+    pub traders: TraderAllowlist,
+    /// contract every party is screened with before creating or funding a deal
+    #[serde(default)]
+    pub screening_contract: Option<String>,
+    /// largest leg a deal can have in each denom; denoms not listed are unlimited
+    #[serde(default)]
+    pub max_deal_amounts: Vec<Coin>,
+    /// deals and locked HTLCs an address can be party to at once, unlimited if zero
+    #[serde(default)]
+    pub max_open_deals: u32,
+    /// most that can be escrowed here at once in each denom, HTLCs and makers'
+    /// inventory included
+    #[serde(default)]
+    pub max_locked: Vec<Coin>,
+    /// contract answering `OracleQueryMsg` that deal prices are checked against
+    #[serde(default)]
+    pub price_oracle: Option<String>,
+    /// how far a deal's price can be from the oracle price, in basis points
+    #[serde(default)]
+    pub price_band_bps: u16,
+}
+
+/// Where the accounts allowed to create and fund deals are listed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TraderAllowlist {
+    #[default]
+    Open,
+    /// the `TRADERS` map, which can also list addresses on other chains
+    Internal,
+    /// members of a cw4 group contract on this chain
+    Group { address: String },
+}
+
+/// Accounts allowed to trade under `TraderAllowlist::Internal`.
+pub const TRADERS: Map<&str, Empty> = Map::new("traders");
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+/// Coins escrowed here across deals, locked HTLCs and inventory, by denom,
+/// kept up to date as they are locked and paid out.
+pub const LOCKED: Map<&str, Uint128> = Map::new("locked");
+
+/// Deals each address is a party to that may still be open, keyed by
+/// (address, deal id).
+pub const OPEN_DEALS: Map<(&str, u64), Empty> = Map::new("open_deals");
+
+/// How many locked HTLCs each address sent or is paid by.
+pub const OPEN_HTLCS: Map<&str, u32> = Map::new("open_htlcs");
+
+/// Fees taken while no fee collector was set, by denom.
+pub const ACCRUED_FEES: Map<&str, Uint128> = Map::new("accrued_fees");
+
+/// Negotiated share of the fee waived for an account, in basis points.
+pub const ACCOUNT_DISCOUNTS: Map<&str, u16> = Map::new("account_discounts");
+
+/// Payouts an account received in one denom since `window_start`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Volume {
+    pub window_start: u64,
+    pub amount: Uint128,
+}
+
+/// Settled volume keyed by (address, denom).
+pub const VOLUMES: Map<(&str, &str), Volume> = Map::new("volumes");
+
+/// What each broker has earned over all deals, keyed by (broker, denom).
+pub const BROKER_EARNINGS: Map<(&str, &str), Uint128> = Map::new("broker_earnings");
+
+/// Parts of the contract that can be paused in an emergency. Refunds and
+/// HTLC claims stay available while paused.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseScope {
+    /// creating deals and HTLCs
+    NewDeals,
+    /// funding deals, directly, over IBC or by locking a remote leg
+    Deposits,
+    /// paying out completed deals
+    Settlements,
+    /// every instruction arriving over IBC except withdrawals
+    IbcReceive,
+}
+
+impl PauseScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PauseScope::NewDeals => "new deals",
+            PauseScope::Deposits => "deposits",
+            PauseScope::Settlements => "settlements",
+            PauseScope::IbcReceive => "IBC instructions",
+        }
+    }
+}
+
+pub const PAUSED: Item<Vec<PauseScope>> = Item::new("paused");
+
+/// Duties that can be granted to separate accounts. The instantiator starts
+/// out holding all of them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// grants and revokes roles, sets the guardian and resets the contract
+    Owner,
+    /// pauses the contract and resolves stuck deals
+    Operator,
+    /// sets fee rates and discounts and claims accrued fees
+    FeeManager,
+    /// manages counterparty channels and interchain accounts
+    ChannelManager,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [
+        Role::Owner,
+        Role::Operator,
+        Role::FeeManager,
+        Role::ChannelManager,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Operator => "operator",
+            Role::FeeManager => "fee_manager",
+            Role::ChannelManager => "channel_manager",
+        }
+    }
+}
+
+/// Holders of each role, keyed by (role, address).
+pub const ROLES: Map<(&str, &Addr), Empty> = Map::new("roles");
+
+/// Coins makers set aside here for their quotes to be filled from.
+pub const INVENTORY: Map<(&Addr, &str), Uint128> = Map::new("inventory");
+
+/// Public key each maker signs quotes with.
+pub const QUOTE_KEYS: Map<&Addr, Binary> = Map::new("quote_keys");
+
+/// Quote nonces makers have had taken or cancelled.
+pub const QUOTE_NONCES: Map<(&Addr, u64), Empty> = Map::new("quote_nonces");
@@ -0,0 +1,25 @@
+use cosmwasm_std::Deps;
+use otc_ibc::cw4;
+
+use crate::state::{TraderAllowlist, CONFIG, TRADERS};
+use crate::ContractError;
+
+/// Whether `address` may trade under the configured allowlist.
+pub fn is_allowed(deps: Deps, address: &str) -> Result<bool, ContractError> {
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    match config.traders {
+        TraderAllowlist::Open => Ok(true),
+        TraderAllowlist::Internal => Ok(TRADERS.has(deps.storage, address)),
+        TraderAllowlist::Group { address: group } => Ok(cw4::is_member(deps, &group, address)?),
+    }
+}
+
+/// Rejects accounts that are not on the allowlist.
+pub fn assert_allowed(deps: Deps, address: &str) -> Result<(), ContractError> {
+    if !is_allowed(deps, address)? {
+        return Err(ContractError::TraderNotAllowed {
+            address: address.to_string(),
+        });
+    }
+    Ok(())
+}